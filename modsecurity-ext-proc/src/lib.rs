@@ -0,0 +1,769 @@
+//! An [Envoy external processor](https://www.envoyproxy.io/docs/envoy/latest/configuration/http/http_filters/ext_proc_filter)
+//! backed by ModSecurity.
+//!
+//! Each `Process` stream opened by Envoy corresponds to a single HTTP request, and is inspected by
+//! a single [`Transaction`](modsecurity::Transaction). Every message on the stream is mapped onto
+//! the matching phase:
+//!
+//! | Message             | Phases                                                        |
+//! |---------------------|---------------------------------------------------------------|
+//! | `request_headers`   | connection, URI and request headers                           |
+//! | `request_body`      | request body, once the final chunk has been received          |
+//! | `request_trailers`  | request body, if it has not been processed yet                |
+//! | `response_headers`  | response headers                                              |
+//! | `response_body`     | response body, once the final chunk has been received         |
+//! | `response_trailers` | response body, if it has not been processed yet               |
+//!
+//! The logging phase is run once the stream is closed.
+//!
+//! If a phase raises a disruptive intervention, an `ImmediateResponse` with the status of the
+//! intervention (and a `Location` header for redirects) is sent back, and the stream is closed.
+//!
+//! Phases are run on a [`BlockingPool`] so they don't block the gRPC server.
+//!
+//! The client and server addresses are read from the `source.address` and
+//! `destination.address` attributes, and the HTTP version from the `request.protocol`
+//! attribute, when Envoy is configured to send them.
+
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+use envoy_types::pb::{
+    envoy::{
+        config::core::v3::{HeaderMap, HeaderValue, HeaderValueOption},
+        r#type::v3::HttpStatus,
+        service::ext_proc::v3::{
+            external_processor_server::{ExternalProcessor, ExternalProcessorServer},
+            processing_request::Request,
+            processing_response::Response,
+            BodyResponse, HeaderMutation, HeadersResponse, ImmediateResponse, ProcessingRequest,
+            ProcessingResponse, TrailersResponse,
+        },
+    },
+    google::protobuf::{value::Kind, Struct},
+};
+use modsecurity::{
+    asynchronous::{AsyncTransaction, BlockingPool},
+    http::Disruption,
+    Intervention, ModSecurity, ModSecurityResult, OwnedTransaction, Rules,
+};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Status, Streaming};
+
+/// An Envoy `ExternalProcessor` service that inspects traffic using ModSecurity.
+pub struct ModSecurityProcessor {
+    ms: Arc<ModSecurity>,
+    rules: Arc<Rules>,
+    pool: BlockingPool,
+}
+
+impl ModSecurityProcessor {
+    /// Creates a new processor that inspects traffic using `ms` and `rules`, running phases on
+    /// `pool`.
+    pub fn new(ms: Arc<ModSecurity>, rules: Arc<Rules>, pool: BlockingPool) -> Self {
+        Self { ms, rules, pool }
+    }
+
+    /// Wraps the processor in a gRPC service that can be added to a [`tonic`] server.
+    pub fn into_service(self) -> ExternalProcessorServer<Self> {
+        ExternalProcessorServer::new(self)
+    }
+}
+
+#[tonic::async_trait]
+impl ExternalProcessor for ModSecurityProcessor {
+    type ProcessStream = ReceiverStream<Result<ProcessingResponse, Status>>;
+
+    async fn process(
+        &self,
+        request: tonic::Request<Streaming<ProcessingRequest>>,
+    ) -> Result<tonic::Response<Self::ProcessStream>, Status> {
+        let mut messages = request.into_inner();
+
+        let transaction = self
+            .ms
+            .shared_transaction_builder()
+            .with_shared_rules(Arc::clone(&self.rules))
+            .build()
+            .map_err(|err| Status::internal(err.to_string()))?;
+
+        let mut session = Session::new(AsyncTransaction::new(transaction, self.pool.clone()));
+
+        let (sender, receiver) = mpsc::channel(4);
+
+        tokio::spawn(async move {
+            // A stream error means Envoy has given up on the request, which leaves nothing to
+            // respond to.
+            while let Ok(Some(message)) = messages.message().await {
+                let response = session.handle(message).await;
+
+                let last = !matches!(
+                    response,
+                    Ok(ProcessingResponse {
+                        response: Some(
+                            Response::RequestHeaders(_)
+                                | Response::RequestBody(_)
+                                | Response::RequestTrailers(_)
+                                | Response::ResponseHeaders(_)
+                                | Response::ResponseBody(_)
+                                | Response::ResponseTrailers(_)
+                        ),
+                        ..
+                    })
+                );
+
+                if sender.send(response).await.is_err() || last {
+                    break;
+                }
+            }
+
+            session.finish().await;
+        });
+
+        Ok(tonic::Response::new(ReceiverStream::new(receiver)))
+    }
+}
+
+/// The state of a single `Process` stream.
+struct Session {
+    transaction: AsyncTransaction,
+    version: String,
+    request_body_done: bool,
+    response_body_done: bool,
+}
+
+impl Session {
+    fn new(transaction: AsyncTransaction) -> Self {
+        Self {
+            transaction,
+            version: "1.1".to_string(),
+            request_body_done: false,
+            response_body_done: false,
+        }
+    }
+
+    /// Runs the phases for `message`, returning the response to send back to Envoy.
+    async fn handle(&mut self, message: ProcessingRequest) -> Result<ProcessingResponse, Status> {
+        let request = message
+            .request
+            .ok_or_else(|| Status::invalid_argument("Missing request"))?;
+
+        let (disruption, response) = match request {
+            Request::RequestHeaders(headers) => {
+                if let Some(protocol) = attribute(&message.attributes, "request.protocol") {
+                    self.version = protocol.trim_start_matches("HTTP/").to_string();
+                }
+
+                let peer = attribute(&message.attributes, "source.address")
+                    .and_then(|addr| addr.parse().ok())
+                    .unwrap_or_else(unspecified);
+                let local = attribute(&message.attributes, "destination.address")
+                    .and_then(|addr| addr.parse().ok())
+                    .unwrap_or_else(unspecified);
+                let version = self.version.clone();
+                let end_of_stream = headers.end_of_stream;
+                let headers = header_pairs(headers.headers);
+
+                self.request_body_done = end_of_stream;
+
+                let disruption = self
+                    .transaction
+                    .run(move |transaction| {
+                        inspect_request_headers(
+                            transaction,
+                            peer,
+                            local,
+                            &version,
+                            &headers,
+                            end_of_stream,
+                        )
+                    })
+                    .await;
+
+                (
+                    disruption,
+                    Response::RequestHeaders(HeadersResponse::default()),
+                )
+            }
+            Request::RequestBody(body) => {
+                let disruption = self
+                    .append_request_body(body.body, body.end_of_stream)
+                    .await;
+
+                (disruption, Response::RequestBody(BodyResponse::default()))
+            }
+            Request::RequestTrailers(_) => {
+                let disruption = self.append_request_body(Vec::new(), true).await;
+
+                (
+                    disruption,
+                    Response::RequestTrailers(TrailersResponse::default()),
+                )
+            }
+            Request::ResponseHeaders(headers) => {
+                let mut disruption = self.append_request_body(Vec::new(), true).await;
+
+                if let Ok(None) = disruption {
+                    let version = self.version.clone();
+                    let end_of_stream = headers.end_of_stream;
+                    let headers = header_pairs(headers.headers);
+
+                    disruption = self
+                        .transaction
+                        .run(move |transaction| {
+                            inspect_response_headers(transaction, &version, &headers)
+                        })
+                        .await;
+
+                    if end_of_stream {
+                        if let Ok(None) = disruption {
+                            disruption = self.append_response_body(Vec::new(), true).await;
+                        }
+                    }
+                }
+
+                (
+                    disruption,
+                    Response::ResponseHeaders(HeadersResponse::default()),
+                )
+            }
+            Request::ResponseBody(body) => {
+                let disruption = self
+                    .append_response_body(body.body, body.end_of_stream)
+                    .await;
+
+                (disruption, Response::ResponseBody(BodyResponse::default()))
+            }
+            Request::ResponseTrailers(_) => {
+                let disruption = self.append_response_body(Vec::new(), true).await;
+
+                (
+                    disruption,
+                    Response::ResponseTrailers(TrailersResponse::default()),
+                )
+            }
+        };
+
+        let response = match disruption.map_err(|err| Status::internal(err.to_string()))? {
+            Some(disruption) => {
+                Response::ImmediateResponse(immediate_response(&disruption.intervention))
+            }
+            None => response,
+        };
+
+        Ok(ProcessingResponse {
+            response: Some(response),
+            ..Default::default()
+        })
+    }
+
+    /// Appends a chunk of the request body, processing the body once `end_of_stream` is set.
+    ///
+    /// Does nothing once the request body has been processed.
+    async fn append_request_body(
+        &mut self,
+        body: Vec<u8>,
+        end_of_stream: bool,
+    ) -> ModSecurityResult<Option<Disruption>> {
+        if self.request_body_done {
+            return Ok(None);
+        }
+
+        self.request_body_done = end_of_stream;
+
+        self.transaction
+            .run(move |transaction| transaction.inspect_request_body(&body, end_of_stream))
+            .await
+    }
+
+    /// Appends a chunk of the response body, processing the body once `end_of_stream` is set.
+    ///
+    /// Does nothing once the response body has been processed.
+    async fn append_response_body(
+        &mut self,
+        body: Vec<u8>,
+        end_of_stream: bool,
+    ) -> ModSecurityResult<Option<Disruption>> {
+        if self.response_body_done {
+            return Ok(None);
+        }
+
+        self.response_body_done = end_of_stream;
+
+        self.transaction
+            .run(move |transaction| transaction.inspect_response_body(&body, end_of_stream))
+            .await
+    }
+
+    /// Runs the logging phase once the stream has ended.
+    async fn finish(mut self) {
+        // There is no one left to report an error to.
+        let _ = self
+            .transaction
+            .run(|transaction| transaction.process_logging())
+            .await;
+    }
+}
+
+fn inspect_request_headers(
+    transaction: &mut OwnedTransaction,
+    peer: SocketAddr,
+    local: SocketAddr,
+    version: &str,
+    headers: &[(String, Vec<u8>)],
+    end_of_stream: bool,
+) -> ModSecurityResult<Option<Disruption>> {
+    let method = pseudo_header(headers, ":method").unwrap_or("GET");
+    let path = pseudo_header(headers, ":path").unwrap_or("/");
+
+    let has_host = headers
+        .iter()
+        .any(|(key, _)| key.eq_ignore_ascii_case("host"));
+
+    let disruption = transaction.inspect_request_headers(
+        peer,
+        local,
+        method,
+        path,
+        version,
+        headers.iter().filter_map(|(key, value)| {
+            if key == ":authority" && !has_host {
+                Some((&b"Host"[..], value.as_slice()))
+            } else if !key.starts_with(':') {
+                Some((key.as_bytes(), value.as_slice()))
+            } else {
+                None
+            }
+        }),
+    )?;
+
+    match disruption {
+        Some(disruption) => Ok(Some(disruption)),
+        None if end_of_stream => transaction.inspect_request_body(&[], true),
+        None => Ok(None),
+    }
+}
+
+fn inspect_response_headers(
+    transaction: &mut OwnedTransaction,
+    version: &str,
+    headers: &[(String, Vec<u8>)],
+) -> ModSecurityResult<Option<Disruption>> {
+    let status = pseudo_header(headers, ":status")
+        .and_then(|status| status.parse().ok())
+        .unwrap_or(200);
+
+    transaction.inspect_response_headers(
+        status,
+        version,
+        headers
+            .iter()
+            .filter(|(key, _)| !key.starts_with(':'))
+            .map(|(key, value)| (key.as_bytes(), value.as_slice())),
+    )
+}
+
+fn unspecified() -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+}
+
+/// Returns the headers in `map` as key-value pairs.
+///
+/// Envoy sets either `value` or `raw_value` depending on its configuration.
+fn header_pairs(map: Option<HeaderMap>) -> Vec<(String, Vec<u8>)> {
+    map.map(|map| map.headers)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|header| {
+            let value = if header.raw_value.is_empty() {
+                header.value.into_bytes()
+            } else {
+                header.raw_value
+            };
+
+            (header.key, value)
+        })
+        .collect()
+}
+
+fn pseudo_header<'a>(headers: &'a [(String, Vec<u8>)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key == name)
+        .and_then(|(_, value)| std::str::from_utf8(value).ok())
+}
+
+/// Returns the string value of the attribute `name`, as sent by any filter.
+fn attribute<'a>(attributes: &'a HashMap<String, Struct>, name: &str) -> Option<&'a str> {
+    attributes
+        .values()
+        .filter_map(|attributes| attributes.fields.get(name))
+        .find_map(|value| match &value.kind {
+            Some(Kind::StringValue(value)) => Some(value.as_str()),
+            _ => None,
+        })
+}
+
+/// Builds the `ImmediateResponse` for `intervention`.
+///
+/// The response has the status of the intervention (or `403 Forbidden` if it is not a valid
+/// status), and a `Location` header if the intervention has a redirect URL.
+fn immediate_response(intervention: &Intervention) -> ImmediateResponse {
+    let status = intervention.response_status().as_u16().into();
+
+    let headers = intervention.url().map(|url| HeaderMutation {
+        set_headers: vec![HeaderValueOption {
+            header: Some(HeaderValue {
+                key: "location".to_string(),
+                raw_value: url.as_bytes().to_vec(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }],
+        ..Default::default()
+    });
+
+    ImmediateResponse {
+        status: Some(HttpStatus { code: status }),
+        headers,
+        details: "modsecurity_intervention".to_string(),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use envoy_types::pb::{
+        envoy::service::ext_proc::v3::{
+            external_processor_client::ExternalProcessorClient, HttpBody, HttpHeaders,
+        },
+        google::protobuf::Value,
+    };
+    use hyper_util::rt::TokioIo;
+    use tonic::transport::{Channel, Endpoint, Server, Uri};
+
+    use super::*;
+
+    /// Starts `processor` on an in-memory connection and returns a client connected to it.
+    async fn client(plain_rules: &str) -> ExternalProcessorClient<Channel> {
+        let ms = Arc::new(ModSecurity::default());
+        let mut rules = Rules::new();
+        rules.add_plain(plain_rules).unwrap();
+
+        let processor = ModSecurityProcessor::new(ms, Arc::new(rules), BlockingPool::new(1));
+
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+
+        tokio::spawn(
+            Server::builder()
+                .add_service(processor.into_service())
+                .serve_with_incoming(tokio_stream::once(Ok::<_, io::Error>(server_io))),
+        );
+
+        let mut client_io = Some(client_io);
+        let channel = Endpoint::try_from("http://[::]:50051")
+            .unwrap()
+            .connect_with_connector(tower::service_fn(move |_: Uri| {
+                let client_io = client_io.take();
+                async move {
+                    client_io
+                        .map(TokioIo::new)
+                        .ok_or_else(|| io::Error::other("Client already connected"))
+                }
+            }))
+            .await
+            .unwrap();
+
+        ExternalProcessorClient::new(channel)
+    }
+
+    /// Sends `messages` on a single stream, returning the responses.
+    async fn process(
+        client: &mut ExternalProcessorClient<Channel>,
+        messages: Vec<ProcessingRequest>,
+    ) -> Vec<Response> {
+        let (sender, receiver) = mpsc::channel(messages.len().max(1));
+        for message in messages {
+            sender.send(message).await.unwrap();
+        }
+        drop(sender);
+
+        let mut stream = client
+            .process(ReceiverStream::new(receiver))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let mut responses = Vec::new();
+        while let Some(response) = stream.message().await.unwrap() {
+            responses.push(response.response.unwrap());
+        }
+        responses
+    }
+
+    fn headers(headers: &[(&str, &str)], end_of_stream: bool) -> HttpHeaders {
+        HttpHeaders {
+            headers: Some(HeaderMap {
+                headers: headers
+                    .iter()
+                    .map(|(key, value)| HeaderValue {
+                        key: key.to_string(),
+                        raw_value: value.as_bytes().to_vec(),
+                        ..Default::default()
+                    })
+                    .collect(),
+            }),
+            end_of_stream,
+            ..Default::default()
+        }
+    }
+
+    fn message(request: Request) -> ProcessingRequest {
+        ProcessingRequest {
+            request: Some(request),
+            ..Default::default()
+        }
+    }
+
+    fn request_headers(path: &str, end_of_stream: bool) -> ProcessingRequest {
+        message(Request::RequestHeaders(headers(
+            &[
+                (":method", "POST"),
+                (":path", path),
+                (":authority", "example.com"),
+            ],
+            end_of_stream,
+        )))
+    }
+
+    fn body(body: &str, end_of_stream: bool) -> HttpBody {
+        HttpBody {
+            body: body.as_bytes().to_vec(),
+            end_of_stream,
+            ..Default::default()
+        }
+    }
+
+    fn immediate_status(response: &Response) -> Option<i32> {
+        match response {
+            Response::ImmediateResponse(response) => response.status.as_ref().map(|s| s.code),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_allowed() {
+        let mut client = client(
+            r#"
+            SecRuleEngine On
+
+            SecRule REQUEST_URI "@rx admin" "id:1,phase:1,deny,status:401"
+        "#,
+        )
+        .await;
+
+        let responses = process(
+            &mut client,
+            vec![
+                request_headers("/index.html", false),
+                message(Request::RequestBody(body("hello", true))),
+                message(Request::ResponseHeaders(headers(
+                    &[(":status", "200"), ("content-type", "text/plain")],
+                    false,
+                ))),
+                message(Request::ResponseBody(body("world", true))),
+            ],
+        )
+        .await;
+
+        assert_eq!(responses.len(), 4);
+        assert!(matches!(responses[0], Response::RequestHeaders(_)));
+        assert!(matches!(responses[1], Response::RequestBody(_)));
+        assert!(matches!(responses[2], Response::ResponseHeaders(_)));
+        assert!(matches!(responses[3], Response::ResponseBody(_)));
+    }
+
+    #[tokio::test]
+    async fn test_request_headers_intervention() {
+        let mut client = client(
+            r#"
+            SecRuleEngine On
+
+            SecRule REQUEST_URI "@rx admin" "id:1,phase:1,deny,status:401"
+        "#,
+        )
+        .await;
+
+        let responses = process(
+            &mut client,
+            vec![
+                request_headers("/admin", false),
+                message(Request::RequestBody(body("hello", true))),
+            ],
+        )
+        .await;
+
+        // The stream is closed after the immediate response.
+        assert_eq!(responses.len(), 1);
+        assert_eq!(immediate_status(&responses[0]), Some(401));
+    }
+
+    #[tokio::test]
+    async fn test_request_body_intervention() {
+        let mut client = client(
+            r#"
+            SecRuleEngine On
+
+            SecRequestBodyAccess On
+
+            SecRule REQUEST_BODY "@rx attack" "id:1,phase:2,deny,status:403"
+        "#,
+        )
+        .await;
+
+        let responses = process(
+            &mut client,
+            vec![
+                request_headers("/", false),
+                message(Request::RequestBody(body("an ", false))),
+                message(Request::RequestBody(body("attack", true))),
+            ],
+        )
+        .await;
+
+        assert_eq!(responses.len(), 3);
+        assert!(matches!(responses[1], Response::RequestBody(_)));
+        assert_eq!(immediate_status(&responses[2]), Some(403));
+    }
+
+    #[tokio::test]
+    async fn test_response_headers_intervention() {
+        let mut client = client(
+            r#"
+            SecRuleEngine On
+
+            SecRule RESPONSE_STATUS "@streq 418" "id:1,phase:3,deny,status:500"
+        "#,
+        )
+        .await;
+
+        let responses = process(
+            &mut client,
+            vec![
+                request_headers("/", true),
+                message(Request::ResponseHeaders(headers(
+                    &[(":status", "418")],
+                    true,
+                ))),
+            ],
+        )
+        .await;
+
+        assert_eq!(responses.len(), 2);
+        assert_eq!(immediate_status(&responses[1]), Some(500));
+    }
+
+    #[tokio::test]
+    async fn test_redirect() {
+        let mut client = client(
+            r#"
+            SecRuleEngine On
+
+            SecRule REQUEST_URI "@rx admin" "id:1,phase:1,redirect:https://example.com/login"
+        "#,
+        )
+        .await;
+
+        let responses = process(&mut client, vec![request_headers("/admin", true)]).await;
+
+        let Response::ImmediateResponse(response) = &responses[0] else {
+            panic!("Expected immediate response");
+        };
+        let header = response.headers.as_ref().unwrap().set_headers[0]
+            .header
+            .as_ref()
+            .unwrap();
+
+        assert_eq!(response.status.as_ref().unwrap().code, 302);
+        assert_eq!(header.key, "location");
+        assert_eq!(header.raw_value, b"https://example.com/login");
+    }
+
+    #[tokio::test]
+    async fn test_missing_request() {
+        let mut client = client("SecRuleEngine On").await;
+
+        let (sender, receiver) = mpsc::channel(1);
+        sender.send(ProcessingRequest::default()).await.unwrap();
+
+        let mut stream = client
+            .process(ReceiverStream::new(receiver))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let status = stream.message().await.unwrap_err();
+
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[test]
+    fn test_header_pairs() {
+        let map = HeaderMap {
+            headers: vec![
+                HeaderValue {
+                    key: "x-value".to_string(),
+                    value: "value".to_string(),
+                    ..Default::default()
+                },
+                HeaderValue {
+                    key: "x-raw".to_string(),
+                    raw_value: b"raw".to_vec(),
+                    ..Default::default()
+                },
+            ],
+        };
+
+        assert_eq!(
+            header_pairs(Some(map)),
+            vec![
+                ("x-value".to_string(), b"value".to_vec()),
+                ("x-raw".to_string(), b"raw".to_vec()),
+            ]
+        );
+        assert!(header_pairs(None).is_empty());
+    }
+
+    #[test]
+    fn test_attribute() {
+        let mut fields = HashMap::new();
+        fields.insert(
+            "source.address".to_string(),
+            Value {
+                kind: Some(Kind::StringValue("124.123.122.121:12345".to_string())),
+            },
+        );
+        fields.insert(
+            "request.size".to_string(),
+            Value {
+                kind: Some(Kind::NumberValue(5.0)),
+            },
+        );
+
+        let mut attributes = HashMap::new();
+        attributes.insert("envoy.filters.http.ext_proc".to_string(), Struct { fields });
+
+        assert_eq!(
+            attribute(&attributes, "source.address"),
+            Some("124.123.122.121:12345")
+        );
+        assert_eq!(attribute(&attributes, "request.size"), None);
+        assert_eq!(attribute(&attributes, "destination.address"), None);
+    }
+}
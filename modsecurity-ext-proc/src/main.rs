@@ -0,0 +1,102 @@
+//! Runs an Envoy external processor backed by ModSecurity.
+//!
+//! ```text
+//! modsecurity-ext-proc [--listen <ADDR>] [--threads <N>] --rules <FILE>...
+//! ```
+
+use std::{net::SocketAddr, process, sync::Arc};
+
+use modsecurity::{asynchronous::BlockingPool, ModSecurity, Rules};
+use modsecurity_ext_proc::ModSecurityProcessor;
+use tonic::transport::Server;
+
+const USAGE: &str =
+    "Usage: modsecurity-ext-proc [--listen <ADDR>] [--threads <N>] --rules <FILE>...
+
+Options:
+    --listen <ADDR>   Address to listen on [default: 127.0.0.1:50051]
+    --threads <N>     Number of threads used to run rules [default: 4]
+    --rules <FILE>    Rules file to load, can be given more than once";
+
+struct Args {
+    listen: SocketAddr,
+    threads: Option<usize>,
+    rules: Vec<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args {
+        listen: SocketAddr::from(([127, 0, 0, 1], 50051)),
+        threads: None,
+        rules: Vec::new(),
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", arg))
+        };
+
+        match arg.as_str() {
+            "--listen" => {
+                parsed.listen = value()?
+                    .parse()
+                    .map_err(|err| format!("Invalid address: {}", err))?
+            }
+            "--threads" => {
+                let threads = value()?
+                    .parse()
+                    .map_err(|err| format!("Invalid thread count: {}", err))?;
+
+                if threads == 0 {
+                    return Err("Invalid thread count: must be at least 1".to_string());
+                }
+
+                parsed.threads = Some(threads);
+            }
+            "--rules" => parsed.rules.push(value()?),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+
+    if parsed.rules.is_empty() {
+        return Err("At least one rules file is required".to_string());
+    }
+
+    Ok(parsed)
+}
+
+#[tokio::main]
+async fn main() {
+    let args = parse_args(std::env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{}\n\n{}", err, USAGE);
+        process::exit(2);
+    });
+
+    let mut rules = Rules::new();
+    for file in &args.rules {
+        if let Err(err) = rules.add_file(file) {
+            eprintln!("Failed to load {}: {}", file, err);
+            process::exit(1);
+        }
+    }
+
+    let pool = match args.threads {
+        Some(threads) => BlockingPool::new(threads),
+        None => BlockingPool::default(),
+    };
+
+    let processor =
+        ModSecurityProcessor::new(Arc::new(ModSecurity::default()), Arc::new(rules), pool);
+
+    eprintln!("Listening on {}", args.listen);
+
+    if let Err(err) = Server::builder()
+        .add_service(processor.into_service())
+        .serve(args.listen)
+        .await
+    {
+        eprintln!("Server error: {}", err);
+        process::exit(1);
+    }
+}
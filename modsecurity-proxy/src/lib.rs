@@ -0,0 +1,272 @@
+//! A reverse proxy that enforces ModSecurity rules on the traffic to an upstream HTTP server.
+//!
+//! Every request runs through all phases of a [`Transaction`](modsecurity::Transaction), from
+//! the connection phase to the logging phase, using a
+//! [`ModSecurityLayer`](modsecurity::tower::ModSecurityLayer). Requests that are not disrupted
+//! are forwarded to the upstream server, and its responses are inspected before being sent back
+//! to the client.
+//!
+//! Request bodies are buffered up to a limit so they can be inspected. Response bodies are
+//! streamed back unless response body inspection is enabled, in which case they are buffered up
+//! to a limit as well. See [`Proxy::with_request_body_limit()`] and
+//! [`Proxy::with_response_body_inspection()`].
+//!
+//! Errors that do not stop the proxy, such as failed connections, can be reported with
+//! [`Proxy::with_error_handler()`].
+
+use std::{convert::Infallible, fmt, io, net::SocketAddr, sync::Arc, time::Duration};
+
+use bytes::Bytes;
+use http::{header, uri::Authority, HeaderMap, HeaderValue, Request, Response, StatusCode, Uri};
+use http_body_util::{Collected, Either, Full};
+use hyper::{body::Incoming, server::conn::http1};
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::{TokioExecutor, TokioIo},
+};
+use modsecurity::{
+    tower::{ConnectionInfo, ModSecurityLayer},
+    ModSecurity, Rules,
+};
+use tokio::net::TcpListener;
+use tower::{Layer, ServiceExt};
+
+/// Headers that only apply to a single connection and must not be forwarded.
+const HOP_BY_HOP_HEADERS: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+];
+
+/// How long [`Proxy::serve()`] waits before accepting connections again after an error.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+type UpstreamBody = Either<Incoming, Full<Bytes>>;
+
+type UpstreamClient = Client<HttpConnector, Collected<Bytes>>;
+
+/// An error that did not stop the proxy. See [`Proxy::with_error_handler()`].
+#[derive(Debug)]
+pub enum Error {
+    /// A connection could not be accepted, or its local address could not be read. The proxy
+    /// keeps accepting connections after a short delay.
+    Accept(io::Error),
+    /// A connection failed and was closed.
+    Connection(hyper::Error),
+    /// The upstream server could not be reached, so the client was sent a `502 Bad Gateway`
+    /// response.
+    Upstream(hyper_util::client::legacy::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Accept(err) => write!(f, "Error accepting connection: {}", err),
+            Error::Connection(err) => write!(f, "Connection error: {}", err),
+            Error::Upstream(err) => write!(f, "Upstream error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The handler set with [`Proxy::with_error_handler()`].
+///
+/// This is a struct rather than a type alias, as the compiler cannot prove that futures holding a
+/// `dyn Fn(&Error)` directly are `Send`.
+#[derive(Clone)]
+struct ErrorHandler(Arc<dyn Fn(&Error) + Send + Sync + 'static>);
+
+impl ErrorHandler {
+    fn report(&self, err: Error) {
+        (self.0)(&err)
+    }
+}
+
+/// A reverse proxy to a single upstream server.
+pub struct Proxy {
+    layer: ModSecurityLayer,
+    upstream: Authority,
+    client: UpstreamClient,
+    error_handler: ErrorHandler,
+}
+
+impl Proxy {
+    /// Creates a new proxy to `upstream` that inspects traffic using `ms` and `rules`.
+    pub fn new(ms: Arc<ModSecurity>, rules: Arc<Rules>, upstream: SocketAddr) -> Self {
+        Self {
+            layer: ModSecurityLayer::new(ms, rules),
+            upstream: upstream
+                .to_string()
+                .parse()
+                .expect("Socket addresses are valid authorities"),
+            client: Client::builder(TokioExecutor::new()).build_http(),
+            error_handler: ErrorHandler(Arc::new(|_| {})),
+        }
+    }
+
+    /// Sets a handler for errors that do not stop the proxy. Errors are ignored by default.
+    pub fn with_error_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&Error) + Send + Sync + 'static,
+    {
+        self.error_handler = ErrorHandler(Arc::new(handler));
+        self
+    }
+
+    /// Sets the maximum size of request bodies, in bytes. Requests with a larger body are
+    /// rejected with a `413 Payload Too Large` response without being forwarded.
+    ///
+    /// Defaults to [`DEFAULT_REQUEST_BODY_LIMIT`](modsecurity::tower::DEFAULT_REQUEST_BODY_LIMIT).
+    pub fn with_request_body_limit(mut self, limit: usize) -> Self {
+        self.layer = self.layer.with_request_body_limit(limit);
+        self
+    }
+
+    /// Sets the maximum size of response bodies that are inspected, in bytes. Only the start of
+    /// larger responses from the upstream server is inspected, and the rest is streamed back.
+    ///
+    /// Defaults to
+    /// [`DEFAULT_RESPONSE_BODY_LIMIT`](modsecurity::tower::DEFAULT_RESPONSE_BODY_LIMIT).
+    pub fn with_response_body_limit(mut self, limit: usize) -> Self {
+        self.layer = self.layer.with_response_body_limit(limit);
+        self
+    }
+
+    /// Sets whether response bodies are buffered and inspected. Disabled by default, so that
+    /// responses such as downloads or event streams are streamed back as they are received.
+    ///
+    /// This should be enabled when rules use `SecResponseBodyAccess On`.
+    pub fn with_response_body_inspection(mut self, enabled: bool) -> Self {
+        self.layer = self.layer.with_response_body_inspection(enabled);
+        self
+    }
+
+    /// Accepts connections from `listener`, handling each one on its own task.
+    ///
+    /// Errors accepting a connection are passed to the error handler, and accepting is retried
+    /// after a short delay.
+    pub async fn serve(self, listener: TcpListener) -> io::Result<()> {
+        let upstream = Arc::new(self.upstream);
+        let client = self.client;
+        let error_handler = self.error_handler;
+
+        let service = self.layer.layer(tower::service_fn({
+            let error_handler = error_handler.clone();
+            move |request| {
+                forward(
+                    client.clone(),
+                    Arc::clone(&upstream),
+                    error_handler.clone(),
+                    request,
+                )
+            }
+        }));
+
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    // Errors such as running out of file descriptors are usually temporary.
+                    error_handler.report(Error::Accept(err));
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                    continue;
+                }
+            };
+            let local = match stream.local_addr() {
+                Ok(local) => local,
+                Err(err) => {
+                    error_handler.report(Error::Accept(err));
+                    continue;
+                }
+            };
+            let connection = ConnectionInfo { peer, local };
+            let service = service.clone();
+            let error_handler = error_handler.clone();
+
+            tokio::spawn(async move {
+                let service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+                    request.extensions_mut().insert(connection);
+                    service.clone().oneshot(request)
+                });
+
+                if let Err(err) = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    error_handler.report(Error::Connection(err));
+                }
+            });
+        }
+    }
+}
+
+/// Forwards `request` to `upstream`, returning a `502 Bad Gateway` response if it cannot be
+/// reached.
+async fn forward(
+    client: UpstreamClient,
+    upstream: Arc<Authority>,
+    error_handler: ErrorHandler,
+    mut request: Request<Collected<Bytes>>,
+) -> Result<Response<UpstreamBody>, Infallible> {
+    let mut uri = Uri::builder().scheme("http").authority((*upstream).clone());
+    if let Some(path_and_query) = request.uri().path_and_query() {
+        uri = uri.path_and_query(path_and_query.clone());
+    }
+    *request.uri_mut() = uri.build().expect("URI parts are valid");
+
+    remove_hop_by_hop_headers(request.headers_mut());
+
+    if let Some(connection) = request.extensions().get::<ConnectionInfo>().copied() {
+        append_forwarded_for(request.headers_mut(), connection.peer);
+    }
+
+    match client.request(request).await {
+        Ok(response) => {
+            let mut response = response.map(Either::Left);
+            remove_hop_by_hop_headers(response.headers_mut());
+            Ok(response)
+        }
+        Err(err) => {
+            error_handler.report(Error::Upstream(err));
+
+            let mut response = Response::new(Either::Right(Full::default()));
+            *response.status_mut() = StatusCode::BAD_GATEWAY;
+            Ok(response)
+        }
+    }
+}
+
+fn remove_hop_by_hop_headers(headers: &mut HeaderMap) {
+    // Headers listed in `Connection` are hop-by-hop as well.
+    let listed: Vec<String> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .collect();
+
+    for name in listed.iter().map(String::as_str).chain(HOP_BY_HOP_HEADERS) {
+        headers.remove(name);
+    }
+}
+
+fn append_forwarded_for(headers: &mut HeaderMap, peer: SocketAddr) {
+    let forwarded_for = match headers
+        .get("x-forwarded-for")
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(existing) => format!("{}, {}", existing, peer.ip()),
+        None => peer.ip().to_string(),
+    };
+
+    if let Ok(value) = HeaderValue::from_str(&forwarded_for) {
+        headers.insert("x-forwarded-for", value);
+    }
+}
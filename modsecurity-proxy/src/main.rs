@@ -0,0 +1,134 @@
+//! Runs a reverse proxy that enforces ModSecurity rules.
+//!
+//! ```text
+//! modsecurity-proxy [--listen <ADDR>] [--request-body-limit <BYTES>]
+//!     [--inspect-response-body] [--response-body-limit <BYTES>] --upstream <ADDR>
+//!     --rules <FILE>...
+//! ```
+
+use std::{net::SocketAddr, process, sync::Arc};
+
+use modsecurity::{
+    tower::{DEFAULT_REQUEST_BODY_LIMIT, DEFAULT_RESPONSE_BODY_LIMIT},
+    ModSecurity, Rules,
+};
+use modsecurity_proxy::Proxy;
+use tokio::net::TcpListener;
+
+const USAGE: &str = "Usage: modsecurity-proxy [--listen <ADDR>] [--request-body-limit <BYTES>]
+    [--inspect-response-body] [--response-body-limit <BYTES>] --upstream <ADDR>
+    --rules <FILE>...
+
+Options:
+    --listen <ADDR>                  Address to listen on [default: 127.0.0.1:8080]
+    --request-body-limit <BYTES>     Largest request body to accept [default: 13107200]
+    --inspect-response-body          Buffer and inspect response bodies
+    --response-body-limit <BYTES>    Largest part of response bodies to inspect [default: 524288]
+    --upstream <ADDR>                Address of the server to forward requests to
+    --rules <FILE>                   Rules file to load, can be given more than once";
+
+struct Args {
+    listen: SocketAddr,
+    request_body_limit: usize,
+    inspect_response_body: bool,
+    response_body_limit: usize,
+    upstream: SocketAddr,
+    rules: Vec<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut listen = SocketAddr::from(([127, 0, 0, 1], 8080));
+    let mut request_body_limit = DEFAULT_REQUEST_BODY_LIMIT;
+    let mut inspect_response_body = false;
+    let mut response_body_limit = DEFAULT_RESPONSE_BODY_LIMIT;
+    let mut upstream = None;
+    let mut rules = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", arg))
+        };
+
+        match arg.as_str() {
+            "--listen" => {
+                listen = value()?
+                    .parse()
+                    .map_err(|err| format!("Invalid address: {}", err))?
+            }
+            "--request-body-limit" => {
+                request_body_limit = value()?
+                    .parse()
+                    .map_err(|err| format!("Invalid request body limit: {}", err))?
+            }
+            "--inspect-response-body" => inspect_response_body = true,
+            "--response-body-limit" => {
+                response_body_limit = value()?
+                    .parse()
+                    .map_err(|err| format!("Invalid response body limit: {}", err))?
+            }
+            "--upstream" => {
+                upstream = Some(
+                    value()?
+                        .parse()
+                        .map_err(|err| format!("Invalid upstream address: {}", err))?,
+                )
+            }
+            "--rules" => rules.push(value()?),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+
+    let upstream = upstream.ok_or_else(|| "An upstream address is required".to_string())?;
+
+    if rules.is_empty() {
+        return Err("At least one rules file is required".to_string());
+    }
+
+    Ok(Args {
+        listen,
+        request_body_limit,
+        inspect_response_body,
+        response_body_limit,
+        upstream,
+        rules,
+    })
+}
+
+#[tokio::main]
+async fn main() {
+    let args = parse_args(std::env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{}\n\n{}", err, USAGE);
+        process::exit(2);
+    });
+
+    let mut rules = Rules::new();
+    for file in &args.rules {
+        if let Err(err) = rules.add_file(file) {
+            eprintln!("Failed to load {}: {}", file, err);
+            process::exit(1);
+        }
+    }
+
+    let listener = TcpListener::bind(args.listen).await.unwrap_or_else(|err| {
+        eprintln!("Failed to listen on {}: {}", args.listen, err);
+        process::exit(1);
+    });
+
+    eprintln!("Forwarding {} to {}", args.listen, args.upstream);
+
+    let proxy = Proxy::new(
+        Arc::new(ModSecurity::default()),
+        Arc::new(rules),
+        args.upstream,
+    )
+    .with_request_body_limit(args.request_body_limit)
+    .with_response_body_inspection(args.inspect_response_body)
+    .with_response_body_limit(args.response_body_limit)
+    .with_error_handler(|err| eprintln!("{}", err));
+
+    if let Err(err) = proxy.serve(listener).await {
+        eprintln!("Server error: {}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,299 @@
+use std::{
+    convert::Infallible,
+    io::Write,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use bytes::Bytes;
+use http::{Request, Response, StatusCode};
+use http_body_util::{BodyExt, Full};
+use hyper::{body::Incoming, server::conn::http1};
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::{TokioExecutor, TokioIo},
+};
+use modsecurity::{ModSecurity, Rules};
+use modsecurity_proxy::Proxy;
+use tempfile::NamedTempFile;
+use tokio::net::TcpListener;
+
+const RULES: &str = r#"
+    SecRuleEngine On
+
+    SecRequestBodyAccess On
+    SecResponseBodyAccess On
+    SecResponseBodyMimeType text/plain
+
+    SecRule REQUEST_URI "@rx admin" "id:1,phase:1,deny,status:401"
+    SecRule REQUEST_BODY "@rx attack" "id:2,phase:2,deny,status:403"
+    SecRule RESPONSE_BODY "@rx secret" "id:3,phase:4,deny,status:500"
+"#;
+
+/// An upstream server that echoes the method, path and `X-Forwarded-For` header of each request
+/// it receives, followed by its body.
+struct Upstream {
+    addr: SocketAddr,
+    requests: Arc<AtomicUsize>,
+}
+
+impl Upstream {
+    async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&requests);
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let counter = Arc::clone(&counter);
+
+                tokio::spawn(async move {
+                    let service = hyper::service::service_fn(move |request: Request<Incoming>| {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        echo(request)
+                    });
+
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+
+        Self { addr, requests }
+    }
+
+    fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+}
+
+async fn echo(request: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
+    let (parts, body) = request.into_parts();
+    let body = body.collect().await.unwrap().to_bytes();
+
+    let forwarded_for = parts
+        .headers
+        .get("x-forwarded-for")
+        .map(|value| value.to_str().unwrap())
+        .unwrap_or_default();
+
+    let echoed = format!(
+        "{} {} {}\n{}",
+        parts.method,
+        parts.uri,
+        forwarded_for,
+        String::from_utf8_lossy(&body)
+    );
+
+    Ok(Response::builder()
+        .header("content-type", "text/plain")
+        .header("x-upstream", "stub")
+        .body(Full::new(Bytes::from(echoed)))
+        .unwrap())
+}
+
+/// Starts a proxy to `upstream` with rules loaded from a file, returning its address.
+async fn start_proxy(upstream: SocketAddr, plain_rules: &str) -> SocketAddr {
+    start_configured_proxy(upstream, plain_rules, |proxy| proxy).await
+}
+
+/// Starts a proxy like [`start_proxy()`], applying `configure` to it first.
+async fn start_configured_proxy(
+    upstream: SocketAddr,
+    plain_rules: &str,
+    configure: impl FnOnce(Proxy) -> Proxy,
+) -> SocketAddr {
+    let mut file = NamedTempFile::new().unwrap();
+    file.write_all(plain_rules.as_bytes()).unwrap();
+
+    let mut rules = Rules::new();
+    rules.add_file(file.path()).unwrap();
+
+    let proxy = configure(Proxy::new(
+        Arc::new(ModSecurity::default()),
+        Arc::new(rules),
+        upstream,
+    ));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(proxy.serve(listener));
+
+    addr
+}
+
+async fn send(
+    proxy: SocketAddr,
+    method: &str,
+    path: &str,
+    body: &'static str,
+) -> (StatusCode, http::HeaderMap, String) {
+    let client: Client<HttpConnector, Full<Bytes>> =
+        Client::builder(TokioExecutor::new()).build_http();
+
+    let request = Request::builder()
+        .method(method)
+        .uri(format!("http://{}{}", proxy, path))
+        .header("content-type", "text/plain")
+        .body(Full::new(Bytes::from(body)))
+        .unwrap();
+
+    let response = client.request(request).await.unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+
+    (status, headers, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn test_forwarded() {
+    let upstream = Upstream::start().await;
+    let proxy = start_proxy(upstream.addr, RULES).await;
+
+    let (status, headers, body) = send(proxy, "POST", "/echo?name=value", "hello").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["x-upstream"], "stub");
+    assert_eq!(body, "POST /echo?name=value 127.0.0.1\nhello");
+    assert_eq!(upstream.requests(), 1);
+}
+
+#[tokio::test]
+async fn test_request_intervention() {
+    let upstream = Upstream::start().await;
+    let proxy = start_proxy(upstream.addr, RULES).await;
+
+    let (status, _, _) = send(proxy, "GET", "/admin", "").await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(upstream.requests(), 0);
+}
+
+#[tokio::test]
+async fn test_request_body_intervention() {
+    let upstream = Upstream::start().await;
+    let proxy = start_proxy(upstream.addr, RULES).await;
+
+    let (status, _, _) = send(proxy, "POST", "/", "an attack").await;
+
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(upstream.requests(), 0);
+}
+
+#[tokio::test]
+async fn test_response_body_intervention() {
+    let upstream = Upstream::start().await;
+    let proxy = start_configured_proxy(upstream.addr, RULES, |proxy| {
+        proxy.with_response_body_inspection(true)
+    })
+    .await;
+
+    // The upstream echoes the request body, which the response body rule matches.
+    let (status, _, body) = send(proxy, "POST", "/", "a secret").await;
+
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(body.is_empty());
+    assert_eq!(upstream.requests(), 1);
+}
+
+#[tokio::test]
+async fn test_body_limits() {
+    let upstream = Upstream::start().await;
+
+    let proxy = start_configured_proxy(upstream.addr, RULES, |proxy| {
+        proxy.with_request_body_limit(4)
+    })
+    .await;
+    let (status, _, _) = send(proxy, "POST", "/", "hello").await;
+
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(upstream.requests(), 0);
+
+    // The upstream echoes the method, path and client address before the body, so the part of
+    // the response that the response body rule matches is not inspected.
+    let proxy = start_configured_proxy(upstream.addr, RULES, |proxy| {
+        proxy
+            .with_response_body_inspection(true)
+            .with_response_body_limit(16)
+    })
+    .await;
+    let (status, _, body) = send(proxy, "POST", "/", "a secret").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "POST / 127.0.0.1\na secret");
+    assert_eq!(upstream.requests(), 1);
+}
+
+#[tokio::test]
+async fn test_response_body_streamed() {
+    let upstream = Upstream::start().await;
+    let proxy = start_proxy(upstream.addr, RULES).await;
+
+    // Response bodies are not inspected by default.
+    let (status, _, body) = send(proxy, "POST", "/", "a secret").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "POST / 127.0.0.1\na secret");
+}
+
+#[tokio::test]
+async fn test_logging() {
+    let upstream = Upstream::start().await;
+    let audit_log = NamedTempFile::new().unwrap();
+
+    let proxy = start_proxy(
+        upstream.addr,
+        &format!(
+            r#"
+            SecRuleEngine On
+
+            SecAuditEngine On
+            SecAuditLogParts ABZ
+            SecAuditLogType Serial
+            SecAuditLog {}
+        "#,
+            audit_log.path().display()
+        ),
+    )
+    .await;
+
+    let (status, _, _) = send(proxy, "GET", "/logged", "").await;
+
+    assert_eq!(status, StatusCode::OK);
+
+    // The audit log is written by the logging phase.
+    let log = std::fs::read_to_string(audit_log.path()).unwrap();
+    assert!(log.contains("GET /logged"));
+}
+
+#[tokio::test]
+async fn test_upstream_unavailable() {
+    // Bind and drop a listener to find a port that nothing is listening on.
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let proxy = start_configured_proxy(addr, RULES, |proxy| {
+        let errors = Arc::clone(&errors);
+        proxy.with_error_handler(move |err| errors.lock().unwrap().push(err.to_string()))
+    })
+    .await;
+
+    let (status, _, _) = send(proxy, "GET", "/", "").await;
+
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+
+    let errors = errors.lock().unwrap();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].starts_with("Upstream error"));
+}
@@ -0,0 +1,683 @@
+//! Parsing of captured traffic into [`Entry`] values.
+//!
+//! Two formats are supported:
+//!
+//! - [HAR](http://www.softwareishard.com/blog/har-12-spec/) files, as exported by browsers and
+//!   most HTTP debugging proxies.
+//! - JSON lines, where each line is an object of the following form. Only `uri` is required.
+//!
+//! ```json
+//! {
+//!     "method": "POST",
+//!     "uri": "/login?next=/",
+//!     "http_version": "1.1",
+//!     "headers": {"Host": "example.com", "Content-Type": "application/x-www-form-urlencoded"},
+//!     "body": "user=admin",
+//!     "client": "10.0.0.1",
+//!     "client_port": 51234,
+//!     "server": "10.0.0.2",
+//!     "server_port": 443,
+//!     "response": {"status": 200, "headers": {"Content-Type": "text/plain"}, "body": "Hello"}
+//! }
+//! ```
+//!
+//! `headers` may also be a list of `[name, value]` pairs, or map names to arrays of values.
+//! Repeated headers are kept, in the order they appear.
+
+use std::{fmt, io::BufRead};
+
+use base64::Engine;
+use serde::{
+    de::{MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+
+/// A captured request, and optionally the response it received.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Entry {
+    /// The request method.
+    pub method: String,
+    /// The request URI, as it appears in the request line.
+    pub uri: String,
+    /// The HTTP version, e.g. `1.1`.
+    pub http_version: String,
+    /// The request headers.
+    pub headers: Vec<(String, String)>,
+    /// The request body.
+    pub body: Vec<u8>,
+    /// The address of the client.
+    pub client: String,
+    /// The port of the client.
+    pub client_port: i32,
+    /// The address of the server.
+    pub server: String,
+    /// The port of the server.
+    pub server_port: i32,
+    /// The response, if it was captured.
+    pub response: Option<ResponseEntry>,
+}
+
+/// A captured response.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ResponseEntry {
+    /// The response status code.
+    pub status: i32,
+    /// The HTTP version, e.g. `1.1`.
+    pub http_version: String,
+    /// The response headers.
+    pub headers: Vec<(String, String)>,
+    /// The response body.
+    pub body: Vec<u8>,
+}
+
+/// An error parsing captured traffic.
+#[derive(Debug)]
+pub enum InputError {
+    /// The input could not be read.
+    Io(std::io::Error),
+    /// The input is not valid JSON, or does not have the expected structure.
+    Json {
+        /// The line the error occurred on, for JSON lines input.
+        line: Option<usize>,
+        /// The underlying error.
+        source: serde_json::Error,
+    },
+    /// A base64-encoded HAR response body could not be decoded.
+    Base64(base64::DecodeError),
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputError::Io(err) => write!(f, "Failed to read input: {}", err),
+            InputError::Json {
+                line: Some(line),
+                source,
+            } => write!(f, "Invalid entry on line {}: {}", line, source),
+            InputError::Json { line: None, source } => write!(f, "Invalid HAR: {}", source),
+            InputError::Base64(err) => write!(f, "Invalid base64 response body: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for InputError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            InputError::Io(err) => Some(err),
+            InputError::Json { source, .. } => Some(source),
+            InputError::Base64(err) => Some(err),
+        }
+    }
+}
+
+const UNSPECIFIED: &str = "0.0.0.0";
+
+#[derive(Deserialize)]
+struct Har {
+    log: HarLog,
+}
+
+#[derive(Deserialize)]
+struct HarLog {
+    entries: Vec<HarEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HarEntry {
+    request: HarRequest,
+    response: Option<HarResponse>,
+    #[serde(rename = "serverIPAddress")]
+    server_ip_address: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HarRequest {
+    method: String,
+    url: String,
+    http_version: String,
+    headers: Vec<HarHeader>,
+    post_data: Option<HarPostData>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HarResponse {
+    status: i32,
+    http_version: String,
+    headers: Vec<HarHeader>,
+    content: Option<HarContent>,
+}
+
+#[derive(Deserialize)]
+struct HarHeader {
+    name: String,
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct HarPostData {
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct HarContent {
+    text: Option<String>,
+    encoding: Option<String>,
+}
+
+/// Parses the entries of a HAR file.
+pub fn parse_har(input: &str) -> Result<Vec<Entry>, InputError> {
+    let har: Har =
+        serde_json::from_str(input).map_err(|source| InputError::Json { line: None, source })?;
+
+    har.log
+        .entries
+        .into_iter()
+        .map(|entry| {
+            let request = entry.request;
+            let (uri, authority) = split_url(&request.url);
+            let headers = request_headers(
+                request
+                    .headers
+                    .into_iter()
+                    .map(|header| (header.name, header.value))
+                    .collect(),
+                authority,
+            );
+
+            let response = entry
+                .response
+                .map(|response| {
+                    let body = match response.content {
+                        Some(HarContent {
+                            text: Some(text),
+                            encoding: Some(encoding),
+                        }) if encoding == "base64" => base64::engine::general_purpose::STANDARD
+                            .decode(text)
+                            .map_err(InputError::Base64)?,
+                        Some(HarContent {
+                            text: Some(text), ..
+                        }) => text.into_bytes(),
+                        _ => Vec::new(),
+                    };
+
+                    Ok::<_, InputError>(ResponseEntry {
+                        status: response.status,
+                        http_version: http_version(&response.http_version),
+                        headers: response
+                            .headers
+                            .into_iter()
+                            .filter(|header| !header.name.starts_with(':'))
+                            .map(|header| (header.name, header.value))
+                            .collect(),
+                        body,
+                    })
+                })
+                .transpose()?;
+
+            let server = entry
+                .server_ip_address
+                .filter(|address| !address.is_empty())
+                .map(|address| address.trim_matches(['[', ']']).to_string())
+                .unwrap_or_else(|| UNSPECIFIED.to_string());
+
+            Ok(Entry {
+                method: request.method,
+                uri,
+                http_version: http_version(&request.http_version),
+                headers,
+                body: request
+                    .post_data
+                    .and_then(|post_data| post_data.text)
+                    .unwrap_or_default()
+                    .into_bytes(),
+                client: UNSPECIFIED.to_string(),
+                client_port: 0,
+                server,
+                server_port: default_port(&request.url),
+                response,
+            })
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct JsonEntry {
+    #[serde(default = "default_method")]
+    method: String,
+    uri: String,
+    #[serde(default = "default_http_version")]
+    http_version: String,
+    #[serde(default)]
+    headers: JsonHeaders,
+    #[serde(default)]
+    body: String,
+    #[serde(default = "unspecified")]
+    client: String,
+    #[serde(default)]
+    client_port: i32,
+    #[serde(default = "unspecified")]
+    server: String,
+    #[serde(default)]
+    server_port: i32,
+    response: Option<JsonResponse>,
+}
+
+#[derive(Deserialize)]
+struct JsonResponse {
+    status: i32,
+    #[serde(default = "default_http_version")]
+    http_version: String,
+    #[serde(default)]
+    headers: JsonHeaders,
+    #[serde(default)]
+    body: String,
+}
+
+/// The headers of a JSON lines entry, in the order they appear.
+#[derive(Default)]
+struct JsonHeaders(Vec<(String, String)>);
+
+/// The value of a header in an object of headers.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonHeaderValue {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl<'de> Deserialize<'de> for JsonHeaders {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct HeadersVisitor;
+
+        impl<'de> Visitor<'de> for HeadersVisitor {
+            type Value = JsonHeaders;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("an object of headers or a list of [name, value] pairs")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<JsonHeaders, A::Error> {
+                let mut headers = Vec::new();
+                while let Some((name, value)) = map.next_entry::<String, JsonHeaderValue>()? {
+                    match value {
+                        JsonHeaderValue::One(value) => headers.push((name, value)),
+                        JsonHeaderValue::Many(values) => {
+                            headers.extend(values.into_iter().map(|value| (name.clone(), value)))
+                        }
+                    }
+                }
+                Ok(JsonHeaders(headers))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<JsonHeaders, A::Error> {
+                let mut headers = Vec::new();
+                while let Some(header) = seq.next_element()? {
+                    headers.push(header);
+                }
+                Ok(JsonHeaders(headers))
+            }
+        }
+
+        deserializer.deserialize_any(HeadersVisitor)
+    }
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+fn default_http_version() -> String {
+    "1.1".to_string()
+}
+
+fn unspecified() -> String {
+    UNSPECIFIED.to_string()
+}
+
+/// Parses JSON lines entries, skipping blank lines.
+pub fn parse_json_lines<R: BufRead>(input: R) -> Result<Vec<Entry>, InputError> {
+    let mut entries = Vec::new();
+
+    for (index, line) in input.lines().enumerate() {
+        let line = line.map_err(InputError::Io)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let entry: JsonEntry = serde_json::from_str(&line).map_err(|source| InputError::Json {
+            line: Some(index + 1),
+            source,
+        })?;
+
+        let (uri, authority) = split_url(&entry.uri);
+
+        entries.push(Entry {
+            method: entry.method,
+            uri,
+            http_version: http_version(&entry.http_version),
+            headers: request_headers(entry.headers.0, authority),
+            body: entry.body.into_bytes(),
+            client: entry.client,
+            client_port: entry.client_port,
+            server: entry.server,
+            server_port: entry.server_port,
+            response: entry.response.map(|response| ResponseEntry {
+                status: response.status,
+                http_version: http_version(&response.http_version),
+                headers: response.headers.0,
+                body: response.body.into_bytes(),
+            }),
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Splits an absolute URL into the URI of the request line and its authority. Other URIs are
+/// returned as is.
+fn split_url(url: &str) -> (String, Option<&str>) {
+    let Some((_, rest)) = url.split_once("://") else {
+        return (url.to_string(), None);
+    };
+
+    let (authority, uri) = rest.split_at(rest.find(['/', '?']).unwrap_or(rest.len()));
+
+    if uri.starts_with('/') {
+        (uri.to_string(), Some(authority))
+    } else {
+        (format!("/{}", uri), Some(authority))
+    }
+}
+
+/// Drops HTTP/2 pseudo-headers, adding a `Host` header from `authority` if there is none.
+fn request_headers(
+    headers: Vec<(String, String)>,
+    authority: Option<&str>,
+) -> Vec<(String, String)> {
+    let mut headers: Vec<_> = headers
+        .into_iter()
+        .filter(|(name, _)| !name.starts_with(':'))
+        .collect();
+
+    if let Some(authority) = authority {
+        if !headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("host"))
+        {
+            headers.insert(0, ("Host".to_string(), authority.to_string()));
+        }
+    }
+
+    headers
+}
+
+/// Normalizes an HTTP version as found in HAR files (e.g. `HTTP/1.1`, `h2`) to the form expected
+/// by ModSecurity (e.g. `1.1`, `2.0`).
+fn http_version(version: &str) -> String {
+    let version = version.trim();
+    let number = version
+        .get(..5)
+        .filter(|prefix| prefix.eq_ignore_ascii_case("http/"))
+        .map_or(version, |_| &version[5..]);
+
+    match number {
+        "" => default_http_version(),
+        "h2" | "2" => "2.0".to_string(),
+        "h3" | "3" => "3.0".to_string(),
+        number => number.to_string(),
+    }
+}
+
+/// Returns the port of an absolute URL, or `0` if it cannot be determined.
+fn default_port(url: &str) -> i32 {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return 0;
+    };
+
+    let authority = &rest[..rest.find(['/', '?']).unwrap_or(rest.len())];
+    let port = authority
+        .rsplit_once(':')
+        .filter(|(host, _)| host.starts_with('[') == host.ends_with(']'))
+        .and_then(|(_, port)| port.parse().ok());
+
+    match (port, scheme) {
+        (Some(port), _) => port,
+        (None, "https") => 443,
+        (None, "http") => 80,
+        (None, _) => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HAR: &str = r#"{
+        "log": {
+            "version": "1.2",
+            "creator": {"name": "test", "version": "1.0"},
+            "entries": [
+                {
+                    "startedDateTime": "2024-01-01T00:00:00.000Z",
+                    "time": 1,
+                    "serverIPAddress": "[::1]",
+                    "request": {
+                        "method": "POST",
+                        "url": "https://example.com:8443/login?next=/",
+                        "httpVersion": "h2",
+                        "headers": [
+                            {"name": ":authority", "value": "example.com:8443"},
+                            {"name": "content-type", "value": "application/x-www-form-urlencoded"}
+                        ],
+                        "postData": {"mimeType": "application/x-www-form-urlencoded", "text": "user=admin"}
+                    },
+                    "response": {
+                        "status": 200,
+                        "httpVersion": "HTTP/1.1",
+                        "headers": [{"name": "content-type", "value": "text/plain"}],
+                        "content": {"size": 5, "mimeType": "text/plain", "text": "SGVsbG8=", "encoding": "base64"}
+                    }
+                },
+                {
+                    "request": {
+                        "method": "GET",
+                        "url": "http://example.com",
+                        "httpVersion": "HTTP/1.1",
+                        "headers": [{"name": "Host", "value": "example.com"}]
+                    }
+                }
+            ]
+        }
+    }"#;
+
+    #[test]
+    fn test_parse_har() {
+        let entries = parse_har(HAR).unwrap();
+
+        assert_eq!(
+            entries,
+            vec![
+                Entry {
+                    method: "POST".to_string(),
+                    uri: "/login?next=/".to_string(),
+                    http_version: "2.0".to_string(),
+                    headers: vec![
+                        ("Host".to_string(), "example.com:8443".to_string()),
+                        (
+                            "content-type".to_string(),
+                            "application/x-www-form-urlencoded".to_string()
+                        ),
+                    ],
+                    body: b"user=admin".to_vec(),
+                    client: "0.0.0.0".to_string(),
+                    client_port: 0,
+                    server: "::1".to_string(),
+                    server_port: 8443,
+                    response: Some(ResponseEntry {
+                        status: 200,
+                        http_version: "1.1".to_string(),
+                        headers: vec![("content-type".to_string(), "text/plain".to_string())],
+                        body: b"Hello".to_vec(),
+                    }),
+                },
+                Entry {
+                    method: "GET".to_string(),
+                    uri: "/".to_string(),
+                    http_version: "1.1".to_string(),
+                    headers: vec![("Host".to_string(), "example.com".to_string())],
+                    body: Vec::new(),
+                    client: "0.0.0.0".to_string(),
+                    client_port: 0,
+                    server: "0.0.0.0".to_string(),
+                    server_port: 80,
+                    response: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_har_invalid() {
+        assert!(matches!(
+            parse_har(r#"{"log": {}}"#),
+            Err(InputError::Json { line: None, .. })
+        ));
+        assert!(matches!(
+            parse_har(&HAR.replace("SGVsbG8=", "not base64!")),
+            Err(InputError::Base64(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_json_lines() {
+        let input = concat!(
+            r#"{"uri": "/"}"#,
+            "\n\n",
+            r#"{"method": "POST", "uri": "http://example.com/a?b=c", "headers": {"Content-Type": "text/plain"}, "body": "hi", "client": "10.0.0.1", "client_port": 1234, "response": {"status": 404}}"#,
+            "\n",
+            r#"{"uri": "/", "headers": [["Cookie", "a=1"], ["Accept", "*/*"], ["Cookie", "b=2"]], "response": {"status": 200, "headers": {"Set-Cookie": ["a=1", "b=2"], "Content-Type": "text/plain"}}}"#,
+            "\n",
+        );
+
+        let entries = parse_json_lines(input.as_bytes()).unwrap();
+
+        assert_eq!(
+            entries,
+            vec![
+                Entry {
+                    method: "GET".to_string(),
+                    uri: "/".to_string(),
+                    http_version: "1.1".to_string(),
+                    headers: Vec::new(),
+                    body: Vec::new(),
+                    client: "0.0.0.0".to_string(),
+                    client_port: 0,
+                    server: "0.0.0.0".to_string(),
+                    server_port: 0,
+                    response: None,
+                },
+                Entry {
+                    method: "POST".to_string(),
+                    uri: "/a?b=c".to_string(),
+                    http_version: "1.1".to_string(),
+                    headers: vec![
+                        ("Host".to_string(), "example.com".to_string()),
+                        ("Content-Type".to_string(), "text/plain".to_string()),
+                    ],
+                    body: b"hi".to_vec(),
+                    client: "10.0.0.1".to_string(),
+                    client_port: 1234,
+                    server: "0.0.0.0".to_string(),
+                    server_port: 0,
+                    response: Some(ResponseEntry {
+                        status: 404,
+                        http_version: "1.1".to_string(),
+                        headers: Vec::new(),
+                        body: Vec::new(),
+                    }),
+                },
+                Entry {
+                    method: "GET".to_string(),
+                    uri: "/".to_string(),
+                    http_version: "1.1".to_string(),
+                    headers: vec![
+                        ("Cookie".to_string(), "a=1".to_string()),
+                        ("Accept".to_string(), "*/*".to_string()),
+                        ("Cookie".to_string(), "b=2".to_string()),
+                    ],
+                    body: Vec::new(),
+                    client: "0.0.0.0".to_string(),
+                    client_port: 0,
+                    server: "0.0.0.0".to_string(),
+                    server_port: 0,
+                    response: Some(ResponseEntry {
+                        status: 200,
+                        http_version: "1.1".to_string(),
+                        headers: vec![
+                            ("Set-Cookie".to_string(), "a=1".to_string()),
+                            ("Set-Cookie".to_string(), "b=2".to_string()),
+                            ("Content-Type".to_string(), "text/plain".to_string()),
+                        ],
+                        body: Vec::new(),
+                    }),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_json_lines_invalid() {
+        let input = "{\"uri\": \"/\"}\n{\"method\": \"GET\"}\n";
+
+        assert!(matches!(
+            parse_json_lines(input.as_bytes()),
+            Err(InputError::Json { line: Some(2), .. })
+        ));
+
+        let input = "{\"uri\": \"/\", \"headers\": [[\"Host\"]]}\n";
+
+        assert!(matches!(
+            parse_json_lines(input.as_bytes()),
+            Err(InputError::Json { line: Some(1), .. })
+        ));
+    }
+
+    #[test]
+    fn test_split_url() {
+        assert_eq!(split_url("/a?b"), ("/a?b".to_string(), None));
+        assert_eq!(
+            split_url("https://example.com/a?b"),
+            ("/a?b".to_string(), Some("example.com"))
+        );
+        assert_eq!(
+            split_url("http://example.com?b"),
+            ("/?b".to_string(), Some("example.com"))
+        );
+        assert_eq!(
+            split_url("http://example.com"),
+            ("/".to_string(), Some("example.com"))
+        );
+    }
+
+    #[test]
+    fn test_http_version() {
+        assert_eq!(http_version("HTTP/1.0"), "1.0");
+        assert_eq!(http_version("http/2.0"), "2.0");
+        assert_eq!(http_version("h2"), "2.0");
+        assert_eq!(http_version("h3"), "3.0");
+        assert_eq!(http_version(""), "1.1");
+    }
+
+    #[test]
+    fn test_default_port() {
+        assert_eq!(default_port("https://example.com/"), 443);
+        assert_eq!(default_port("http://example.com"), 80);
+        assert_eq!(default_port("http://example.com:8080/a"), 8080);
+        assert_eq!(default_port("http://[::1]/a"), 80);
+        assert_eq!(default_port("http://[::1]:81/a"), 81);
+        assert_eq!(default_port("/a"), 0);
+    }
+}
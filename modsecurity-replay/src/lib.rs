@@ -0,0 +1,387 @@
+//! Replays captured HTTP traffic against a ModSecurity rule set.
+//!
+//! Each [`Entry`] is driven through its own [`Transaction`](modsecurity::Transaction), the same
+//! way a server would: processing stops at the first disruptive intervention, and the logging
+//! phase is always run. The resulting [`Outcome`] records every rule that matched and every
+//! intervention, along with the phase they occurred in.
+//!
+//! Interventions are only disruptive with `SecRuleEngine On`. With `SecRuleEngine DetectionOnly`,
+//! no request is reported as blocked, but the rules that matched are still recorded.
+
+pub mod input;
+
+use std::sync::{Arc, Mutex, PoisonError};
+
+use modsecurity::{
+    transaction::Phase, ModSecurity, ModSecurityResult, RuleMatch, Rules, Transaction,
+};
+
+pub use input::{Entry, InputError, ResponseEntry};
+
+/// An intervention raised while replaying an entry.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Triggered {
+    /// The phase that raised the intervention.
+    pub phase: Phase,
+    /// The status of the intervention.
+    pub status: i32,
+    /// The log of the intervention, if any.
+    pub log: Option<String>,
+    /// Whether the intervention is disruptive.
+    pub disruptive: bool,
+}
+
+/// A rule that matched while replaying an entry.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Matched {
+    /// The phase the rule matched in.
+    pub phase: Phase,
+    /// The match, as logged by libmodsecurity.
+    pub rule: RuleMatch,
+}
+
+/// The outcome of replaying an entry.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Outcome {
+    /// The interventions raised, in the order of the phases that raised them.
+    pub triggered: Vec<Triggered>,
+    /// The rules that matched, in the order they matched.
+    pub matched: Vec<Matched>,
+}
+
+impl Outcome {
+    /// Returns the disruptive intervention that would have blocked the request, if any.
+    pub fn disruption(&self) -> Option<&Triggered> {
+        self.triggered.iter().find(|triggered| triggered.disruptive)
+    }
+
+    /// Returns the phases in which a rule matched or an intervention was raised, in order.
+    pub fn phases(&self) -> impl Iterator<Item = Phase> + '_ {
+        PHASES.into_iter().filter(move |&phase| {
+            self.matched.iter().any(|matched| matched.phase == phase)
+                || self
+                    .triggered
+                    .iter()
+                    .any(|triggered| triggered.phase == phase)
+        })
+    }
+}
+
+/// All phases, in the order they run in.
+const PHASES: [Phase; 7] = [
+    Phase::Connection,
+    Phase::Uri,
+    Phase::RequestHeaders,
+    Phase::RequestBody,
+    Phase::ResponseHeaders,
+    Phase::ResponseBody,
+    Phase::Logging,
+];
+
+/// Returns the name of `phase`, as used in the output of the replay CLI.
+pub fn phase_name(phase: Phase) -> &'static str {
+    match phase {
+        Phase::Connection => "connection",
+        Phase::Uri => "uri",
+        Phase::RequestHeaders => "request_headers",
+        Phase::RequestBody => "request_body",
+        Phase::ResponseHeaders => "response_headers",
+        Phase::ResponseBody => "response_body",
+        Phase::Logging => "logging",
+    }
+}
+
+/// Replays `entry` against `rules`.
+///
+/// Rules that matched are only recorded if `ms` was built with
+/// [`with_log_callbacks()`](modsecurity::msc::ModSecurityBuilder::with_log_callbacks), and if
+/// they log, which excludes rules with the `nolog` action.
+pub fn replay(ms: &ModSecurity, rules: &Rules, entry: &Entry) -> ModSecurityResult<Outcome> {
+    let logged = Arc::new(Mutex::new(Vec::new()));
+
+    let mut transaction = ms
+        .transaction_builder()
+        .with_rules(rules)
+        .with_structured_logging({
+            let logged = Arc::clone(&logged);
+            move |rule_match| {
+                logged
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .push(rule_match.clone());
+            }
+        })
+        .build()?;
+    let mut replayed = Replayed {
+        outcome: Outcome::default(),
+        logged,
+    };
+
+    let result = run_phases(&mut transaction, entry, &mut replayed);
+
+    transaction.process_logging()?;
+    replayed.check(&mut transaction, Phase::Logging);
+
+    result.map(|()| replayed.outcome)
+}
+
+/// The state of an entry being replayed.
+struct Replayed {
+    outcome: Outcome,
+    /// The rule matches logged since the last call to [`Replayed::check()`].
+    logged: Arc<Mutex<Vec<RuleMatch>>>,
+}
+
+impl Replayed {
+    /// Records the rules that matched in `phase` and the intervention it raised, if any,
+    /// returning whether the intervention is disruptive.
+    ///
+    /// Log callbacks are called while a phase is processed, so every match logged since the
+    /// previous phase belongs to `phase`.
+    fn check(&mut self, transaction: &mut Transaction, phase: Phase) -> bool {
+        let logged =
+            std::mem::take(&mut *self.logged.lock().unwrap_or_else(PoisonError::into_inner));
+        self.outcome
+            .matched
+            .extend(logged.into_iter().map(|rule| Matched { phase, rule }));
+
+        let Some(intervention) = transaction.intervention() else {
+            return false;
+        };
+
+        let disruptive = intervention.disruptive();
+
+        self.outcome.triggered.push(Triggered {
+            phase,
+            status: intervention.status(),
+            log: intervention.log().map(str::to_string),
+            disruptive,
+        });
+
+        disruptive
+    }
+}
+
+/// Runs the phases before logging, stopping at the first disruptive intervention.
+fn run_phases(
+    transaction: &mut Transaction,
+    entry: &Entry,
+    replayed: &mut Replayed,
+) -> ModSecurityResult<()> {
+    transaction.process_connection(
+        &entry.client,
+        entry.client_port,
+        &entry.server,
+        entry.server_port,
+    )?;
+    if replayed.check(transaction, Phase::Connection) {
+        return Ok(());
+    }
+
+    transaction.process_uri(&entry.uri, &entry.method, &entry.http_version)?;
+    if replayed.check(transaction, Phase::Uri) {
+        return Ok(());
+    }
+
+    for (key, value) in &entry.headers {
+        transaction.add_request_header(key, value)?;
+    }
+    transaction.process_request_headers()?;
+    if replayed.check(transaction, Phase::RequestHeaders) {
+        return Ok(());
+    }
+
+    transaction.append_request_body(&entry.body)?;
+    transaction.process_request_body()?;
+    if replayed.check(transaction, Phase::RequestBody) {
+        return Ok(());
+    }
+
+    let Some(response) = &entry.response else {
+        return Ok(());
+    };
+
+    for (key, value) in &response.headers {
+        transaction.add_response_header(key, value)?;
+    }
+    transaction
+        .process_response_headers(response.status, &format!("HTTP {}", response.http_version))?;
+    if replayed.check(transaction, Phase::ResponseHeaders) {
+        return Ok(());
+    }
+
+    transaction.append_response_body(&response.body)?;
+    transaction.process_response_body()?;
+    replayed.check(transaction, Phase::ResponseBody);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(uri: &str) -> Entry {
+        Entry {
+            method: "GET".to_string(),
+            uri: uri.to_string(),
+            http_version: "1.1".to_string(),
+            headers: vec![("Host".to_string(), "example.com".to_string())],
+            body: Vec::new(),
+            client: "127.0.0.1".to_string(),
+            client_port: 12345,
+            server: "127.0.0.1".to_string(),
+            server_port: 80,
+            response: None,
+        }
+    }
+
+    fn rules(plain_rules: &str) -> Rules {
+        let mut rules = Rules::new();
+        rules.add_plain(plain_rules).unwrap();
+        rules
+    }
+
+    fn ms() -> ModSecurity {
+        ModSecurity::builder().with_log_callbacks().build()
+    }
+
+    #[test]
+    fn test_replay_allowed() {
+        let ms = ms();
+        let rules = rules(
+            r#"
+            SecRuleEngine On
+
+            SecRule REQUEST_URI "@rx admin" "id:1,phase:1,deny,status:401"
+        "#,
+        );
+
+        let outcome = replay(&ms, &rules, &entry("/index.html")).unwrap();
+
+        assert_eq!(outcome, Outcome::default());
+        assert!(outcome.disruption().is_none());
+    }
+
+    #[test]
+    fn test_replay_request_intervention() {
+        let ms = ms();
+        let rules = rules(
+            r#"
+            SecRuleEngine On
+
+            SecRule REQUEST_URI "@rx admin" "id:1,phase:1,log,deny,status:401"
+        "#,
+        );
+
+        let outcome = replay(&ms, &rules, &entry("/admin")).unwrap();
+
+        let disruption = outcome.disruption().unwrap();
+        assert_eq!(disruption.phase, Phase::RequestHeaders);
+        assert_eq!(disruption.status, 401);
+        assert!(disruption.log.as_deref().unwrap().contains(r#"[id "1"]"#));
+
+        assert_eq!(outcome.matched.len(), 1);
+        assert_eq!(outcome.matched[0].phase, Phase::RequestHeaders);
+        assert_eq!(outcome.matched[0].rule.id, Some(1));
+    }
+
+    #[test]
+    fn test_replay_detection_only() {
+        let ms = ms();
+        let rules = rules(
+            r#"
+            SecRuleEngine DetectionOnly
+
+            SecRule REQUEST_URI "@rx admin" "id:1,phase:1,log,deny,status:401,msg:'Admin access'"
+            SecRule REQUEST_HEADERS:Host "@rx example" "id:2,phase:2,log,pass"
+            SecRule REQUEST_URI "@rx admin" "id:3,phase:2,nolog,pass"
+        "#,
+        );
+
+        let outcome = replay(&ms, &rules, &entry("/admin")).unwrap();
+
+        // No intervention is disruptive, but the rules that matched are still recorded.
+        assert!(outcome.disruption().is_none());
+        assert_eq!(
+            outcome
+                .matched
+                .iter()
+                .map(|matched| (matched.phase, matched.rule.id))
+                .collect::<Vec<_>>(),
+            vec![
+                (Phase::RequestHeaders, Some(1)),
+                (Phase::RequestBody, Some(2)),
+            ]
+        );
+        assert_eq!(outcome.matched[0].rule.msg.as_deref(), Some("Admin access"));
+        assert_eq!(
+            outcome.phases().collect::<Vec<_>>(),
+            vec![Phase::RequestHeaders, Phase::RequestBody]
+        );
+    }
+
+    #[test]
+    fn test_phases() {
+        let rule = |phase| Matched {
+            phase,
+            rule: RuleMatch::default(),
+        };
+
+        let outcome = Outcome {
+            triggered: vec![Triggered {
+                phase: Phase::ResponseBody,
+                status: 403,
+                log: None,
+                disruptive: true,
+            }],
+            matched: vec![
+                rule(Phase::RequestHeaders),
+                rule(Phase::RequestHeaders),
+                rule(Phase::ResponseBody),
+            ],
+        };
+
+        assert_eq!(
+            outcome.phases().collect::<Vec<_>>(),
+            vec![Phase::RequestHeaders, Phase::ResponseBody]
+        );
+    }
+
+    #[test]
+    fn test_replay_response_intervention() {
+        let ms = ms();
+        let rules = rules(
+            r#"
+            SecRuleEngine On
+
+            SecResponseBodyAccess On
+            SecResponseBodyMimeType text/plain
+
+            SecRule RESPONSE_BODY "@rx secret" "id:1,phase:4,deny,status:500"
+        "#,
+        );
+
+        let mut entry = entry("/");
+        entry.response = Some(ResponseEntry {
+            status: 200,
+            http_version: "1.1".to_string(),
+            headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+            body: b"a secret".to_vec(),
+        });
+
+        let outcome = replay(&ms, &rules, &entry).unwrap();
+
+        assert_eq!(
+            outcome.phases().collect::<Vec<_>>(),
+            vec![Phase::ResponseBody]
+        );
+        assert_eq!(outcome.disruption().unwrap().status, 500);
+    }
+
+    #[test]
+    fn test_phase_name() {
+        assert_eq!(phase_name(Phase::RequestHeaders), "request_headers");
+        assert_eq!(phase_name(Phase::Logging), "logging");
+    }
+}
@@ -0,0 +1,180 @@
+//! Replays captured HTTP traffic against a ModSecurity rule set.
+//!
+//! ```text
+//! modsecurity-replay [--format <FORMAT>] --rules <FILE>... [<INPUT>...]
+//! ```
+//!
+//! Prints one JSON object per replayed request, e.g.
+//!
+//! ```json
+//! {"index":0,"input":"traffic.har","log":"...","method":"GET","phases":["request_headers"],"rules":[{"id":1,"msg":"Admin access","phase":"request_headers"}],"status":403,"uri":"/admin"}
+//! ```
+//!
+//! `status` and `log` are those of the disruptive intervention, and are `null` if the request
+//! would not have been blocked, which is always the case unless the rules set
+//! `SecRuleEngine On`. `rules` lists every rule that matched and the phase it matched in, and
+//! `phases` every phase in which a rule matched or an intervention was raised.
+
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    process,
+};
+
+use modsecurity::{ModSecurity, Rules};
+use modsecurity_replay::{input, phase_name, replay, Entry, InputError};
+use serde_json::json;
+
+const USAGE: &str = "Usage: modsecurity-replay [--format <FORMAT>] --rules <FILE>... [<INPUT>...]
+
+Arguments:
+    <INPUT>             HAR or JSON lines file to replay, or `-` for stdin [default: -]
+
+Options:
+    --format <FORMAT>   Input format, `har` or `jsonl` [default: `har` for `.har` files, `jsonl` otherwise]
+    --rules <FILE>      Rules file to load, can be given more than once";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Har,
+    JsonLines,
+}
+
+struct Args {
+    format: Option<Format>,
+    rules: Vec<String>,
+    inputs: Vec<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args {
+        format: None,
+        rules: Vec::new(),
+        inputs: Vec::new(),
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", arg))
+        };
+
+        match arg.as_str() {
+            "--format" => {
+                parsed.format = Some(match value()?.as_str() {
+                    "har" => Format::Har,
+                    "jsonl" => Format::JsonLines,
+                    other => return Err(format!("Invalid format: {}", other)),
+                })
+            }
+            "--rules" => parsed.rules.push(value()?),
+            _ if arg.starts_with("--") => return Err(format!("Unexpected argument: {}", arg)),
+            _ => parsed.inputs.push(arg),
+        }
+    }
+
+    if parsed.rules.is_empty() {
+        return Err("At least one rules file is required".to_string());
+    }
+
+    if parsed.inputs.is_empty() {
+        parsed.inputs.push("-".to_string());
+    }
+
+    Ok(parsed)
+}
+
+fn read_entries(path: &str, format: Option<Format>) -> Result<Vec<Entry>, InputError> {
+    let format = format.unwrap_or(if path.ends_with(".har") {
+        Format::Har
+    } else {
+        Format::JsonLines
+    });
+
+    let mut reader: Box<dyn BufRead> = if path == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(
+            fs::File::open(path).map_err(InputError::Io)?,
+        ))
+    };
+
+    match format {
+        Format::Har => {
+            let mut har = String::new();
+            reader.read_to_string(&mut har).map_err(InputError::Io)?;
+            input::parse_har(&har)
+        }
+        Format::JsonLines => input::parse_json_lines(reader),
+    }
+}
+
+fn main() {
+    let args = parse_args(std::env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{}\n\n{}", err, USAGE);
+        process::exit(2);
+    });
+
+    let mut rules = Rules::new();
+    for file in &args.rules {
+        if let Err(err) = rules.add_file(file) {
+            eprintln!("Failed to load {}: {}", file, err);
+            process::exit(1);
+        }
+    }
+
+    // Log callbacks are needed to record the rules that matched.
+    let ms = ModSecurity::builder().with_log_callbacks().build();
+    let mut stdout = io::stdout().lock();
+    let (mut total, mut blocked) = (0, 0);
+
+    for path in &args.inputs {
+        let entries = read_entries(path, args.format).unwrap_or_else(|err| {
+            eprintln!("{}: {}", path, err);
+            process::exit(1);
+        });
+
+        for (index, entry) in entries.iter().enumerate() {
+            let outcome = match replay(&ms, &rules, entry) {
+                Ok(outcome) => outcome,
+                Err(err) => {
+                    eprintln!("{}: Failed to replay entry {}: {}", path, index, err);
+                    continue;
+                }
+            };
+
+            let disruption = outcome.disruption();
+
+            total += 1;
+            if disruption.is_some() {
+                blocked += 1;
+            }
+
+            let line = json!({
+                "input": path,
+                "index": index,
+                "method": entry.method,
+                "uri": entry.uri,
+                "status": disruption.map(|disruption| disruption.status),
+                "log": disruption.and_then(|disruption| disruption.log.as_deref()),
+                "phases": outcome.phases().map(phase_name).collect::<Vec<_>>(),
+                "rules": outcome
+                    .matched
+                    .iter()
+                    .map(|matched| json!({
+                        "id": matched.rule.id,
+                        "msg": matched.rule.msg,
+                        "phase": phase_name(matched.phase),
+                    }))
+                    .collect::<Vec<_>>(),
+            });
+
+            if writeln!(stdout, "{}", line).is_err() {
+                // The output was closed, e.g. when piped to `head`.
+                process::exit(0);
+            }
+        }
+    }
+
+    eprintln!("Replayed {} requests, {} would be blocked", total, blocked);
+}
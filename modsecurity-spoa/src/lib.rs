@@ -0,0 +1,862 @@
+//! A HAProxy [SPOE](https://github.com/haproxy/haproxy/blob/master/doc/SPOE.txt) agent backed by
+//! ModSecurity.
+//!
+//! Each message sent by HAProxy is inspected by its own [`Transaction`](modsecurity::Transaction),
+//! built from the following arguments:
+//!
+//! | Argument   | Sample fetch                  | Required |
+//! |------------|-------------------------------|----------|
+//! | `method`   | `method`                      | yes      |
+//! | `path`     | `path` (or `pathq`)           | yes      |
+//! | `query`    | `query`                       | no       |
+//! | `version`  | `req.ver`                     | no       |
+//! | `headers`  | `req.hdrs_bin` (or `req.hdrs`) | no      |
+//! | `body`     | `req.body`                    | no       |
+//! | `src`      | `src`                         | no       |
+//! | `src_port` | `src_port`                    | no       |
+//! | `dst`      | `dst`                         | no       |
+//! | `dst_port` | `dst_port`                    | no       |
+//!
+//! The verdict is returned as transaction-scoped variables:
+//!
+//! | Variable  | Value                                                                    |
+//! |-----------|--------------------------------------------------------------------------|
+//! | `status`  | The status of the disruptive intervention, or `0` if there was none     |
+//! | `rule_id` | The ID of the rule that raised the intervention, if it could be found   |
+//! | `log`     | The log of the intervention, if any                                      |
+//! | `url`     | The redirect URL of the intervention, if any                             |
+//!
+//! With `option var-prefix modsec`, these are available as `txn.modsec.status` and so on.
+//! A message that cannot be processed sets `status` to `-1`. The `log` variables are left out if
+//! they would make the `ACK` frame larger than the maximum frame size.
+//!
+//! Errors that do not stop the agent, such as failed connections, are passed to the handler set
+//! with [`Agent::with_error_handler()`].
+//!
+//! ```text
+//! [modsecurity]
+//! spoe-agent modsecurity-agent
+//!     messages     check-request
+//!     option       var-prefix modsec
+//!     timeout      hello 100ms
+//!     timeout      idle  30s
+//!     timeout      processing 1s
+//!     use-backend  spoe-modsecurity
+//!
+//! spoe-message check-request
+//!     args method=method path=path query=query version=req.ver headers=req.hdrs_bin body=req.body src=src src_port=src_port dst=dst dst_port=dst_port
+//!     event on-frontend-http-request
+//! ```
+
+pub mod spop;
+
+use std::{
+    fmt,
+    io::{self, BufReader, BufWriter},
+    net::{IpAddr, Ipv4Addr, TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use modsecurity::{Intervention, ModSecurity, ModSecurityError, ModSecurityResult, Rules};
+
+use spop::{status, Action, Data, Decoder, Encoder, Frame, FrameType, Message, Scope};
+
+/// The largest frame size supported by the agent.
+pub const MAX_FRAME_SIZE: u32 = 16384;
+
+/// How long [`Agent::serve()`] waits before accepting connections again after an error.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// An error that did not stop the agent. See [`Agent::with_error_handler()`].
+#[derive(Debug)]
+pub enum Error {
+    /// A connection could not be accepted. The agent keeps accepting connections after a short
+    /// delay.
+    Accept(io::Error),
+    /// A connection failed and was closed.
+    Connection(io::Error),
+    /// A message could not be inspected, so its `status` was set to `-1`.
+    Inspect {
+        /// The name of the message.
+        message: String,
+        /// The error returned by ModSecurity.
+        error: ModSecurityError,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Accept(err) => write!(f, "Error accepting connection: {}", err),
+            Error::Connection(err) => write!(f, "Connection error: {}", err),
+            Error::Inspect { message, error } => {
+                write!(f, "Error inspecting message {}: {}", message, error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+type ErrorHandler = Box<dyn Fn(&Error) + Send + Sync + 'static>;
+
+/// A SPOE agent that inspects requests using ModSecurity.
+pub struct Agent {
+    ms: ModSecurity,
+    rules: Rules,
+    error_handler: ErrorHandler,
+}
+
+impl Agent {
+    /// Creates a new agent that inspects requests using `ms` and `rules`.
+    pub fn new(ms: ModSecurity, rules: Rules) -> Self {
+        Self {
+            ms,
+            rules,
+            error_handler: Box::new(|_| {}),
+        }
+    }
+
+    /// Sets a handler that is called with each error that does not stop the agent. Errors are
+    /// ignored by default.
+    pub fn with_error_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&Error) + Send + Sync + 'static,
+    {
+        self.error_handler = Box::new(handler);
+        self
+    }
+
+    /// Accepts connections from `listener`, handling each one on its own thread.
+    ///
+    /// This only returns if `listener` stops yielding connections. Errors accepting a connection
+    /// are passed to the error handler, and accepting is retried after a short delay.
+    pub fn serve(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    (self.error_handler)(&Error::Accept(err));
+                    thread::sleep(ACCEPT_BACKOFF);
+                    continue;
+                }
+            };
+            let agent = Arc::clone(&self);
+
+            thread::spawn(move || {
+                if let Err(err) = agent.handle_connection(stream) {
+                    (agent.error_handler)(&Error::Connection(err));
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Handles a single connection from HAProxy until it is closed.
+    pub fn handle_connection(&self, stream: TcpStream) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream);
+
+        let mut max_frame_size = MAX_FRAME_SIZE;
+        let mut connected = false;
+
+        loop {
+            let frame = match spop::read_frame(&mut reader, max_frame_size) {
+                Ok(frame) => frame,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(err)
+                    if err
+                        .get_ref()
+                        .is_some_and(|err| err.is::<spop::FrameTooBig>()) =>
+                {
+                    return disconnect(&mut writer, status::FRAME_TOO_BIG, &err.to_string());
+                }
+                Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                    return disconnect(&mut writer, status::INVALID_FRAME, &err.to_string());
+                }
+                Err(err) => return Err(err),
+            };
+
+            if frame.flags & spop::FLAG_FIN == 0 {
+                return disconnect(
+                    &mut writer,
+                    status::FRAGMENTATION_NOT_SUPPORTED,
+                    "Fragmentation is not supported",
+                );
+            }
+
+            match (frame.frame_type, connected) {
+                (FrameType::HaproxyHello, false) => {
+                    let hello = match Decoder::new(&frame.payload).kv_list() {
+                        Ok(hello) => hello,
+                        Err(err) => {
+                            return disconnect(&mut writer, status::INVALID_FRAME, &err.to_string())
+                        }
+                    };
+                    let value = |name: &str| {
+                        hello
+                            .iter()
+                            .find(|(key, _)| key == name)
+                            .map(|(_, value)| value)
+                    };
+
+                    let supported = value("supported-versions")
+                        .and_then(Data::as_bytes)
+                        .map(|versions| {
+                            String::from_utf8_lossy(versions)
+                                .split(',')
+                                .any(|version| version.trim() == spop::VERSION)
+                        })
+                        .unwrap_or(false);
+
+                    if !supported {
+                        return disconnect(
+                            &mut writer,
+                            status::VERSION_MISMATCH,
+                            "Unsupported version",
+                        );
+                    }
+
+                    if let Some(size) = value("max-frame-size").and_then(Data::as_i64) {
+                        max_frame_size = max_frame_size.min(size.clamp(0, u32::MAX.into()) as u32);
+                    }
+
+                    let mut payload = Encoder::new();
+                    payload
+                        .kv("version", &Data::String(spop::VERSION.into()))
+                        .kv("max-frame-size", &Data::Uint32(max_frame_size))
+                        .kv("capabilities", &Data::String(b"pipelining".to_vec()));
+
+                    spop::write_frame(
+                        &mut writer,
+                        &Frame::new(FrameType::AgentHello, payload.finish()),
+                    )?;
+
+                    if value("healthcheck") == Some(&Data::Bool(true)) {
+                        return Ok(());
+                    }
+
+                    connected = true;
+                }
+                (FrameType::HaproxyDisconnect, true) => {
+                    return disconnect(&mut writer, status::NORMAL, "Disconnected by HAProxy");
+                }
+                (FrameType::Notify, true) => {
+                    let messages = match Decoder::new(&frame.payload).messages() {
+                        Ok(messages) => messages,
+                        Err(err) => {
+                            return disconnect(&mut writer, status::INVALID_FRAME, &err.to_string())
+                        }
+                    };
+
+                    let actions: Vec<_> = messages
+                        .iter()
+                        .flat_map(|message| self.actions(message))
+                        .collect();
+
+                    let ack = |include_log: bool| {
+                        let mut payload = Encoder::new();
+                        for action in &actions {
+                            if include_log || !is_log(action) {
+                                payload.action(action);
+                            }
+                        }
+
+                        Frame {
+                            frame_type: FrameType::Ack,
+                            flags: spop::FLAG_FIN,
+                            stream_id: frame.stream_id,
+                            frame_id: frame.frame_id,
+                            payload: payload.finish(),
+                        }
+                    };
+
+                    // The logs are only informational, so they are dropped rather than the whole
+                    // verdict if the frame would be too big for HAProxy.
+                    let fits = |ack: &Frame| ack.encoded_len() <= max_frame_size as usize;
+                    let ack = Some(ack(true))
+                        .filter(fits)
+                        .or_else(|| Some(ack(false)).filter(fits));
+
+                    match ack {
+                        Some(ack) => spop::write_frame(&mut writer, &ack)?,
+                        None => {
+                            return disconnect(
+                                &mut writer,
+                                status::FRAME_TOO_BIG,
+                                "Verdict is too big for a frame",
+                            )
+                        }
+                    }
+                }
+                // Unknown frames must be ignored.
+                (FrameType::Unknown(_), _) => {}
+                _ => {
+                    return disconnect(&mut writer, status::INVALID_FRAME, "Unexpected frame");
+                }
+            }
+        }
+    }
+
+    /// Returns the actions that report the verdict for `message`.
+    pub fn actions(&self, message: &Message) -> Vec<Action> {
+        let set_var = |name: &str, value: Data| Action::SetVar {
+            scope: Scope::Transaction,
+            name: name.to_string(),
+            value,
+        };
+
+        match self.inspect(message) {
+            Ok(Some(intervention)) => {
+                let mut actions = vec![set_var("status", Data::Int32(intervention.status()))];
+
+                if let Some(id) = intervention
+                    .rule_match()
+                    .and_then(|rule_match| rule_match.id)
+                {
+                    actions.push(set_var("rule_id", Data::String(id.to_string().into())));
+                }
+
+                if let Some(log) = intervention.log() {
+                    actions.push(set_var("log", Data::String(log.into())));
+                }
+
+                if let Some(url) = intervention.url() {
+                    actions.push(set_var("url", Data::String(url.into())));
+                }
+
+                actions
+            }
+            Ok(None) => vec![set_var("status", Data::Int32(0))],
+            Err(error) => {
+                (self.error_handler)(&Error::Inspect {
+                    message: message.name.clone(),
+                    error,
+                });
+                vec![set_var("status", Data::Int32(-1))]
+            }
+        }
+    }
+
+    /// Runs the request phases and the logging phase for `message`, returning the disruptive
+    /// intervention, if any.
+    fn inspect(&self, message: &Message) -> ModSecurityResult<Option<Intervention>> {
+        let mut transaction = self
+            .ms
+            .transaction_builder()
+            .with_rules(&self.rules)
+            .build()?;
+
+        let result = (|| {
+            let str_arg = |name: &str| {
+                message
+                    .arg(name)
+                    .and_then(Data::as_bytes)
+                    .map(String::from_utf8_lossy)
+            };
+            let disruption = |transaction: &mut modsecurity::Transaction| {
+                transaction
+                    .intervention()
+                    .filter(|intervention| intervention.disruptive())
+            };
+
+            let src = message.arg("src").and_then(ip).unwrap_or(UNSPECIFIED);
+            let dst = message.arg("dst").and_then(ip).unwrap_or(UNSPECIFIED);
+            let port = |name: &str| {
+                message
+                    .arg(name)
+                    .and_then(Data::as_i64)
+                    .and_then(|port| i32::try_from(port).ok())
+                    .unwrap_or(0)
+            };
+
+            transaction.process_connection(
+                &src.to_string(),
+                port("src_port"),
+                &dst.to_string(),
+                port("dst_port"),
+            )?;
+            if let Some(intervention) = disruption(&mut transaction) {
+                return Ok(Some(intervention));
+            }
+
+            let method = str_arg("method").unwrap_or_default();
+            let mut uri = str_arg("path").unwrap_or_default().into_owned();
+            if let Some(query) = str_arg("query").filter(|query| !query.is_empty()) {
+                uri.push('?');
+                uri.push_str(&query);
+            }
+            let version = str_arg("version").unwrap_or_else(|| "1.1".into());
+
+            transaction.process_uri(&uri, &method, &version)?;
+            if let Some(intervention) = disruption(&mut transaction) {
+                return Ok(Some(intervention));
+            }
+
+            match message.arg("headers") {
+                Some(Data::Binary(headers)) => {
+                    for (key, value) in binary_headers(headers) {
+                        transaction.add_request_header_bytes(key, value)?;
+                    }
+                }
+                Some(Data::String(headers)) => {
+                    for (key, value) in text_headers(headers) {
+                        transaction.add_request_header_bytes(key, value)?;
+                    }
+                }
+                _ => {}
+            }
+            transaction.process_request_headers()?;
+            if let Some(intervention) = disruption(&mut transaction) {
+                return Ok(Some(intervention));
+            }
+
+            if let Some(body) = message.arg("body").and_then(Data::as_bytes) {
+                transaction.append_request_body(body)?;
+            }
+            transaction.process_request_body()?;
+
+            Ok(disruption(&mut transaction))
+        })();
+
+        transaction.process_logging()?;
+
+        result
+    }
+}
+
+const UNSPECIFIED: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+
+fn ip(data: &Data) -> Option<IpAddr> {
+    match data {
+        Data::Ipv4(addr) => Some((*addr).into()),
+        Data::Ipv6(addr) => Some((*addr).into()),
+        _ => None,
+    }
+}
+
+/// Sends an `AGENT-DISCONNECT` frame.
+fn disconnect<W: io::Write>(writer: &mut W, status_code: u32, message: &str) -> io::Result<()> {
+    let mut payload = Encoder::new();
+    payload
+        .kv("status-code", &Data::Uint32(status_code))
+        .kv("message", &Data::String(message.into()));
+
+    spop::write_frame(
+        writer,
+        &Frame::new(FrameType::AgentDisconnect, payload.finish()),
+    )
+}
+
+/// Parses headers in the format of the `req.hdrs_bin` sample fetch.
+///
+/// Each header is a length-prefixed name followed by a length-prefixed value, and the list ends
+/// with an empty name and value.
+fn binary_headers(headers: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut decoder = Decoder::new(headers);
+    let mut parsed = Vec::new();
+
+    // A truncated list is still inspected up to the point where it was cut off.
+    while let (Ok(key), Ok(value)) = (decoder.bytes(), decoder.bytes()) {
+        if key.is_empty() && value.is_empty() {
+            break;
+        }
+        parsed.push((key, value));
+    }
+
+    parsed
+}
+
+/// Parses headers in the format of the `req.hdrs` sample fetch.
+fn text_headers(headers: &[u8]) -> Vec<(&[u8], &[u8])> {
+    headers
+        .split(|byte| *byte == b'\n')
+        .filter_map(|line| {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let colon = line.iter().position(|byte| *byte == b':')?;
+            let (key, value) = line.split_at(colon);
+
+            Some((key, value[1..].trim_ascii()))
+        })
+        .collect()
+}
+
+/// Returns `true` if `action` sets the `log` variable.
+fn is_log(action: &Action) -> bool {
+    matches!(action, Action::SetVar { name, .. } if name == "log")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    /// A scripted HAProxy connected to an agent over a local TCP socket.
+    struct Haproxy {
+        stream: TcpStream,
+    }
+
+    impl Haproxy {
+        fn connect(plain_rules: &str) -> Self {
+            let mut rules = Rules::new();
+            rules.add_plain(plain_rules).unwrap();
+
+            let agent = Arc::new(Agent::new(ModSecurity::default(), rules));
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+
+            thread::spawn(move || agent.serve(listener));
+
+            Self {
+                stream: TcpStream::connect(addr).unwrap(),
+            }
+        }
+
+        fn send(&mut self, frame: &Frame) {
+            spop::write_frame(&mut self.stream, frame).unwrap();
+        }
+
+        fn receive(&mut self) -> Frame {
+            spop::read_frame(&mut self.stream, MAX_FRAME_SIZE).unwrap()
+        }
+
+        fn hello(&mut self, healthcheck: bool) -> Vec<(String, Data)> {
+            self.hello_with_max_frame_size(healthcheck, 65536)
+        }
+
+        fn hello_with_max_frame_size(
+            &mut self,
+            healthcheck: bool,
+            max_frame_size: u32,
+        ) -> Vec<(String, Data)> {
+            let mut payload = Encoder::new();
+            payload
+                .kv("supported-versions", &Data::String(b"2.0".to_vec()))
+                .kv("max-frame-size", &Data::Uint32(max_frame_size))
+                .kv("capabilities", &Data::String(b"pipelining,async".to_vec()));
+            if healthcheck {
+                payload.kv("healthcheck", &Data::Bool(true));
+            }
+
+            self.send(&Frame::new(FrameType::HaproxyHello, payload.finish()));
+
+            let frame = self.receive();
+            assert_eq!(frame.frame_type, FrameType::AgentHello);
+            Decoder::new(&frame.payload).kv_list().unwrap()
+        }
+
+        fn notify(&mut self, stream_id: u64, args: Vec<(&str, Data)>) -> Vec<(String, Data)> {
+            let mut payload = Encoder::new();
+            payload.message(&Message {
+                name: "check-request".to_string(),
+                args: args
+                    .into_iter()
+                    .map(|(name, value)| (name.to_string(), value))
+                    .collect(),
+            });
+
+            self.send(&Frame {
+                frame_type: FrameType::Notify,
+                flags: spop::FLAG_FIN,
+                stream_id,
+                frame_id: 1,
+                payload: payload.finish(),
+            });
+
+            let frame = self.receive();
+            assert_eq!(frame.frame_type, FrameType::Ack);
+            assert_eq!(frame.stream_id, stream_id);
+            assert_eq!(frame.frame_id, 1);
+
+            // Decode the `SET-VAR` actions into name-value pairs.
+            let mut decoder = Decoder::new(&frame.payload);
+            let mut vars = Vec::new();
+            while !decoder.is_empty() {
+                assert_eq!(decoder.u8().unwrap(), 1);
+                assert_eq!(decoder.u8().unwrap(), 3);
+                assert_eq!(decoder.u8().unwrap(), Scope::Transaction as u8);
+                vars.push((decoder.string().unwrap(), decoder.data().unwrap()));
+            }
+            vars
+        }
+
+        fn disconnect(&mut self) -> Vec<(String, Data)> {
+            let mut payload = Encoder::new();
+            payload
+                .kv("status-code", &Data::Uint32(status::NORMAL))
+                .kv("message", &Data::String(Vec::new()));
+
+            self.send(&Frame::new(FrameType::HaproxyDisconnect, payload.finish()));
+
+            let frame = self.receive();
+            assert_eq!(frame.frame_type, FrameType::AgentDisconnect);
+            Decoder::new(&frame.payload).kv_list().unwrap()
+        }
+    }
+
+    fn hdrs_bin(headers: &[(&str, &str)]) -> Data {
+        let mut encoder = Encoder::new();
+        for (key, value) in headers {
+            encoder.bytes(key.as_bytes()).bytes(value.as_bytes());
+        }
+        encoder.bytes(b"").bytes(b"");
+        Data::Binary(encoder.finish())
+    }
+
+    fn var<'a>(vars: &'a [(String, Data)], name: &str) -> Option<&'a Data> {
+        vars.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+
+    const ADMIN_RULES: &str = r#"
+        SecRuleEngine On
+
+        SecRule REQUEST_URI "@rx admin" "id:1,phase:1,log,deny,status:401"
+    "#;
+
+    #[test]
+    fn test_allowed() {
+        let mut haproxy = Haproxy::connect(ADMIN_RULES);
+
+        let hello = haproxy.hello(false);
+        assert_eq!(
+            hello[0],
+            ("version".to_string(), Data::String(b"2.0".to_vec()))
+        );
+        assert_eq!(
+            hello[1],
+            ("max-frame-size".to_string(), Data::Uint32(MAX_FRAME_SIZE))
+        );
+
+        let vars = haproxy.notify(
+            1,
+            vec![
+                ("method", Data::String(b"GET".to_vec())),
+                ("path", Data::String(b"/index.html".to_vec())),
+                ("headers", hdrs_bin(&[("host", "example.com")])),
+                ("src", Data::Ipv4(Ipv4Addr::new(127, 0, 0, 1))),
+                ("src_port", Data::Int32(12345)),
+            ],
+        );
+
+        assert_eq!(vars, vec![("status".to_string(), Data::Int32(0))]);
+
+        let disconnect = haproxy.disconnect();
+        assert_eq!(disconnect[0].1, Data::Uint32(status::NORMAL));
+    }
+
+    #[test]
+    fn test_intervention() {
+        let mut haproxy = Haproxy::connect(ADMIN_RULES);
+        haproxy.hello(false);
+
+        let vars = haproxy.notify(
+            7,
+            vec![
+                ("method", Data::String(b"GET".to_vec())),
+                ("path", Data::String(b"/admin".to_vec())),
+            ],
+        );
+
+        assert_eq!(var(&vars, "status"), Some(&Data::Int32(401)));
+        assert_eq!(var(&vars, "rule_id"), Some(&Data::String(b"1".to_vec())));
+        assert!(var(&vars, "log").is_some());
+    }
+
+    #[test]
+    fn test_body_intervention() {
+        let mut haproxy = Haproxy::connect(
+            r#"
+            SecRuleEngine On
+
+            SecRequestBodyAccess On
+
+            SecRule REQUEST_BODY "@rx attack" "id:2,phase:2,deny,status:403"
+        "#,
+        );
+        haproxy.hello(false);
+
+        let vars = haproxy.notify(
+            1,
+            vec![
+                ("method", Data::String(b"POST".to_vec())),
+                ("path", Data::String(b"/".to_vec())),
+                (
+                    "headers",
+                    hdrs_bin(&[("content-type", "text/plain"), ("content-length", "9")]),
+                ),
+                ("body", Data::Binary(b"an attack".to_vec())),
+            ],
+        );
+
+        assert_eq!(var(&vars, "status"), Some(&Data::Int32(403)));
+    }
+
+    #[test]
+    fn test_pipelined_notify() {
+        let mut haproxy = Haproxy::connect(ADMIN_RULES);
+        haproxy.hello(false);
+
+        for stream_id in 1..=3 {
+            let vars = haproxy.notify(
+                stream_id,
+                vec![
+                    ("method", Data::String(b"GET".to_vec())),
+                    ("path", Data::String(b"/".to_vec())),
+                ],
+            );
+
+            assert_eq!(var(&vars, "status"), Some(&Data::Int32(0)));
+        }
+    }
+
+    #[test]
+    fn test_healthcheck() {
+        let mut haproxy = Haproxy::connect(ADMIN_RULES);
+        haproxy.hello(true);
+
+        // The agent closes the connection after a health check.
+        let mut buf = [0; 1];
+        assert_eq!(io::Read::read(&mut haproxy.stream, &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_version_mismatch() {
+        let mut haproxy = Haproxy::connect(ADMIN_RULES);
+
+        let mut payload = Encoder::new();
+        payload
+            .kv("supported-versions", &Data::String(b"1.0".to_vec()))
+            .kv("max-frame-size", &Data::Uint32(16384))
+            .kv("capabilities", &Data::String(Vec::new()));
+        haproxy.send(&Frame::new(FrameType::HaproxyHello, payload.finish()));
+
+        let frame = haproxy.receive();
+        assert_eq!(frame.frame_type, FrameType::AgentDisconnect);
+
+        let payload = Decoder::new(&frame.payload).kv_list().unwrap();
+        assert_eq!(payload[0].1, Data::Uint32(status::VERSION_MISMATCH));
+    }
+
+    #[test]
+    fn test_notify_before_hello() {
+        let mut haproxy = Haproxy::connect(ADMIN_RULES);
+
+        haproxy.send(&Frame::new(FrameType::Notify, Vec::new()));
+
+        let frame = haproxy.receive();
+        assert_eq!(frame.frame_type, FrameType::AgentDisconnect);
+    }
+
+    #[test]
+    fn test_frame_too_big() {
+        let mut haproxy = Haproxy::connect(ADMIN_RULES);
+        haproxy.hello(false);
+
+        let len = MAX_FRAME_SIZE + 1;
+        haproxy.stream.write_all(&len.to_be_bytes()).unwrap();
+
+        let frame = haproxy.receive();
+        assert_eq!(frame.frame_type, FrameType::AgentDisconnect);
+
+        let payload = Decoder::new(&frame.payload).kv_list().unwrap();
+        assert_eq!(payload[0].1, Data::Uint32(status::FRAME_TOO_BIG));
+    }
+
+    #[test]
+    fn test_log_too_big() {
+        let mut haproxy = Haproxy::connect(ADMIN_RULES);
+        haproxy.hello_with_max_frame_size(false, 64);
+
+        let vars = haproxy.notify(
+            1,
+            vec![
+                ("method", Data::String(b"GET".to_vec())),
+                ("path", Data::String(b"/admin".to_vec())),
+            ],
+        );
+
+        // The log does not fit in the frame, but the rest of the verdict does.
+        assert_eq!(var(&vars, "status"), Some(&Data::Int32(401)));
+        assert_eq!(var(&vars, "rule_id"), Some(&Data::String(b"1".to_vec())));
+        assert_eq!(var(&vars, "log"), None);
+    }
+
+    #[test]
+    fn test_invalid_frame() {
+        let mut haproxy = Haproxy::connect(ADMIN_RULES);
+        haproxy.hello(false);
+
+        // A frame that ends before its header.
+        haproxy.stream.write_all(&[0, 0, 0, 1, 3]).unwrap();
+
+        let frame = haproxy.receive();
+        assert_eq!(frame.frame_type, FrameType::AgentDisconnect);
+
+        let payload = Decoder::new(&frame.payload).kv_list().unwrap();
+        assert_eq!(payload[0].1, Data::Uint32(status::INVALID_FRAME));
+    }
+
+    #[test]
+    fn test_error_handler() {
+        let errors = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let agent = Agent::new(ModSecurity::default(), Rules::new()).with_error_handler({
+            let errors = Arc::clone(&errors);
+            move |err| errors.lock().unwrap().push(err.to_string())
+        });
+
+        let actions = agent.actions(&Message {
+            name: "check-request".to_string(),
+            args: vec![
+                ("method".to_string(), Data::String(b"GET".to_vec())),
+                ("path".to_string(), Data::String(b"/\0".to_vec())),
+            ],
+        });
+
+        assert_eq!(
+            actions,
+            vec![Action::SetVar {
+                scope: Scope::Transaction,
+                name: "status".to_string(),
+                value: Data::Int32(-1),
+            }]
+        );
+
+        let errors = errors.lock().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("Error inspecting message check-request: "));
+    }
+
+    #[test]
+    fn test_binary_headers() {
+        let Data::Binary(headers) = hdrs_bin(&[("host", "example.com"), ("accept", "*/*")]) else {
+            unreachable!()
+        };
+
+        assert_eq!(
+            binary_headers(&headers),
+            vec![
+                (&b"host"[..], &b"example.com"[..]),
+                (&b"accept"[..], &b"*/*"[..]),
+            ]
+        );
+        assert!(binary_headers(&headers[..8]).is_empty());
+    }
+
+    #[test]
+    fn test_text_headers() {
+        assert_eq!(
+            text_headers(b"host: example.com\r\naccept:*/*\r\n\r\n"),
+            vec![
+                (&b"host"[..], &b"example.com"[..]),
+                (&b"accept"[..], &b"*/*"[..]),
+            ]
+        );
+    }
+}
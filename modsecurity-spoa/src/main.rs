@@ -0,0 +1,86 @@
+//! Runs a HAProxy SPOE agent backed by ModSecurity.
+//!
+//! ```text
+//! modsecurity-spoa [--listen <ADDR>] --rules <FILE>...
+//! ```
+
+use std::{
+    net::{SocketAddr, TcpListener},
+    process,
+    sync::Arc,
+};
+
+use modsecurity::{ModSecurity, Rules};
+use modsecurity_spoa::Agent;
+
+const USAGE: &str = "Usage: modsecurity-spoa [--listen <ADDR>] --rules <FILE>...
+
+Options:
+    --listen <ADDR>   Address to listen on [default: 127.0.0.1:12345]
+    --rules <FILE>    Rules file to load, can be given more than once";
+
+struct Args {
+    listen: SocketAddr,
+    rules: Vec<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args {
+        listen: SocketAddr::from(([127, 0, 0, 1], 12345)),
+        rules: Vec::new(),
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", arg))
+        };
+
+        match arg.as_str() {
+            "--listen" => {
+                parsed.listen = value()?
+                    .parse()
+                    .map_err(|err| format!("Invalid address: {}", err))?
+            }
+            "--rules" => parsed.rules.push(value()?),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+
+    if parsed.rules.is_empty() {
+        return Err("At least one rules file is required".to_string());
+    }
+
+    Ok(parsed)
+}
+
+fn main() {
+    let args = parse_args(std::env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{}\n\n{}", err, USAGE);
+        process::exit(2);
+    });
+
+    let mut rules = Rules::new();
+    for file in &args.rules {
+        if let Err(err) = rules.add_file(file) {
+            eprintln!("Failed to load {}: {}", file, err);
+            process::exit(1);
+        }
+    }
+
+    let listener = TcpListener::bind(args.listen).unwrap_or_else(|err| {
+        eprintln!("Failed to listen on {}: {}", args.listen, err);
+        process::exit(1);
+    });
+
+    eprintln!("Listening on {}", args.listen);
+
+    let agent = Arc::new(
+        Agent::new(ModSecurity::default(), rules).with_error_handler(|err| eprintln!("{}", err)),
+    );
+
+    if let Err(err) = agent.serve(listener) {
+        eprintln!("Server error: {}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,657 @@
+//! Encoding and decoding of the frames of the Stream Processing Offload Protocol (SPOP).
+//!
+//! See the [SPOE documentation](https://github.com/haproxy/haproxy/blob/master/doc/SPOE.txt) for
+//! the details of the protocol. Fragmented payloads are not supported.
+
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Read, Write},
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+/// Set on the last frame of a payload.
+pub const FLAG_FIN: u32 = 0x01;
+
+/// The SPOP version implemented by this module.
+pub const VERSION: &str = "2.0";
+
+/// The type of a frame.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FrameType {
+    /// Sent by HAProxy when it opens a connection.
+    HaproxyHello,
+    /// Sent by HAProxy before it closes a connection.
+    HaproxyDisconnect,
+    /// Sent by HAProxy to pass messages to the agent.
+    Notify,
+    /// Sent by the agent in response to a [`FrameType::HaproxyHello`].
+    AgentHello,
+    /// Sent by the agent before it closes a connection.
+    AgentDisconnect,
+    /// Sent by the agent in response to a [`FrameType::Notify`].
+    Ack,
+    /// Any other frame type, which must be ignored.
+    Unknown(u8),
+}
+
+impl From<u8> for FrameType {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => FrameType::HaproxyHello,
+            2 => FrameType::HaproxyDisconnect,
+            3 => FrameType::Notify,
+            101 => FrameType::AgentHello,
+            102 => FrameType::AgentDisconnect,
+            103 => FrameType::Ack,
+            other => FrameType::Unknown(other),
+        }
+    }
+}
+
+impl From<FrameType> for u8 {
+    fn from(value: FrameType) -> Self {
+        match value {
+            FrameType::HaproxyHello => 1,
+            FrameType::HaproxyDisconnect => 2,
+            FrameType::Notify => 3,
+            FrameType::AgentHello => 101,
+            FrameType::AgentDisconnect => 102,
+            FrameType::Ack => 103,
+            FrameType::Unknown(other) => other,
+        }
+    }
+}
+
+/// The status codes sent in disconnect frames.
+pub mod status {
+    /// Normal disconnect.
+    pub const NORMAL: u32 = 0;
+    /// A frame was larger than the maximum frame size.
+    pub const FRAME_TOO_BIG: u32 = 3;
+    /// A frame could not be decoded.
+    pub const INVALID_FRAME: u32 = 4;
+    /// None of the versions supported by HAProxy are supported.
+    pub const VERSION_MISMATCH: u32 = 5;
+    /// A fragmented payload was received.
+    pub const FRAGMENTATION_NOT_SUPPORTED: u32 = 9;
+}
+
+/// A single SPOP frame.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Frame {
+    /// The type of the frame.
+    pub frame_type: FrameType,
+    /// The frame flags, see [`FLAG_FIN`].
+    pub flags: u32,
+    /// The ID of the stream the frame belongs to, or `0` for connection-level frames.
+    pub stream_id: u64,
+    /// The ID of the frame within its stream, or `0` for connection-level frames.
+    pub frame_id: u64,
+    /// The encoded payload of the frame.
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Creates a connection-level frame, with the FIN flag set.
+    pub fn new(frame_type: FrameType, payload: Vec<u8>) -> Self {
+        Self {
+            frame_type,
+            flags: FLAG_FIN,
+            stream_id: 0,
+            frame_id: 0,
+            payload,
+        }
+    }
+
+    /// Returns the size of the frame once encoded, excluding its length prefix. This is the size
+    /// that is compared to the maximum frame size.
+    pub fn encoded_len(&self) -> usize {
+        self.header().len() + self.payload.len()
+    }
+
+    fn header(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        encoder
+            .u8(self.frame_type.into())
+            .u32(self.flags)
+            .varint(self.stream_id)
+            .varint(self.frame_id);
+
+        encoder.finish()
+    }
+}
+
+/// The error returned by [`read_frame()`] when a frame is larger than the maximum frame size,
+/// wrapped in an [`io::Error`] of kind [`io::ErrorKind::InvalidData`].
+#[derive(Debug)]
+pub struct FrameTooBig;
+
+impl fmt::Display for FrameTooBig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Frame is too big")
+    }
+}
+
+impl Error for FrameTooBig {}
+
+/// A typed value.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Data {
+    /// No value.
+    Null,
+    /// A boolean.
+    Bool(bool),
+    /// A signed 32-bit integer.
+    Int32(i32),
+    /// An unsigned 32-bit integer.
+    Uint32(u32),
+    /// A signed 64-bit integer.
+    Int64(i64),
+    /// An unsigned 64-bit integer.
+    Uint64(u64),
+    /// An IPv4 address.
+    Ipv4(Ipv4Addr),
+    /// An IPv6 address.
+    Ipv6(Ipv6Addr),
+    /// A string. HAProxy does not guarantee that strings are valid UTF-8.
+    String(Vec<u8>),
+    /// Binary data.
+    Binary(Vec<u8>),
+}
+
+impl Data {
+    /// Returns the value as bytes, if it is a string or binary data.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Data::String(value) | Data::Binary(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as an integer, if it is one that fits in an `i64`.
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Data::Int32(value) => Some(value.into()),
+            Data::Uint32(value) => Some(value.into()),
+            Data::Int64(value) => Some(value),
+            Data::Uint64(value) => i64::try_from(value).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// A message sent in a [`FrameType::Notify`] frame.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Message {
+    /// The name of the message, as configured in the SPOE configuration.
+    pub name: String,
+    /// The arguments of the message.
+    pub args: Vec<(String, Data)>,
+}
+
+impl Message {
+    /// Returns the value of the argument `name`, if any.
+    pub fn arg(&self, name: &str) -> Option<&Data> {
+        self.args
+            .iter()
+            .find(|(arg, _)| arg == name)
+            .map(|(_, value)| value)
+    }
+}
+
+/// The scope of a variable set by an [`Action`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Scope {
+    /// `proc.*` variables.
+    Process = 0,
+    /// `sess.*` variables.
+    Session = 1,
+    /// `txn.*` variables.
+    Transaction = 2,
+    /// `req.*` variables.
+    Request = 3,
+    /// `res.*` variables.
+    Response = 4,
+}
+
+/// An action sent in a [`FrameType::Ack`] frame.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Action {
+    /// Sets a variable.
+    SetVar {
+        /// The scope of the variable.
+        scope: Scope,
+        /// The name of the variable, without the scope or the SPOE prefix.
+        name: String,
+        /// The value of the variable.
+        value: Data,
+    },
+    /// Unsets a variable.
+    UnsetVar {
+        /// The scope of the variable.
+        scope: Scope,
+        /// The name of the variable, without the scope or the SPOE prefix.
+        name: String,
+    },
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reads SPOP values from a buffer.
+pub struct Decoder<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    /// Creates a decoder over `buf`.
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    /// Returns `true` if the whole buffer has been read.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.buf.len() < len {
+            return Err(invalid_data("Unexpected end of frame"));
+        }
+
+        let (taken, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(taken)
+    }
+
+    /// Reads a single byte.
+    pub fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Reads a big-endian 32-bit integer.
+    pub fn u32(&mut self) -> io::Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads a variable-length integer.
+    pub fn varint(&mut self) -> io::Result<u64> {
+        let mut value = u64::from(self.u8()?);
+
+        if value < 240 {
+            return Ok(value);
+        }
+
+        let mut shift = 4;
+        loop {
+            if shift > 60 {
+                return Err(invalid_data("Variable-length integer is too large"));
+            }
+
+            let byte = self.u8()?;
+            value = value.wrapping_add(u64::from(byte) << shift);
+            shift += 7;
+
+            if byte < 128 {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// Reads a length-prefixed byte string.
+    pub fn bytes(&mut self) -> io::Result<&'a [u8]> {
+        let len = usize::try_from(self.varint()?).map_err(|_| invalid_data("Length overflow"))?;
+        self.take(len)
+    }
+
+    /// Reads a length-prefixed string, replacing invalid UTF-8.
+    pub fn string(&mut self) -> io::Result<String> {
+        Ok(String::from_utf8_lossy(self.bytes()?).into_owned())
+    }
+
+    /// Reads a typed value.
+    pub fn data(&mut self) -> io::Result<Data> {
+        let byte = self.u8()?;
+        let flags = byte >> 4;
+
+        Ok(match byte & 0x0f {
+            0 => Data::Null,
+            1 => Data::Bool(flags & 0x01 != 0),
+            2 => Data::Int32(self.varint()? as i32),
+            3 => Data::Uint32(self.varint()? as u32),
+            4 => Data::Int64(self.varint()? as i64),
+            5 => Data::Uint64(self.varint()?),
+            6 => {
+                let bytes = self.take(4)?;
+                Data::Ipv4(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]))
+            }
+            7 => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(self.take(16)?);
+                Data::Ipv6(Ipv6Addr::from(octets))
+            }
+            8 => Data::String(self.bytes()?.to_vec()),
+            9 => Data::Binary(self.bytes()?.to_vec()),
+            _ => return Err(invalid_data("Unknown data type")),
+        })
+    }
+
+    /// Reads key-value pairs until the end of the buffer.
+    pub fn kv_list(&mut self) -> io::Result<Vec<(String, Data)>> {
+        let mut list = Vec::new();
+
+        while !self.is_empty() {
+            let name = self.string()?;
+            list.push((name, self.data()?));
+        }
+
+        Ok(list)
+    }
+
+    /// Reads the messages of a [`FrameType::Notify`] payload.
+    pub fn messages(&mut self) -> io::Result<Vec<Message>> {
+        let mut messages = Vec::new();
+
+        while !self.is_empty() {
+            let name = self.string()?;
+            let count = self.u8()?;
+
+            let args = (0..count)
+                .map(|_| Ok((self.string()?, self.data()?)))
+                .collect::<io::Result<_>>()?;
+
+            messages.push(Message { name, args });
+        }
+
+        Ok(messages)
+    }
+}
+
+/// Writes SPOP values to a buffer.
+#[derive(Default)]
+pub struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    /// Creates an empty encoder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the encoded bytes.
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+
+    /// Writes a single byte.
+    pub fn u8(&mut self, value: u8) -> &mut Self {
+        self.buf.push(value);
+        self
+    }
+
+    /// Writes a big-endian 32-bit integer.
+    pub fn u32(&mut self, value: u32) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    /// Writes a variable-length integer.
+    pub fn varint(&mut self, mut value: u64) -> &mut Self {
+        if value < 240 {
+            return self.u8(value as u8);
+        }
+
+        self.buf.push(value as u8 | 240);
+        value = (value - 240) >> 4;
+
+        while value >= 128 {
+            self.buf.push(value as u8 | 128);
+            value = (value - 128) >> 7;
+        }
+
+        self.u8(value as u8)
+    }
+
+    /// Writes a length-prefixed byte string.
+    pub fn bytes(&mut self, value: &[u8]) -> &mut Self {
+        self.varint(value.len() as u64);
+        self.buf.extend_from_slice(value);
+        self
+    }
+
+    /// Writes a typed value.
+    pub fn data(&mut self, value: &Data) -> &mut Self {
+        match value {
+            Data::Null => self.u8(0),
+            Data::Bool(value) => self.u8(1 | if *value { 0x10 } else { 0 }),
+            Data::Int32(value) => self.u8(2).varint(*value as u64),
+            Data::Uint32(value) => self.u8(3).varint((*value).into()),
+            Data::Int64(value) => self.u8(4).varint(*value as u64),
+            Data::Uint64(value) => self.u8(5).varint(*value),
+            Data::Ipv4(value) => {
+                self.u8(6);
+                self.buf.extend_from_slice(&value.octets());
+                self
+            }
+            Data::Ipv6(value) => {
+                self.u8(7);
+                self.buf.extend_from_slice(&value.octets());
+                self
+            }
+            Data::String(value) => self.u8(8).bytes(value),
+            Data::Binary(value) => self.u8(9).bytes(value),
+        }
+    }
+
+    /// Writes a key-value pair.
+    pub fn kv(&mut self, name: &str, value: &Data) -> &mut Self {
+        self.bytes(name.as_bytes()).data(value)
+    }
+
+    /// Writes a message, as found in a [`FrameType::Notify`] payload.
+    pub fn message(&mut self, message: &Message) -> &mut Self {
+        self.bytes(message.name.as_bytes())
+            .u8(message.args.len() as u8);
+
+        for (name, value) in &message.args {
+            self.kv(name, value);
+        }
+
+        self
+    }
+
+    /// Writes an action, as found in a [`FrameType::Ack`] payload.
+    pub fn action(&mut self, action: &Action) -> &mut Self {
+        match action {
+            Action::SetVar { scope, name, value } => self
+                .u8(1)
+                .u8(3)
+                .u8(*scope as u8)
+                .bytes(name.as_bytes())
+                .data(value),
+            Action::UnsetVar { scope, name } => {
+                self.u8(2).u8(2).u8(*scope as u8).bytes(name.as_bytes())
+            }
+        }
+    }
+}
+
+/// Reads a frame from `reader`.
+///
+/// Frames larger than `max_frame_size` are rejected with an [`io::ErrorKind::InvalidData`]
+/// error.
+pub fn read_frame<R: Read>(reader: &mut R, max_frame_size: u32) -> io::Result<Frame> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len);
+
+    if len > max_frame_size {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, FrameTooBig));
+    }
+
+    let mut frame = vec![0; len as usize];
+    reader.read_exact(&mut frame)?;
+
+    let mut decoder = Decoder::new(&frame);
+    let frame_type = decoder.u8()?.into();
+    let flags = decoder.u32()?;
+    let stream_id = decoder.varint()?;
+    let frame_id = decoder.varint()?;
+
+    Ok(Frame {
+        frame_type,
+        flags,
+        stream_id,
+        frame_id,
+        payload: decoder.buf.to_vec(),
+    })
+}
+
+/// Writes `frame` to `writer`.
+pub fn write_frame<W: Write>(writer: &mut W, frame: &Frame) -> io::Result<()> {
+    let mut buf = frame.header();
+    buf.extend_from_slice(&frame.payload);
+
+    writer.write_all(&(buf.len() as u32).to_be_bytes())?;
+    writer.write_all(&buf)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_varint() {
+        let cases: &[(u64, &[u8])] = &[
+            (0, &[0x00]),
+            (239, &[0xef]),
+            (240, &[0xf0, 0x00]),
+            (2287, &[0xff, 0x7f]),
+            (2288, &[0xf0, 0x80, 0x00]),
+            (264431, &[0xff, 0xff, 0x7f]),
+            (264432, &[0xf0, 0x80, 0x80, 0x00]),
+        ];
+
+        for (value, encoded) in cases {
+            let mut encoder = Encoder::new();
+            encoder.varint(*value);
+            assert_eq!(encoder.finish(), *encoded, "encoding {}", value);
+
+            let mut decoder = Decoder::new(encoded);
+            assert_eq!(decoder.varint().unwrap(), *value, "decoding {}", value);
+            assert!(decoder.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_varint_roundtrip() {
+        for value in (0..64).map(|shift| 1u64 << shift).chain([u64::MAX]) {
+            for value in [value - 1, value, value.saturating_add(1)] {
+                let mut encoder = Encoder::new();
+                encoder.varint(value);
+                let encoded = encoder.finish();
+
+                assert_eq!(Decoder::new(&encoded).varint().unwrap(), value);
+            }
+        }
+    }
+
+    #[test]
+    fn test_data_roundtrip() {
+        let values = [
+            Data::Null,
+            Data::Bool(true),
+            Data::Bool(false),
+            Data::Int32(-42),
+            Data::Uint32(u32::MAX),
+            Data::Int64(i64::MIN),
+            Data::Uint64(u64::MAX),
+            Data::Ipv4(Ipv4Addr::new(124, 123, 122, 121)),
+            Data::Ipv6(Ipv6Addr::LOCALHOST),
+            Data::String(b"hello".to_vec()),
+            Data::Binary(vec![0, 159, 146, 150]),
+        ];
+
+        for value in &values {
+            let mut encoder = Encoder::new();
+            encoder.data(value);
+            let encoded = encoder.finish();
+
+            let mut decoder = Decoder::new(&encoded);
+            assert_eq!(decoder.data().unwrap(), *value);
+            assert!(decoder.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_messages() {
+        let message = Message {
+            name: "check-request".to_string(),
+            args: vec![
+                ("method".to_string(), Data::String(b"GET".to_vec())),
+                ("src_port".to_string(), Data::Int32(12345)),
+            ],
+        };
+
+        let mut encoder = Encoder::new();
+        encoder.message(&message).message(&message);
+        let encoded = encoder.finish();
+
+        let messages = Decoder::new(&encoded).messages().unwrap();
+
+        assert_eq!(messages, vec![message.clone(), message]);
+        assert_eq!(messages[0].arg("src_port"), Some(&Data::Int32(12345)));
+        assert_eq!(messages[0].arg("path"), None);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut encoder = Encoder::new();
+        encoder.data(&Data::String(b"hello".to_vec()));
+        let encoded = encoder.finish();
+
+        for len in 0..encoded.len() {
+            assert!(Decoder::new(&encoded[..len]).data().is_err());
+        }
+    }
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let frame = Frame {
+            frame_type: FrameType::Ack,
+            flags: FLAG_FIN,
+            stream_id: 300,
+            frame_id: 7,
+            payload: b"payload".to_vec(),
+        };
+
+        let mut buf = Vec::new();
+        write_frame(&mut buf, &frame).unwrap();
+
+        assert_eq!(&buf[4..9], &[103, 0, 0, 0, 1]);
+        assert_eq!(frame.encoded_len(), buf.len() - 4);
+        assert_eq!(read_frame(&mut buf.as_slice(), 1024).unwrap(), frame);
+    }
+
+    #[test]
+    fn test_frame_too_big() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, &Frame::new(FrameType::Ack, vec![0; 64])).unwrap();
+
+        let err = read_frame(&mut buf.as_slice(), 32).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.get_ref().unwrap().is::<FrameTooBig>());
+
+        // Other invalid frames are not reported as too big.
+        let err = read_frame(&mut [0, 0, 0, 1, 103].as_slice(), 32).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(!err.get_ref().is_some_and(|err| err.is::<FrameTooBig>()));
+    }
+}
@@ -0,0 +1,758 @@
+//! Middleware that enforces ModSecurity rules on [`actix-web`](https://docs.rs/actix-web)
+//! services.
+//!
+//! This module is only available with the `actix` feature enabled.
+//!
+//! [`ModSecurityMiddleware`] runs the request phases of a transaction before calling the wrapped
+//! service, and the response phases on the response it returns. If a phase raises a disruptive
+//! intervention, a response is built from it instead.
+//!
+//! The connection, URI and request headers phases run before the request body is read, so they
+//! can reject a request without receiving its body. The request body is then buffered up to a
+//! limit, set with [`ModSecurityMiddleware::with_request_body_limit()`], so that it can be
+//! inspected before the wrapped service is called. The
+//! [`PayloadConfig`](actix_web::web::PayloadConfig) of the app does not apply to this, only to
+//! the extractors of the wrapped service.
+//!
+//! Response body inspection is disabled by default: response bodies are streamed through and
+//! the response body phase is skipped, so rules on `RESPONSE_BODY` never match, even with
+//! `SecResponseBodyAccess On`. Once enabled, response bodies are read up to a limit for
+//! inspection first. See [`ModSecurityMiddleware::with_response_body_inspection()`].
+//!
+//! ## Examples
+//!
+//! ```
+//! use std::sync::Arc;
+//!
+//! use actix_web::{test, web, App};
+//! use modsecurity::{actix::ModSecurityMiddleware, ModSecurity, Rules};
+//!
+//! # actix_web::rt::System::new().block_on(async {
+//! let ms = Arc::new(ModSecurity::default());
+//!
+//! let mut rules = Rules::new();
+//! rules.add_plain(r#"
+//!     SecRuleEngine On
+//!
+//!     SecRule REQUEST_URI "@rx admin" "id:1,phase:1,deny,status:401"
+//! "#).expect("Failed to add rules");
+//!
+//! let app = test::init_service(
+//!     App::new()
+//!         .wrap(ModSecurityMiddleware::new(ms, Arc::new(rules)))
+//!         .route("/admin", web::get().to(|| async { "Hello" })),
+//! )
+//! .await;
+//!
+//! let response = test::call_service(&app, test::TestRequest::get().uri("/admin").to_request()).await;
+//!
+//! assert_eq!(response.status(), 401);
+//! # });
+//! ```
+
+use std::{
+    collections::VecDeque,
+    future::{ready, Future, Ready},
+    net::{Ipv4Addr, SocketAddr},
+    pin::Pin,
+    rc::Rc,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use actix_web::{
+    body::{self, BodyLimitExceeded, BodySize, BodyStream, BoxBody, MessageBody},
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    error::{ErrorBadGateway, ErrorInternalServerError},
+    http::{header, StatusCode, Version},
+    web::Bytes,
+    Error, HttpRequest, HttpResponse,
+};
+
+use crate::{
+    http::{Disruption, DEFAULT_REQUEST_BODY_LIMIT, DEFAULT_RESPONSE_BODY_LIMIT},
+    intervention::Intervention,
+    msc::ModSecurity,
+    rules::Rules,
+    transaction::OwnedTransaction,
+    ModSecurityResult,
+};
+
+type InterventionResponse = Arc<dyn Fn(&Intervention) -> HttpResponse + Send + Sync + 'static>;
+
+/// A [`Transform`] that wraps services in a [`ModSecurityService`].
+#[derive(Clone)]
+pub struct ModSecurityMiddleware {
+    ms: Arc<ModSecurity>,
+    rules: Arc<Rules>,
+    intervention_response: InterventionResponse,
+    request_body_limit: usize,
+    response_body_limit: usize,
+    inspect_response_body: bool,
+}
+
+impl ModSecurityMiddleware {
+    /// Creates a new middleware that inspects traffic using `ms` and `rules`.
+    ///
+    /// Response bodies are not inspected unless enabled with
+    /// [`ModSecurityMiddleware::with_response_body_inspection()`].
+    pub fn new(ms: Arc<ModSecurity>, rules: Arc<Rules>) -> Self {
+        Self {
+            ms,
+            rules,
+            intervention_response: Arc::new(intervention_response),
+            request_body_limit: DEFAULT_REQUEST_BODY_LIMIT,
+            response_body_limit: DEFAULT_RESPONSE_BODY_LIMIT,
+            inspect_response_body: false,
+        }
+    }
+
+    /// Overrides the response that is sent when a disruptive intervention is raised.
+    ///
+    /// By default, the response has the status of the intervention (or `403 Forbidden` if it is
+    /// not a valid status), a `Location` header if the intervention has a redirect URL, and an
+    /// empty body.
+    pub fn with_intervention_response<F>(mut self, response: F) -> Self
+    where
+        F: Fn(&Intervention) -> HttpResponse + Send + Sync + 'static,
+    {
+        self.intervention_response = Arc::new(response);
+        self
+    }
+
+    /// Sets the maximum size of request bodies, in bytes. Defaults to
+    /// [`DEFAULT_REQUEST_BODY_LIMIT`].
+    ///
+    /// Requests with a larger body are rejected with a `413 Payload Too Large` response as soon
+    /// as the limit is exceeded, without calling the wrapped service.
+    pub fn with_request_body_limit(mut self, limit: usize) -> Self {
+        self.request_body_limit = limit;
+        self
+    }
+
+    /// Sets the maximum size of response bodies that are buffered for inspection, in bytes.
+    /// Defaults to [`DEFAULT_RESPONSE_BODY_LIMIT`].
+    ///
+    /// Only the first `limit` bytes of larger bodies are inspected, and the rest is streamed
+    /// through, as ModSecurity does with `SecResponseBodyLimitAction ProcessPartial`.
+    pub fn with_response_body_limit(mut self, limit: usize) -> Self {
+        self.response_body_limit = limit;
+        self
+    }
+
+    /// Sets whether response bodies are buffered and inspected. Disabled by default.
+    ///
+    /// This should be enabled when rules inspect response bodies, i.e. with
+    /// `SecResponseBodyAccess On`. Otherwise, response bodies are streamed through without being
+    /// buffered, and the response body phase is skipped.
+    pub fn with_response_body_inspection(mut self, enabled: bool) -> Self {
+        self.inspect_response_body = enabled;
+        self
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ModSecurityMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = ModSecurityService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ModSecurityService {
+            service: Rc::new(service),
+            middleware: self.clone(),
+        }))
+    }
+}
+
+/// A [`Service`] that enforces ModSecurity rules on the requests and responses of a wrapped
+/// service. See [`ModSecurityMiddleware`].
+///
+/// Errors from ModSecurity result in a `500 Internal Server Error` response, and errors reading
+/// the response body of the wrapped service in a `502 Bad Gateway` response. Errors reading the
+/// request body and errors of the wrapped service are returned as they are. The logging phase runs
+/// in all these cases, except if the transaction could not be created.
+pub struct ModSecurityService<S> {
+    service: Rc<S>,
+    middleware: ModSecurityMiddleware,
+}
+
+impl<S, B> Service<ServiceRequest> for ModSecurityService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let middleware = self.middleware.clone();
+
+        Box::pin(async move {
+            let mut transaction = middleware
+                .ms
+                .shared_transaction_builder()
+                .with_shared_rules(Arc::clone(&middleware.rules))
+                .build()
+                .map_err(ErrorInternalServerError)?;
+
+            let (request, payload) = request.into_parts();
+
+            if let Some(disruption) = inspect_request_headers(&mut transaction, &request)
+                .map_err(|err| fail(&mut transaction, ErrorInternalServerError(err)))?
+            {
+                return Ok(respond(
+                    &middleware,
+                    &mut transaction,
+                    request,
+                    &disruption.intervention,
+                ));
+            }
+
+            let body = match body::to_bytes_limited(
+                BodyStream::new(payload),
+                middleware.request_body_limit,
+            )
+            .await
+            {
+                Ok(body) => body.map_err(|err| fail(&mut transaction, err.into()))?,
+                Err(BodyLimitExceeded { .. }) => {
+                    let _ = transaction.process_logging();
+                    return Ok(ServiceResponse::new(
+                        request,
+                        HttpResponse::PayloadTooLarge().finish(),
+                    ));
+                }
+            };
+
+            if let Some(disruption) = transaction
+                .inspect_request_body(&body, true)
+                .map_err(|err| fail(&mut transaction, ErrorInternalServerError(err)))?
+            {
+                return Ok(respond(
+                    &middleware,
+                    &mut transaction,
+                    request,
+                    &disruption.intervention,
+                ));
+            }
+
+            let response = service
+                .call(ServiceRequest::from_parts(request, Payload::from(body)))
+                .await
+                .map_err(|err| fail(&mut transaction, err))?;
+
+            let (request, response) = response.into_parts();
+            let (response, body) = response.into_parts();
+
+            if let Some(disruption) =
+                inspect_response_headers(&mut transaction, &request, &response)
+                    .map_err(|err| fail(&mut transaction, ErrorInternalServerError(err)))?
+            {
+                return Ok(respond(
+                    &middleware,
+                    &mut transaction,
+                    request,
+                    &disruption.intervention,
+                ));
+            }
+
+            // Without inspection, the response body phase is skipped rather than run on an
+            // empty body.
+            let body = if middleware.inspect_response_body {
+                let body = BufferedBody::read(body, middleware.response_body_limit)
+                    .await
+                    .map_err(|err| fail(&mut transaction, ErrorBadGateway(err.into())))?;
+
+                if let Some(disruption) = transaction
+                    .inspect_response_body(&body.data(middleware.response_body_limit), true)
+                    .map_err(|err| fail(&mut transaction, ErrorInternalServerError(err)))?
+                {
+                    return Ok(respond(
+                        &middleware,
+                        &mut transaction,
+                        request,
+                        &disruption.intervention,
+                    ));
+                }
+
+                BoxBody::new(body)
+            } else {
+                BoxBody::new(body)
+            };
+
+            // The response has already been decided at this point, so any intervention raised
+            // by the logging phase is not acted upon.
+            let _ = transaction.process_logging();
+
+            Ok(ServiceResponse::new(request, response.set_body(body)))
+        })
+    }
+}
+
+/// Runs the connection, URI and request headers phases for `request`.
+fn inspect_request_headers(
+    transaction: &mut OwnedTransaction,
+    request: &HttpRequest,
+) -> ModSecurityResult<Option<Disruption>> {
+    let unspecified = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0));
+
+    transaction.inspect_request_headers(
+        request.peer_addr().unwrap_or(unspecified),
+        request.app_config().local_addr(),
+        request.method().as_str(),
+        &request.uri().to_string(),
+        version_str(request.version()),
+        request
+            .headers()
+            .iter()
+            .map(|(key, value)| (key.as_str().as_bytes(), value.as_bytes())),
+    )
+}
+
+/// Runs the response headers phase for `response`.
+fn inspect_response_headers(
+    transaction: &mut OwnedTransaction,
+    request: &HttpRequest,
+    response: &HttpResponse<()>,
+) -> ModSecurityResult<Option<Disruption>> {
+    transaction.inspect_response_headers(
+        response.status().as_u16(),
+        version_str(request.version()),
+        response
+            .headers()
+            .iter()
+            .map(|(key, value)| (key.as_str().as_bytes(), value.as_bytes())),
+    )
+}
+
+/// Builds the response for `intervention`, running the logging phase first.
+fn respond(
+    middleware: &ModSecurityMiddleware,
+    transaction: &mut OwnedTransaction,
+    request: HttpRequest,
+    intervention: &Intervention,
+) -> ServiceResponse<BoxBody> {
+    let _ = transaction.process_logging();
+    ServiceResponse::new(request, (middleware.intervention_response)(intervention))
+}
+
+/// Runs the logging phase for a request that could not be inspected or served, returning `err`.
+fn fail(transaction: &mut OwnedTransaction, err: Error) -> Error {
+    let _ = transaction.process_logging();
+    err
+}
+
+/// The body of a response of the wrapped service, after it was read for inspection.
+///
+/// The chunks that were read are sent first. If the body was larger than the response body limit,
+/// they are followed by the rest of it.
+struct BufferedBody<B> {
+    chunks: VecDeque<Bytes>,
+    rest: Option<Pin<Box<B>>>,
+}
+
+impl<B: MessageBody> BufferedBody<B> {
+    /// Reads the chunks of `body` until it ends, or until more than `limit` bytes have been read.
+    async fn read(body: B, limit: usize) -> Result<Self, B::Error> {
+        let mut body = Box::pin(body);
+        let mut chunks = VecDeque::new();
+        let mut len = 0;
+
+        while len <= limit {
+            let chunk = match NextChunk(body.as_mut()).await {
+                Some(chunk) => chunk?,
+                None => return Ok(Self { chunks, rest: None }),
+            };
+
+            len += chunk.len();
+            chunks.push_back(chunk);
+        }
+
+        Ok(Self {
+            chunks,
+            rest: Some(body),
+        })
+    }
+
+    /// Returns the data that was read, up to `limit` bytes.
+    fn data(&self, limit: usize) -> Vec<u8> {
+        let mut data: Vec<u8> = self.chunks.iter().flatten().copied().collect();
+        data.truncate(limit);
+        data
+    }
+}
+
+impl<B: MessageBody> MessageBody for BufferedBody<B> {
+    type Error = B::Error;
+
+    fn size(&self) -> BodySize {
+        // The size of the rest is unknown once reading it has started.
+        match self.rest {
+            Some(_) => BodySize::Stream,
+            None => BodySize::Sized(self.chunks.iter().map(|chunk| chunk.len() as u64).sum()),
+        }
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+
+        if let Some(chunk) = this.chunks.pop_front() {
+            return Poll::Ready(Some(Ok(chunk)));
+        }
+
+        match &mut this.rest {
+            Some(rest) => rest.as_mut().poll_next(cx),
+            None => Poll::Ready(None),
+        }
+    }
+}
+
+/// A future that resolves to the next chunk of a body.
+///
+/// `std::future::poll_fn` is not used, as it requires Rust 1.64.
+struct NextChunk<'a, B>(Pin<&'a mut B>);
+
+impl<B: MessageBody> Future for NextChunk<'_, B> {
+    type Output = Option<Result<Bytes, B::Error>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.0.as_mut().poll_next(cx)
+    }
+}
+
+/// The default response for an intervention. See
+/// [`ModSecurityMiddleware::with_intervention_response()`].
+fn intervention_response(intervention: &Intervention) -> HttpResponse {
+    // actix-web uses version 0.2 of the `http` crate, so the status is converted from its code.
+    let status = StatusCode::from_u16(intervention.response_status().as_u16())
+        .unwrap_or(StatusCode::FORBIDDEN);
+
+    let mut response = HttpResponse::build(status);
+
+    if let Some(url) = intervention.url() {
+        response.insert_header((header::LOCATION, url));
+    }
+
+    response.finish()
+}
+
+/// Returns the HTTP version in the form expected by ModSecurity (e.g. `1.1`).
+///
+/// actix-web uses version 0.2 of the `http` crate, so this cannot use the helpers of the
+/// [`http`](crate::http) module.
+fn version_str(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "0.9",
+        Version::HTTP_10 => "1.0",
+        Version::HTTP_2 => "2.0",
+        Version::HTTP_3 => "3.0",
+        _ => "1.1",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        test::{call_service, init_service, read_body, TestRequest},
+        web::{self, Bytes},
+        App,
+    };
+
+    use super::*;
+
+    fn rules(plain_rules: &str) -> Arc<Rules> {
+        let mut rules = Rules::new();
+        rules.add_plain(plain_rules).unwrap();
+        Arc::new(rules)
+    }
+
+    fn middleware(plain_rules: &str) -> ModSecurityMiddleware {
+        ModSecurityMiddleware::new(Arc::new(ModSecurity::default()), rules(plain_rules))
+    }
+
+    const ADMIN_RULES: &str = r#"
+        SecRuleEngine On
+
+        SecRule REQUEST_URI "@rx admin" "id:1,phase:1,deny,status:401"
+    "#;
+
+    #[actix_web::test]
+    async fn test_allowed() {
+        let app = init_service(
+            App::new()
+                .wrap(middleware(ADMIN_RULES))
+                .route("/echo", web::post().to(|body: Bytes| async move { body })),
+        )
+        .await;
+
+        let request = TestRequest::post()
+            .uri("/echo")
+            .set_payload("hello")
+            .to_request();
+        let response = call_service(&app, request).await;
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(read_body(response).await, "hello");
+    }
+
+    #[actix_web::test]
+    async fn test_request_body_limit() {
+        let called = Arc::new(std::sync::atomic::AtomicBool::new(false));
+
+        let app = init_service(
+            App::new()
+                .wrap(middleware(ADMIN_RULES).with_request_body_limit(4))
+                .route(
+                    "/echo",
+                    web::post().to({
+                        let called = Arc::clone(&called);
+                        move |body: Bytes| {
+                            called.store(true, std::sync::atomic::Ordering::SeqCst);
+                            async move { body }
+                        }
+                    }),
+                ),
+        )
+        .await;
+
+        let request = TestRequest::post()
+            .uri("/echo")
+            .set_payload("hello")
+            .to_request();
+        let response = call_service(&app, request).await;
+
+        assert_eq!(response.status(), 413);
+        assert!(!called.load(std::sync::atomic::Ordering::SeqCst));
+
+        // The default limit is not the one of the default `PayloadConfig`, which is 256 KiB.
+        let app = init_service(
+            App::new()
+                .wrap(middleware(ADMIN_RULES))
+                .route("/", web::post().to(|| async { "Hello" })),
+        )
+        .await;
+
+        let request = TestRequest::post()
+            .set_payload(vec![b'a'; 512 * 1024])
+            .to_request();
+        let response = call_service(&app, request).await;
+
+        assert_eq!(response.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_request_intervention() {
+        let app = init_service(
+            App::new()
+                .wrap(middleware(ADMIN_RULES))
+                .route("/admin", web::get().to(|| async { "Hello" })),
+        )
+        .await;
+
+        let request = TestRequest::get().uri("/admin").to_request();
+        let response = call_service(&app, request).await;
+
+        assert_eq!(response.status(), 401);
+        assert!(read_body(response).await.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_response_intervention() {
+        let app = init_service(
+            App::new()
+                .wrap(
+                    middleware(
+                        r#"
+                    SecRuleEngine On
+
+                    SecResponseBodyAccess On
+
+                    SecRule RESPONSE_BODY "@rx secret" "id:1,phase:4,deny,status:500"
+                "#,
+                    )
+                    .with_response_body_inspection(true),
+                )
+                .route("/", web::get().to(|| async { "a secret" })),
+        )
+        .await;
+
+        let response = call_service(&app, TestRequest::get().to_request()).await;
+
+        assert_eq!(response.status(), 500);
+    }
+
+    #[actix_web::test]
+    async fn test_response_body_phase_skipped() {
+        let app = init_service(
+            App::new()
+                .wrap(middleware(
+                    r#"
+                    SecRuleEngine On
+
+                    SecRule REQUEST_URI "@rx ." "id:1,phase:4,deny,status:500"
+                "#,
+                ))
+                .route("/", web::get().to(|| async { "hello" })),
+        )
+        .await;
+
+        let response = call_service(&app, TestRequest::get().to_request()).await;
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(read_body(response).await, "hello");
+    }
+
+    #[actix_web::test]
+    async fn test_response_body_limit() {
+        let app = init_service(
+            App::new()
+                .wrap(
+                    middleware(
+                        r#"
+                    SecRuleEngine On
+
+                    SecResponseBodyAccess On
+
+                    SecRule RESPONSE_BODY "@rx long" "id:1,phase:4,deny,status:500"
+                "#,
+                    )
+                    .with_response_body_limit(4)
+                    .with_response_body_inspection(true),
+                )
+                .route("/", web::get().to(|| async { "too long" })),
+        )
+        .await;
+
+        let response = call_service(&app, TestRequest::get().to_request()).await;
+
+        // Only the part of the body within the limit is inspected, and the rest is passed on.
+        assert_eq!(response.status(), 200);
+        assert_eq!(read_body(response).await, "too long");
+    }
+
+    #[actix_web::test]
+    async fn test_response_body_streamed() {
+        let app = init_service(
+            App::new()
+                .wrap(middleware(ADMIN_RULES))
+                .route("/", web::get().to(|| async { "not buffered" })),
+        )
+        .await;
+
+        let response = call_service(&app, TestRequest::get().to_request()).await;
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.response().body().size(), BodySize::Sized(12));
+        assert_eq!(read_body(response).await, "not buffered");
+    }
+
+    #[actix_web::test]
+    async fn test_buffered_body() {
+        let buffered = BufferedBody::read(Bytes::from("too long"), 4)
+            .await
+            .unwrap();
+
+        assert_eq!(buffered.data(4), b"too ");
+        assert_eq!(buffered.size(), BodySize::Stream);
+        assert_eq!(body::to_bytes(buffered).await.unwrap(), "too long");
+
+        let buffered = BufferedBody::read(Bytes::from("short"), 8).await.unwrap();
+
+        assert_eq!(buffered.data(8), b"short");
+        assert_eq!(buffered.size(), BodySize::Sized(5));
+        assert_eq!(body::to_bytes(buffered).await.unwrap(), "short");
+    }
+
+    #[actix_web::test]
+    async fn test_redirect() {
+        let app = init_service(
+            App::new()
+                .wrap(middleware(
+                    r#"
+                    SecRuleEngine On
+
+                    SecRule REQUEST_URI "@rx admin" "id:1,phase:1,redirect:https://example.com/login"
+                "#,
+                ))
+                .route("/admin", web::get().to(|| async { "Hello" })),
+        )
+        .await;
+
+        let request = TestRequest::get().uri("/admin").to_request();
+        let response = call_service(&app, request).await;
+
+        assert_eq!(response.status(), 302);
+        assert_eq!(
+            response.headers().get(header::LOCATION).unwrap(),
+            "https://example.com/login"
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_connection_info() {
+        let app = init_service(
+            App::new()
+                .wrap(middleware(
+                    r#"
+                    SecRuleEngine On
+
+                    SecRule REMOTE_ADDR "@ipMatch 124.123.122.121" "id:1,phase:1,deny,status:403"
+                "#,
+                ))
+                .route("/", web::get().to(|| async { "Hello" })),
+        )
+        .await;
+
+        let request = TestRequest::get()
+            .peer_addr("124.123.122.121:12345".parse().unwrap())
+            .to_request();
+        let response = call_service(&app, request).await;
+
+        assert_eq!(response.status(), 403);
+    }
+
+    #[actix_web::test]
+    async fn test_custom_intervention_response() {
+        let app = init_service(
+            App::new()
+                .wrap(
+                    middleware(ADMIN_RULES).with_intervention_response(|intervention| {
+                        HttpResponse::build(
+                            StatusCode::from_u16(intervention.status() as u16).unwrap(),
+                        )
+                        .body("Blocked")
+                    }),
+                )
+                .route("/admin", web::get().to(|| async { "Hello" })),
+        )
+        .await;
+
+        let request = TestRequest::get().uri("/admin").to_request();
+        let response = call_service(&app, request).await;
+
+        assert_eq!(response.status(), 401);
+        assert_eq!(read_body(response).await, "Blocked");
+    }
+
+    #[test]
+    fn test_version_str() {
+        assert_eq!(version_str(Version::HTTP_09), "0.9");
+        assert_eq!(version_str(Version::HTTP_10), "1.0");
+        assert_eq!(version_str(Version::HTTP_11), "1.1");
+        assert_eq!(version_str(Version::HTTP_2), "2.0");
+        assert_eq!(version_str(Version::HTTP_3), "3.0");
+    }
+}
@@ -0,0 +1,625 @@
+//! Running transactions off the async executor.
+//!
+//! This module is only available with the `async` feature enabled.
+//!
+//! Every phase of a [`Transaction`] is a synchronous call into libmodsecurity that can take a
+//! while to complete, for example when evaluating expensive regular expressions. Making those
+//! calls from an async task blocks the executor thread it runs on.
+//!
+//! [`AsyncTransaction`] instead runs each call on a [`BlockingPool`], a fixed-size pool of
+//! dedicated threads, and returns a future that completes once the call has finished. The futures
+//! are runtime agnostic.
+//!
+//! ## Examples
+//!
+//! ```
+//! use std::sync::Arc;
+//!
+//! use modsecurity::{
+//!     asynchronous::{AsyncTransaction, BlockingPool},
+//!     ModSecurity, Rules,
+//! };
+//!
+//! # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+//! let ms = Arc::new(ModSecurity::default());
+//!
+//! let mut rules = Rules::new();
+//! rules.add_plain(r#"
+//!     SecRuleEngine On
+//!
+//!     SecRule REQUEST_URI "@rx admin" "id:1,phase:1,deny,status:401"
+//! "#).expect("Failed to add rules");
+//!
+//! let pool = BlockingPool::new(4);
+//!
+//! let transaction = ms
+//!     .shared_transaction_builder()
+//!     .with_shared_rules(Arc::new(rules))
+//!     .build()
+//!     .expect("Error building transaction");
+//!
+//! let mut transaction = AsyncTransaction::new(transaction, pool);
+//!
+//! transaction.process_uri("/admin", "GET", "1.1").await.expect("Error processing URI");
+//! transaction.process_request_headers().await.expect("Error processing request headers");
+//!
+//! let intervention = transaction.intervention().await.expect("Expected intervention");
+//!
+//! assert_eq!(intervention.status(), 401);
+//! # });
+//! ```
+
+use std::{
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{mpsc, Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    task::{Context, Poll, Waker},
+    thread,
+};
+
+use crate::{intervention::Intervention, transaction::OwnedTransaction, ModSecurityResult};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Recovers the guard of a poisoned lock.
+///
+/// Jobs are run under [`panic::catch_unwind`], so a poisoned lock only means that a previous
+/// call panicked. The panic is propagated to the caller of that call.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A fixed-size pool of dedicated threads that [`AsyncTransaction`] calls are run on.
+///
+/// The pool is cheap to clone, and clones share the same threads. The threads exit once every
+/// clone has been dropped and all queued calls have completed.
+#[derive(Clone)]
+pub struct BlockingPool {
+    sender: Arc<Mutex<mpsc::Sender<Job>>>,
+}
+
+impl BlockingPool {
+    /// The number of threads of a pool created with [`BlockingPool::default()`].
+    ///
+    /// Use [`BlockingPool::new()`] to size the pool after the number of CPUs or the expected
+    /// number of concurrent transactions instead.
+    pub const DEFAULT_THREADS: usize = 4;
+
+    /// Creates a new pool with `threads` worker threads.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `threads` is zero or if a thread cannot be spawned.
+    pub fn new(threads: usize) -> Self {
+        assert!(threads > 0, "A blocking pool requires at least one thread");
+
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for i in 0..threads {
+            let receiver = Arc::clone(&receiver);
+
+            thread::Builder::new()
+                .name(format!("modsecurity-worker-{}", i))
+                .spawn(move || loop {
+                    // The lock is released before the job runs so other workers can pick up the
+                    // next one.
+                    let job = lock(&receiver).recv();
+
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => break,
+                    }
+                })
+                .expect("Failed to spawn blocking pool thread");
+        }
+
+        Self {
+            sender: Arc::new(Mutex::new(sender)),
+        }
+    }
+
+    /// Runs `f` on the pool, returning a future that resolves to its result.
+    ///
+    /// If `f` panics, the panic is resumed when the future is polled.
+    fn spawn<F, T>(&self, f: F) -> Task<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let shared = Arc::new(Mutex::new(TaskState {
+            result: None,
+            waker: None,
+        }));
+
+        let job = {
+            let shared = Arc::clone(&shared);
+
+            Box::new(move || {
+                let result = panic::catch_unwind(AssertUnwindSafe(f));
+
+                let mut state = lock(&shared);
+                state.result = Some(result);
+
+                if let Some(waker) = state.waker.take() {
+                    waker.wake();
+                }
+            })
+        };
+
+        lock(&self.sender)
+            .send(job)
+            .expect("Blocking pool threads have exited");
+
+        Task { shared }
+    }
+}
+
+impl Default for BlockingPool {
+    /// Creates a pool with [`BlockingPool::DEFAULT_THREADS`] threads.
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_THREADS)
+    }
+}
+
+struct TaskState<T> {
+    result: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+/// The future returned by calls made on a [`BlockingPool`].
+struct Task<T> {
+    shared: Arc<Mutex<TaskState<T>>>,
+}
+
+impl<T> Future for Task<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = lock(&self.shared);
+
+        match state.result.take() {
+            Some(Ok(value)) => Poll::Ready(value),
+            Some(Err(panic)) => panic::resume_unwind(panic),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// The transaction of an [`AsyncTransaction`], along with the ticket of the next call allowed to
+/// use it.
+struct Slot {
+    transaction: OwnedTransaction,
+    next: u64,
+}
+
+/// A [`Transaction`](crate::transaction::Transaction) whose calls are run on a [`BlockingPool`].
+///
+/// Only [`OwnedTransaction`]s with the default bindings can be sent to the pool's threads. The
+/// transaction, along with the callback set with
+/// [`TransactionBuilder::with_logging()`](crate::transaction::TransactionBuilder::with_logging),
+/// is kept alive until every call made on it has completed, even if the future for a call is
+/// dropped before then.
+///
+/// Calls run in the order in which they are queued on the pool. [`AsyncTransaction::run()`]
+/// queues its call immediately, while the other methods do so when their future is first polled.
+pub struct AsyncTransaction {
+    slot: Arc<(Mutex<Slot>, Condvar)>,
+    issued: u64,
+    pool: BlockingPool,
+}
+
+impl AsyncTransaction {
+    /// Wraps `transaction` so that its calls are run on `pool`.
+    pub fn new(transaction: OwnedTransaction, pool: BlockingPool) -> Self {
+        Self {
+            slot: Arc::new((
+                Mutex::new(Slot {
+                    transaction,
+                    next: 0,
+                }),
+                Condvar::new(),
+            )),
+            issued: 0,
+            pool,
+        }
+    }
+
+    /// Runs `f` with the transaction on the pool.
+    ///
+    /// This can be used for calls that don't have a dedicated method, or to batch several calls
+    /// into a single trip to the pool.
+    ///
+    /// ## Examples
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    ///
+    /// use modsecurity::{
+    ///     asynchronous::{AsyncTransaction, BlockingPool},
+    ///     ModSecurity, Rules,
+    /// };
+    ///
+    /// # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+    /// let ms = Arc::new(ModSecurity::default());
+    ///
+    /// let transaction = ms
+    ///     .shared_transaction_builder()
+    ///     .with_shared_rules(Arc::new(Rules::new()))
+    ///     .build()
+    ///     .expect("Error building transaction");
+    ///
+    /// let mut transaction = AsyncTransaction::new(transaction, BlockingPool::new(1));
+    ///
+    /// transaction
+    ///     .run(|transaction| {
+    ///         transaction.add_request_header("Host", "example.com")?;
+    ///         transaction.add_request_header("Accept", "*/*")?;
+    ///         transaction.process_request_headers()
+    ///     })
+    ///     .await
+    ///     .expect("Error processing request headers");
+    /// # });
+    /// ```
+    pub fn run<F, T>(&mut self, f: F) -> impl Future<Output = T>
+    where
+        F: FnOnce(&mut OwnedTransaction) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let slot = Arc::clone(&self.slot);
+        let ticket = self.issued;
+        self.issued += 1;
+
+        self.pool.spawn(move || {
+            let (slot, turn) = &*slot;
+
+            // Calls are dequeued in order, so every earlier call is already running or waiting
+            // for its own turn.
+            let mut slot = turn
+                .wait_while(lock(slot), |slot| slot.next != ticket)
+                .unwrap_or_else(PoisonError::into_inner);
+
+            let result = panic::catch_unwind(AssertUnwindSafe(|| f(&mut slot.transaction)));
+
+            slot.next += 1;
+            turn.notify_all();
+
+            match result {
+                Ok(value) => value,
+                Err(panic) => panic::resume_unwind(panic),
+            }
+        })
+    }
+
+    /// Returns the inner transaction, or `self` if a call made on it is still in progress.
+    pub fn try_into_inner(self) -> Result<OwnedTransaction, Self> {
+        match Arc::try_unwrap(self.slot) {
+            Ok((slot, _)) => Ok(slot
+                .into_inner()
+                .unwrap_or_else(PoisonError::into_inner)
+                .transaction),
+            Err(slot) => Err(Self {
+                slot,
+                issued: self.issued,
+                pool: self.pool,
+            }),
+        }
+    }
+
+    /// See [`Transaction::process_connection()`](crate::transaction::Transaction::process_connection).
+    pub async fn process_connection(
+        &mut self,
+        client: &str,
+        c_port: i32,
+        server: &str,
+        s_port: i32,
+    ) -> ModSecurityResult<()> {
+        let client = client.to_owned();
+        let server = server.to_owned();
+
+        self.run(move |transaction| {
+            transaction.process_connection(&client, c_port, &server, s_port)
+        })
+        .await
+    }
+
+    /// See [`Transaction::process_uri()`](crate::transaction::Transaction::process_uri).
+    pub async fn process_uri(
+        &mut self,
+        uri: &str,
+        method: &str,
+        http_version: &str,
+    ) -> ModSecurityResult<()> {
+        let uri = uri.to_owned();
+        let method = method.to_owned();
+        let http_version = http_version.to_owned();
+
+        self.run(move |transaction| transaction.process_uri(&uri, &method, &http_version))
+            .await
+    }
+
+    /// See [`Transaction::add_request_header()`](crate::transaction::Transaction::add_request_header).
+    pub async fn add_request_header(&mut self, key: &str, value: &str) -> ModSecurityResult<()> {
+        let key = key.to_owned();
+        let value = value.to_owned();
+
+        self.run(move |transaction| transaction.add_request_header(&key, &value))
+            .await
+    }
+
+    /// See [`Transaction::process_request_headers()`](crate::transaction::Transaction::process_request_headers).
+    pub async fn process_request_headers(&mut self) -> ModSecurityResult<()> {
+        self.run(|transaction| transaction.process_request_headers())
+            .await
+    }
+
+    /// See [`Transaction::append_request_body()`](crate::transaction::Transaction::append_request_body).
+    pub async fn append_request_body(&mut self, body: Vec<u8>) -> ModSecurityResult<()> {
+        self.run(move |transaction| transaction.append_request_body(&body))
+            .await
+    }
+
+    /// See [`Transaction::process_request_body()`](crate::transaction::Transaction::process_request_body).
+    pub async fn process_request_body(&mut self) -> ModSecurityResult<()> {
+        self.run(|transaction| transaction.process_request_body())
+            .await
+    }
+
+    /// See [`Transaction::add_response_header()`](crate::transaction::Transaction::add_response_header).
+    pub async fn add_response_header(&mut self, key: &str, value: &str) -> ModSecurityResult<()> {
+        let key = key.to_owned();
+        let value = value.to_owned();
+
+        self.run(move |transaction| transaction.add_response_header(&key, &value))
+            .await
+    }
+
+    /// See [`Transaction::process_response_headers()`](crate::transaction::Transaction::process_response_headers).
+    pub async fn process_response_headers(
+        &mut self,
+        code: i32,
+        protocol: &str,
+    ) -> ModSecurityResult<()> {
+        let protocol = protocol.to_owned();
+
+        self.run(move |transaction| transaction.process_response_headers(code, &protocol))
+            .await
+    }
+
+    /// See [`Transaction::append_response_body()`](crate::transaction::Transaction::append_response_body).
+    pub async fn append_response_body(&mut self, body: Vec<u8>) -> ModSecurityResult<()> {
+        self.run(move |transaction| transaction.append_response_body(&body))
+            .await
+    }
+
+    /// See [`Transaction::process_response_body()`](crate::transaction::Transaction::process_response_body).
+    pub async fn process_response_body(&mut self) -> ModSecurityResult<()> {
+        self.run(|transaction| transaction.process_response_body())
+            .await
+    }
+
+    /// See [`Transaction::process_logging()`](crate::transaction::Transaction::process_logging).
+    pub async fn process_logging(&mut self) -> ModSecurityResult<()> {
+        self.run(|transaction| transaction.process_logging()).await
+    }
+
+    /// See [`Transaction::intervention()`](crate::transaction::Transaction::intervention).
+    pub async fn intervention(&mut self) -> Option<Intervention> {
+        self.run(|transaction| transaction.intervention()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{msc::ModSecurity, rules::Rules};
+
+    fn transaction(plain_rules: &str) -> OwnedTransaction {
+        let ms = Arc::new(ModSecurity::builder().with_log_callbacks().build());
+        let mut rules = Rules::new();
+        rules.add_plain(plain_rules).unwrap();
+
+        ms.shared_transaction_builder()
+            .with_shared_rules(Arc::new(rules))
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_runs_on_pool() {
+        let pool = BlockingPool::new(2);
+        let mut transaction = AsyncTransaction::new(transaction(""), pool);
+
+        let name = transaction
+            .run(|_| thread::current().name().map(str::to_owned))
+            .await;
+
+        assert!(name.unwrap().starts_with("modsecurity-worker-"));
+    }
+
+    #[tokio::test]
+    async fn test_phases() {
+        let pool = BlockingPool::new(1);
+        let mut transaction = AsyncTransaction::new(transaction("SecRuleEngine On"), pool);
+
+        transaction
+            .process_connection("127.0.0.1", 12345, "127.0.0.1", 80)
+            .await
+            .unwrap();
+        transaction
+            .process_uri("/index.html", "GET", "1.1")
+            .await
+            .unwrap();
+        transaction
+            .add_request_header("Host", "example.com")
+            .await
+            .unwrap();
+        transaction.process_request_headers().await.unwrap();
+        transaction
+            .append_request_body(b"hello".to_vec())
+            .await
+            .unwrap();
+        transaction.process_request_body().await.unwrap();
+        transaction
+            .add_response_header("Content-Type", "text/plain")
+            .await
+            .unwrap();
+        transaction
+            .process_response_headers(200, "HTTP 1.1")
+            .await
+            .unwrap();
+        transaction
+            .append_response_body(b"world".to_vec())
+            .await
+            .unwrap();
+        transaction.process_response_body().await.unwrap();
+        transaction.process_logging().await.unwrap();
+
+        assert!(transaction.intervention().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_intervention() {
+        let pool = BlockingPool::new(1);
+        let mut transaction = AsyncTransaction::new(
+            transaction(
+                r#"
+                SecRuleEngine On
+
+                SecRule REQUEST_URI "@rx admin" "id:1,phase:1,deny,status:401"
+            "#,
+            ),
+            pool,
+        );
+
+        transaction
+            .process_uri("/admin", "GET", "1.1")
+            .await
+            .unwrap();
+        transaction.process_request_headers().await.unwrap();
+
+        assert_eq!(transaction.intervention().await.unwrap().status(), 401);
+    }
+
+    #[tokio::test]
+    async fn test_log_callback_outlives_dropped_future() {
+        let ms = Arc::new(ModSecurity::builder().with_log_callbacks().build());
+        let mut rules = Rules::new();
+        rules
+            .add_plain(
+                r#"
+                SecRuleEngine On
+
+                SecRule REQUEST_URI "@rx admin" "id:1,phase:1,log,deny,status:401"
+            "#,
+            )
+            .unwrap();
+
+        let logs = Arc::new(AtomicUsize::new(0));
+
+        let transaction = ms
+            .shared_transaction_builder()
+            .with_shared_rules(Arc::new(rules))
+            .with_logging({
+                let logs = Arc::clone(&logs);
+                move |_| {
+                    logs.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+            .build()
+            .unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+
+        let mut transaction = AsyncTransaction::new(transaction, BlockingPool::new(1));
+
+        // Neither the future nor the transaction outlive this statement, but the call still runs
+        // on the pool and keeps the transaction alive until it completes.
+        drop(transaction.run(move |transaction| {
+            thread::sleep(Duration::from_millis(50));
+            transaction.process_uri("/admin", "GET", "1.1").unwrap();
+            transaction.process_request_headers().unwrap();
+            sender.send(()).unwrap();
+        }));
+        drop(transaction);
+
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(logs.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_calls_run_in_order() {
+        let mut transaction = AsyncTransaction::new(transaction(""), BlockingPool::new(4));
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        let calls = (0..16)
+            .map(|i| {
+                let order = Arc::clone(&order);
+                transaction.run(move |_| {
+                    thread::sleep(Duration::from_millis(16 - i));
+                    order.lock().unwrap().push(i);
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for call in calls {
+            call.await;
+        }
+
+        assert_eq!(*order.lock().unwrap(), (0..16).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_try_into_inner() {
+        let mut transaction = AsyncTransaction::new(transaction(""), BlockingPool::new(1));
+
+        transaction
+            .process_uri("/index.html", "GET", "1.1")
+            .await
+            .unwrap();
+
+        assert!(transaction.try_into_inner().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_panic_is_propagated() {
+        let mut transaction = AsyncTransaction::new(transaction(""), BlockingPool::new(1));
+
+        let result = tokio::spawn(async move {
+            transaction.run(|_| panic!("boom")).await;
+        })
+        .await;
+
+        assert!(result.unwrap_err().is_panic());
+    }
+
+    #[tokio::test]
+    async fn test_pool_survives_panic() {
+        let pool = BlockingPool::new(1);
+        let mut first = AsyncTransaction::new(transaction(""), pool.clone());
+        let mut second = AsyncTransaction::new(transaction(""), pool);
+
+        let _ = tokio::spawn(async move {
+            first.run(|_| panic!("boom")).await;
+        })
+        .await;
+
+        assert_eq!(second.run(|_| 42).await, 42);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one thread")]
+    fn test_empty_pool() {
+        BlockingPool::new(0);
+    }
+}
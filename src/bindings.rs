@@ -55,6 +55,14 @@ pub trait RawBindings {
             value: *const ::std::os::raw::c_uchar
         ) -> ::std::os::raw::c_int;
 
+        unsafe fn msc_add_n_request_header(
+            transaction: *mut Transaction,
+            key: *const ::std::os::raw::c_uchar,
+            len_key: usize,
+            value: *const ::std::os::raw::c_uchar,
+            len_value: usize
+        ) -> ::std::os::raw::c_int;
+
         unsafe fn msc_process_request_body(transaction: *mut Transaction) -> ::std::os::raw::c_int;
 
         unsafe fn msc_append_request_body(
@@ -63,6 +71,11 @@ pub trait RawBindings {
             size: usize
         ) -> ::std::os::raw::c_int;
 
+        unsafe fn msc_request_body_from_file(
+            transaction: *mut Transaction,
+            path: *const ::std::os::raw::c_char
+        ) -> ::std::os::raw::c_int;
+
         unsafe fn msc_process_response_headers(
             transaction: *mut Transaction,
             code: ::std::os::raw::c_int,
@@ -75,6 +88,14 @@ pub trait RawBindings {
             value: *const ::std::os::raw::c_uchar
         ) -> ::std::os::raw::c_int;
 
+        unsafe fn msc_add_n_response_header(
+            transaction: *mut Transaction,
+            key: *const ::std::os::raw::c_uchar,
+            len_key: usize,
+            value: *const ::std::os::raw::c_uchar,
+            len_value: usize
+        ) -> ::std::os::raw::c_int;
+
         unsafe fn msc_process_response_body(transaction: *mut Transaction) -> ::std::os::raw::c_int;
 
         unsafe fn msc_append_response_body(
@@ -90,6 +111,18 @@ pub trait RawBindings {
             http_version: *const ::std::os::raw::c_char
         ) -> ::std::os::raw::c_int;
 
+        unsafe fn msc_update_status_code(
+            transaction: *mut Transaction,
+            status: ::std::os::raw::c_int
+        ) -> ::std::os::raw::c_int;
+
+        unsafe fn msc_set_request_hostname(
+            transaction: *mut Transaction,
+            hostname: *const ::std::os::raw::c_uchar
+        ) -> ::std::os::raw::c_int;
+
+        unsafe fn msc_get_response_body(transaction: *mut Transaction) -> *const ::std::os::raw::c_char;
+
         unsafe fn msc_get_response_body_length(transaction: *mut Transaction) -> usize;
 
         unsafe fn msc_get_request_body_length(transaction: *mut Transaction) -> usize;
@@ -131,10 +164,33 @@ pub trait RawBindings {
             error: *mut *const ::std::os::raw::c_char
         ) -> ::std::os::raw::c_int;
 
+        unsafe fn msc_rules_add_remote(
+            rules: *mut RulesSet,
+            key: *const ::std::os::raw::c_char,
+            uri: *const ::std::os::raw::c_char,
+            error: *mut *const ::std::os::raw::c_char
+        ) -> ::std::os::raw::c_int;
+
+        unsafe fn msc_rules_merge(
+            rules_dst: *mut RulesSet,
+            rules_from: *mut RulesSet,
+            error: *mut *const ::std::os::raw::c_char
+        ) -> ::std::os::raw::c_int;
+
         unsafe fn msc_rules_error_cleanup(error: *const ::std::os::raw::c_char);
 
         unsafe fn msc_rules_cleanup(rules: *mut RulesSet) -> ::std::os::raw::c_int;
     }
+
+    /// Frees memory that libmodsecurity allocated and handed over to the caller, such as the
+    /// copy of the response body returned by `msc_get_response_body`.
+    unsafe fn free(ptr: *mut ::std::os::raw::c_void) {
+        extern "C" {
+            fn free(ptr: *mut ::std::os::raw::c_void);
+        }
+
+        free(ptr)
+    }
 }
 
 #[derive(Clone, Copy)]
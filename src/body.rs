@@ -0,0 +1,466 @@
+//! Streaming inspection of request bodies.
+//!
+//! This module is only available with the `http-body` feature enabled.
+//!
+//! [`InspectedBody`] wraps an [`http_body::Body`] and passes each data frame to
+//! [`Transaction::append_request_body()`] as it is polled, so the body never has to be buffered
+//! by the caller. Once the inner body is exhausted, [`Transaction::process_request_body()`] is
+//! run. If either raises a disruptive intervention, the stream ends with an
+//! [`InspectedBodyError::Intervention`].
+//!
+//! ## Examples
+//!
+//! ```
+//! use std::sync::{Arc, Mutex};
+//!
+//! use bytes::Bytes;
+//! use http_body_util::{BodyExt, Full};
+//! use modsecurity::{body::InspectedBody, ModSecurity, Rules};
+//!
+//! # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+//! let ms = ModSecurity::default();
+//!
+//! let mut rules = Rules::new();
+//! rules.add_plain(r#"
+//!     SecRuleEngine On
+//!
+//!     SecRequestBodyAccess On
+//!
+//!     SecRule REQUEST_BODY "@rx attack" "id:1,phase:2,deny,status:403"
+//! "#).expect("Failed to add rules");
+//!
+//! let transaction = ms
+//!     .transaction_builder()
+//!     .with_rules(&rules)
+//!     .build()
+//!     .expect("Error building transaction");
+//! let transaction = Arc::new(Mutex::new(transaction));
+//!
+//! let body = InspectedBody::new(Full::new(Bytes::from("an attack")), Arc::clone(&transaction));
+//!
+//! assert!(body.collect().await.is_err());
+//! # });
+//! ```
+
+use std::{
+    error::Error,
+    fmt,
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    task::{Context, Poll},
+};
+
+use bytes::{Buf, Bytes};
+use http_body::{Body, Frame, SizeHint};
+
+use crate::{
+    bindings::{Bindings, RawBindings},
+    http::Disruption,
+    transaction::Transaction,
+    ModSecurityError,
+};
+
+/// An error produced by an [`InspectedBody`].
+pub enum InspectedBodyError<E, B: RawBindings = Bindings> {
+    /// The inner body returned an error.
+    Body(E),
+    /// ModSecurity failed to process the body.
+    ModSecurity(ModSecurityError),
+    /// A disruptive intervention was raised while processing the body.
+    Intervention(Disruption<B>),
+    /// The body exceeded the configured limit, in bytes.
+    LimitExceeded(usize),
+}
+
+impl<E: fmt::Debug, B: RawBindings> fmt::Debug for InspectedBodyError<E, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InspectedBodyError::Body(err) => f.debug_tuple("Body").field(err).finish(),
+            InspectedBodyError::ModSecurity(err) => {
+                f.debug_tuple("ModSecurity").field(err).finish()
+            }
+            InspectedBodyError::Intervention(disruption) => f
+                .debug_struct("Intervention")
+                .field("phase", &disruption.phase)
+                .field("intervention", &disruption.intervention)
+                .finish(),
+            InspectedBodyError::LimitExceeded(limit) => {
+                f.debug_tuple("LimitExceeded").field(limit).finish()
+            }
+        }
+    }
+}
+
+impl<E: Error + 'static, B: RawBindings> Error for InspectedBodyError<E, B> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            InspectedBodyError::Body(err) => Some(err),
+            InspectedBodyError::ModSecurity(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl<E: fmt::Display, B: RawBindings> fmt::Display for InspectedBodyError<E, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InspectedBodyError::Body(err) => write!(f, "Error reading body: {}", err),
+            InspectedBodyError::ModSecurity(err) => write!(f, "{}", err),
+            InspectedBodyError::Intervention(disruption) => write!(
+                f,
+                "Intervention with status {} raised during {:?}",
+                disruption.intervention.status(),
+                disruption.phase
+            ),
+            InspectedBodyError::LimitExceeded(limit) => {
+                write!(f, "Body exceeded the limit of {} bytes", limit)
+            }
+        }
+    }
+}
+
+/// A request body that is inspected by a [`Transaction`] as it streams through.
+///
+/// The transaction is shared so that it can be used for the remaining phases once the body has
+/// been consumed. See the [module documentation](self) for more details.
+///
+/// Bodies that are not [`Unpin`] can be wrapped with [`Box::pin`] first.
+pub struct InspectedBody<'a, T, B: RawBindings = Bindings> {
+    inner: T,
+    transaction: Arc<Mutex<Transaction<'a, B>>>,
+    limit: Option<usize>,
+    in_flight: usize,
+    finished: bool,
+}
+
+impl<'a, T, B: RawBindings> InspectedBody<'a, T, B> {
+    /// Wraps `inner` so that its data is inspected by `transaction`.
+    ///
+    /// The request headers are expected to have been processed already.
+    pub fn new(inner: T, transaction: Arc<Mutex<Transaction<'a, B>>>) -> Self {
+        Self {
+            inner,
+            transaction,
+            limit: None,
+            in_flight: 0,
+            finished: false,
+        }
+    }
+
+    /// Sets the maximum number of bytes that may be passed to the transaction.
+    ///
+    /// ModSecurity holds on to the body until it is processed at the end of the stream, so this
+    /// bounds the memory used per request. Once the limit is exceeded, the stream ends with an
+    /// [`InspectedBodyError::LimitExceeded`].
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Returns the transaction inspecting this body.
+    pub fn transaction(&self) -> &Arc<Mutex<Transaction<'a, B>>> {
+        &self.transaction
+    }
+
+    /// Returns the number of bytes passed to the transaction so far.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    /// Consumes the wrapper, returning the inner body.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Transaction<'a, B>> {
+        self.transaction
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn append(&mut self, data: &[u8]) -> Result<(), InspectedBodyError<T::Error, B>>
+    where
+        T: Body,
+    {
+        self.in_flight += data.len();
+
+        if let Some(limit) = self.limit.filter(|limit| self.in_flight > *limit) {
+            return Err(InspectedBodyError::LimitExceeded(limit));
+        }
+
+        self.inspect(data, false)
+    }
+
+    fn finish(&mut self) -> Result<(), InspectedBodyError<T::Error, B>>
+    where
+        T: Body,
+    {
+        self.inspect(&[], true)
+    }
+
+    fn inspect(
+        &self,
+        data: &[u8],
+        end_of_stream: bool,
+    ) -> Result<(), InspectedBodyError<T::Error, B>>
+    where
+        T: Body,
+    {
+        let disruption = self
+            .lock()
+            .inspect_request_body(data, end_of_stream)
+            .map_err(InspectedBodyError::ModSecurity)?;
+
+        match disruption {
+            Some(disruption) => Err(InspectedBodyError::Intervention(disruption)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<T, B> Body for InspectedBody<'_, T, B>
+where
+    T: Body + Unpin,
+    B: RawBindings,
+{
+    type Data = Bytes;
+    type Error = InspectedBodyError<T::Error, B>;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+
+        if this.finished {
+            return Poll::Ready(None);
+        }
+
+        // `std::task::ready!` is not used, as it requires Rust 1.64.
+        let polled = match Pin::new(&mut this.inner).poll_frame(cx) {
+            Poll::Ready(polled) => polled,
+            Poll::Pending => return Poll::Pending,
+        };
+
+        let result = match polled {
+            Some(Ok(frame)) => {
+                let frame = frame.map_data(|mut data| data.copy_to_bytes(data.remaining()));
+
+                match frame.data_ref() {
+                    Some(data) => this.append(data).map(|_| Some(frame)),
+                    None => Ok(Some(frame)),
+                }
+            }
+            Some(Err(err)) => Err(InspectedBodyError::Body(err)),
+            None => this.finish().map(|_| None),
+        };
+
+        match result {
+            Ok(Some(frame)) => Poll::Ready(Some(Ok(frame))),
+            Ok(None) => {
+                this.finished = true;
+                Poll::Ready(None)
+            }
+            Err(err) => {
+                this.finished = true;
+                Poll::Ready(Some(Err(err)))
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        // The end of the inner body still has to be observed to process the request body.
+        self.finished
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, io};
+
+    use http_body_util::{BodyExt, Full};
+
+    use super::*;
+    use crate::{msc::ModSecurity, rules::Rules, transaction::Phase};
+
+    struct TestBindings;
+
+    impl RawBindings for TestBindings {}
+
+    struct FallibleBindings;
+
+    impl RawBindings for FallibleBindings {
+        unsafe fn msc_append_request_body(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+            _body: *const std::os::raw::c_uchar,
+            _size: usize,
+        ) -> i32 {
+            0
+        }
+    }
+
+    /// A body that yields the given frames in order.
+    struct Frames(VecDeque<Result<Frame<Bytes>, io::Error>>);
+
+    impl Frames {
+        fn data(chunks: &[&'static str]) -> Self {
+            Self(
+                chunks
+                    .iter()
+                    .map(|chunk| Ok(Frame::data(Bytes::from(*chunk))))
+                    .collect(),
+            )
+        }
+    }
+
+    impl Body for Frames {
+        type Data = Bytes;
+        type Error = io::Error;
+
+        fn poll_frame(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+            Poll::Ready(self.0.pop_front())
+        }
+    }
+
+    fn shared<B: RawBindings>(transaction: Transaction<'_, B>) -> Arc<Mutex<Transaction<'_, B>>> {
+        Arc::new(Mutex::new(transaction))
+    }
+
+    fn rules() -> Rules<TestBindings> {
+        let mut rules = Rules::new();
+        rules
+            .add_plain(
+                r#"
+                SecRuleEngine On
+
+                SecRequestBodyAccess On
+
+                SecRule REQUEST_BODY "@rx attack" "id:1,phase:2,deny,status:403"
+            "#,
+            )
+            .unwrap();
+        rules
+    }
+
+    #[tokio::test]
+    async fn test_streams_chunks() {
+        let ms = ModSecurity::<TestBindings>::default();
+        let rules = rules();
+        let transaction = ms.transaction_builder().with_rules(&rules).build().unwrap();
+        let transaction = shared(transaction);
+
+        let mut body = InspectedBody::new(
+            Frames::data(&["hello, ", "world"]),
+            Arc::clone(&transaction),
+        );
+
+        let frame = body.frame().await.unwrap().unwrap();
+        assert_eq!(frame.into_data().unwrap(), "hello, ");
+        assert_eq!(body.in_flight(), 7);
+
+        let frame = body.frame().await.unwrap().unwrap();
+        assert_eq!(frame.into_data().unwrap(), "world");
+        assert_eq!(body.in_flight(), 12);
+
+        assert!(!body.is_end_stream());
+        assert!(body.frame().await.is_none());
+        assert!(body.is_end_stream());
+        assert!(body.frame().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_passes_trailers() {
+        let ms = ModSecurity::<TestBindings>::default();
+        let rules = rules();
+        let transaction = ms.transaction_builder().with_rules(&rules).build().unwrap();
+
+        let mut trailers = ::http::HeaderMap::new();
+        trailers.insert("x-checksum", "abc".parse().unwrap());
+
+        let mut frames = Frames::data(&["hello"]);
+        frames.0.push_back(Ok(Frame::trailers(trailers)));
+
+        let body = InspectedBody::new(frames, shared(transaction));
+        let collected = body.collect().await.unwrap();
+
+        assert_eq!(collected.trailers().unwrap()["x-checksum"], "abc");
+        assert_eq!(collected.to_bytes(), "hello");
+    }
+
+    #[tokio::test]
+    async fn test_intervention() {
+        let ms = ModSecurity::<TestBindings>::default();
+        let rules = rules();
+        let transaction = ms.transaction_builder().with_rules(&rules).build().unwrap();
+
+        let body = InspectedBody::new(Frames::data(&["an ", "attack"]), shared(transaction));
+
+        match body.collect().await {
+            Err(InspectedBodyError::Intervention(disruption)) => {
+                assert_eq!(disruption.phase, Phase::RequestBody);
+                assert_eq!(disruption.intervention.status(), 403);
+            }
+            _ => panic!("Expected intervention"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_limit() {
+        let ms = ModSecurity::<TestBindings>::default();
+        let rules = rules();
+        let transaction = ms.transaction_builder().with_rules(&rules).build().unwrap();
+
+        let mut body = InspectedBody::new(Frames::data(&["hello", "world"]), shared(transaction))
+            .with_limit(8);
+
+        assert!(body.frame().await.unwrap().is_ok());
+        assert!(matches!(
+            body.frame().await.unwrap(),
+            Err(InspectedBodyError::LimitExceeded(8))
+        ));
+        assert!(body.is_end_stream());
+        assert!(body.frame().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_body_error() {
+        let ms = ModSecurity::<TestBindings>::default();
+        let rules = rules();
+        let transaction = ms.transaction_builder().with_rules(&rules).build().unwrap();
+
+        let mut frames = Frames::data(&["hello"]);
+        frames
+            .0
+            .push_back(Err(io::Error::other("connection reset")));
+
+        let body = InspectedBody::new(frames, shared(transaction));
+        let err = body.collect().await.unwrap_err();
+
+        assert!(matches!(err, InspectedBodyError::Body(_)));
+        assert_eq!(err.to_string(), "Error reading body: connection reset");
+        assert!(err.source().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_append_failure() {
+        let ms = ModSecurity::<FallibleBindings>::default();
+        let rules = Rules::new();
+        let transaction = ms.transaction_builder().with_rules(&rules).build().unwrap();
+
+        let body = InspectedBody::new(Full::new(Bytes::from("hello")), shared(transaction));
+
+        assert!(matches!(
+            body.collect().await,
+            Err(InspectedBodyError::ModSecurity(
+                ModSecurityError::AppendRequestBody
+            ))
+        ));
+    }
+}
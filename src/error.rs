@@ -36,8 +36,16 @@ pub enum ModSecurityError {
     RulesAddFile(String),
     /// Error when adding plain rules to the rule set
     RulesAddPlain(String),
+    /// Error when adding remote rules to the rule set
+    RulesAddRemote(String),
+    /// Error when merging another rule set into the rule set
+    RulesMerge(String),
     /// Error when updating the status code
     UpdateStatusCode,
+    /// Error when setting the request hostname
+    SetRequestHostname,
+    /// Error when reading the request body from a file
+    RequestBodyFromFile,
 }
 
 impl Error for ModSecurityError {}
@@ -68,7 +76,17 @@ impl fmt::Display for ModSecurityError {
             ModSecurityError::RulesAddPlain(err) => {
                 write!(f, "Error adding plain rules to rule set: {}", err)
             }
+            ModSecurityError::RulesAddRemote(err) => {
+                write!(f, "Error adding remote rules to rule set: {}", err)
+            }
+            ModSecurityError::RulesMerge(err) => {
+                write!(f, "Error merging rule sets: {}", err)
+            }
             ModSecurityError::UpdateStatusCode => write!(f, "Error updating status code"),
+            ModSecurityError::SetRequestHostname => write!(f, "Error setting request hostname"),
+            ModSecurityError::RequestBodyFromFile => {
+                write!(f, "Error reading request body from file")
+            }
         }
     }
 }
@@ -0,0 +1,609 @@
+//! Integration with the [`http`](https://docs.rs/http) crate.
+//!
+//! This module is only available with the `http` feature enabled.
+
+use std::net::SocketAddr;
+
+use crate::{
+    bindings::{Bindings, RawBindings},
+    intervention::Intervention,
+    transaction::{Phase, Transaction},
+    ModSecurityResult,
+};
+
+/// The default limit on the size of request bodies, in bytes. This is the default of
+/// ModSecurity's `SecRequestBodyLimit`.
+pub const DEFAULT_REQUEST_BODY_LIMIT: usize = 13_107_200;
+
+/// The default limit on the size of response bodies that are inspected, in bytes. This is the
+/// default of ModSecurity's `SecResponseBodyLimit`.
+pub const DEFAULT_RESPONSE_BODY_LIMIT: usize = 524_288;
+
+/// A disruptive intervention, along with the phase that raised it.
+#[derive(Debug)]
+pub struct Disruption<B: RawBindings = Bindings> {
+    /// The phase that raised the intervention.
+    pub phase: Phase,
+    /// The intervention itself.
+    pub intervention: Intervention<B>,
+}
+
+/// Returns the HTTP version in the form expected by ModSecurity (e.g. `1.1`).
+pub(crate) fn version_str(version: ::http::Version) -> &'static str {
+    match version {
+        ::http::Version::HTTP_09 => "0.9",
+        ::http::Version::HTTP_10 => "1.0",
+        ::http::Version::HTTP_2 => "2.0",
+        ::http::Version::HTTP_3 => "3.0",
+        _ => "1.1",
+    }
+}
+
+/// Returns early with the current disruption, if any.
+macro_rules! check_phase {
+    ($transaction:expr, $phase:expr) => {
+        if let Some(disruption) = $transaction.disruption($phase) {
+            return Ok(Some(disruption));
+        }
+    };
+}
+
+impl<B: RawBindings> Intervention<B> {
+    /// Returns the status of the response to send for this intervention.
+    ///
+    /// This is the status of the intervention, or `403 Forbidden` if it is not a valid HTTP
+    /// status.
+    pub fn response_status(&self) -> ::http::StatusCode {
+        u16::try_from(self.status())
+            .ok()
+            .and_then(|status| ::http::StatusCode::from_u16(status).ok())
+            .unwrap_or(::http::StatusCode::FORBIDDEN)
+    }
+}
+
+impl<B: RawBindings> Transaction<'_, B> {
+    /// Runs the request side of the transaction for `request`.
+    ///
+    /// This processes the connection, URI, request headers and request body in order, stopping
+    /// at the first phase that raises a disruptive intervention. The intervention is returned
+    /// along with the phase that raised it.
+    ///
+    /// `peer` is the address of the client and `local` the address the request was received on.
+    ///
+    /// ## Examples
+    ///
+    /// ```
+    /// use modsecurity::{transaction::Phase, ModSecurity, Rules};
+    ///
+    /// let ms = ModSecurity::default();
+    ///
+    /// let mut rules = Rules::new();
+    /// rules.add_plain(r#"
+    ///     SecRuleEngine On
+    ///
+    ///     SecRule REQUEST_URI "@rx admin" "id:1,phase:1,deny,status:401"
+    /// "#).expect("Failed to add rules");
+    ///
+    /// let mut transaction = ms
+    ///     .transaction_builder()
+    ///     .with_rules(&rules)
+    ///     .build()
+    ///     .expect("Error building transaction");
+    ///
+    /// let request = http::Request::get("/admin").body(Vec::new()).unwrap();
+    ///
+    /// let disruption = transaction
+    ///     .inspect_request(
+    ///         &request,
+    ///         "127.0.0.1:12345".parse().unwrap(),
+    ///         "127.0.0.1:80".parse().unwrap(),
+    ///     )
+    ///     .expect("Error inspecting request")
+    ///     .expect("Expected disruption");
+    ///
+    /// assert_eq!(disruption.phase, Phase::RequestHeaders);
+    /// assert_eq!(disruption.intervention.status(), 401);
+    /// ```
+    pub fn inspect_request<T: AsRef<[u8]>>(
+        &mut self,
+        request: &::http::Request<T>,
+        peer: SocketAddr,
+        local: SocketAddr,
+    ) -> ModSecurityResult<Option<Disruption<B>>> {
+        let disruption = self.inspect_request_headers(
+            peer,
+            local,
+            request.method().as_str(),
+            &request.uri().to_string(),
+            version_str(request.version()),
+            request
+                .headers()
+                .iter()
+                .map(|(key, value)| (key.as_str().as_bytes(), value.as_bytes())),
+        )?;
+
+        match disruption {
+            Some(disruption) => Ok(Some(disruption)),
+            None => self.inspect_request_body(request.body().as_ref(), true),
+        }
+    }
+
+    /// Runs the part of the request side of the transaction that comes before the body.
+    ///
+    /// This processes the connection, URI and request headers in order, stopping at the first
+    /// phase that raises a disruptive intervention. It is meant for servers that receive the
+    /// body separately, e.g. as a stream, which is then passed to
+    /// [`Transaction::inspect_request_body()`].
+    ///
+    /// `version` is the HTTP version in the form expected by ModSecurity, e.g. `1.1`.
+    pub fn inspect_request_headers<'h, I>(
+        &mut self,
+        peer: SocketAddr,
+        local: SocketAddr,
+        method: &str,
+        uri: &str,
+        version: &str,
+        headers: I,
+    ) -> ModSecurityResult<Option<Disruption<B>>>
+    where
+        I: IntoIterator<Item = (&'h [u8], &'h [u8])>,
+    {
+        self.process_connection(
+            &peer.ip().to_string(),
+            peer.port().into(),
+            &local.ip().to_string(),
+            local.port().into(),
+        )?;
+        check_phase!(self, Phase::Connection);
+
+        self.process_uri(uri, method, version)?;
+        check_phase!(self, Phase::Uri);
+
+        for (key, value) in headers {
+            self.add_request_header_bytes(key, value)?;
+        }
+        self.process_request_headers()?;
+        check_phase!(self, Phase::RequestHeaders);
+
+        Ok(None)
+    }
+
+    /// Appends `chunk` to the request body, and processes the body if `end_of_stream` is set.
+    ///
+    /// Returns the first disruptive intervention raised, if any.
+    pub fn inspect_request_body(
+        &mut self,
+        chunk: &[u8],
+        end_of_stream: bool,
+    ) -> ModSecurityResult<Option<Disruption<B>>> {
+        self.append_request_body(chunk)?;
+        check_phase!(self, Phase::RequestBody);
+
+        if end_of_stream {
+            self.process_request_body()?;
+            check_phase!(self, Phase::RequestBody);
+        }
+
+        Ok(None)
+    }
+
+    /// Runs the response side of the transaction for `response`.
+    ///
+    /// This processes the response headers and response body in order, stopping at the first
+    /// phase that raises a disruptive intervention. The intervention is returned along with the
+    /// phase that raised it. The status code and protocol are taken from `response`.
+    ///
+    /// This is usually called after [`Transaction::inspect_request()`].
+    ///
+    /// ## Examples
+    ///
+    /// ```
+    /// use modsecurity::{transaction::Phase, ModSecurity, Rules};
+    ///
+    /// let ms = ModSecurity::default();
+    ///
+    /// let mut rules = Rules::new();
+    /// rules.add_plain(r#"
+    ///     SecRuleEngine On
+    ///
+    ///     SecRule RESPONSE_HEADERS:X-Secret-Key "@streq leaked-secret-key" "id:1,phase:3,deny,status:500"
+    /// "#).expect("Failed to add rules");
+    ///
+    /// let mut transaction = ms
+    ///     .transaction_builder()
+    ///     .with_rules(&rules)
+    ///     .build()
+    ///     .expect("Error building transaction");
+    ///
+    /// let response = http::Response::builder()
+    ///     .header("X-Secret-Key", "leaked-secret-key")
+    ///     .body(Vec::new())
+    ///     .unwrap();
+    ///
+    /// let disruption = transaction
+    ///     .inspect_response(&response)
+    ///     .expect("Error inspecting response")
+    ///     .expect("Expected disruption");
+    ///
+    /// assert_eq!(disruption.phase, Phase::ResponseHeaders);
+    /// assert_eq!(disruption.intervention.status(), 500);
+    /// ```
+    pub fn inspect_response<T: AsRef<[u8]>>(
+        &mut self,
+        response: &::http::Response<T>,
+    ) -> ModSecurityResult<Option<Disruption<B>>> {
+        let disruption = self.inspect_response_headers(
+            response.status().as_u16(),
+            version_str(response.version()),
+            response
+                .headers()
+                .iter()
+                .map(|(key, value)| (key.as_str().as_bytes(), value.as_bytes())),
+        )?;
+
+        match disruption {
+            Some(disruption) => Ok(Some(disruption)),
+            None => self.inspect_response_body(response.body().as_ref(), true),
+        }
+    }
+
+    /// Runs the response headers phase, for servers that receive the response body separately.
+    /// See [`Transaction::inspect_request_headers()`].
+    ///
+    /// `version` is the HTTP version in the form expected by ModSecurity, e.g. `1.1`.
+    pub fn inspect_response_headers<'h, I>(
+        &mut self,
+        status: u16,
+        version: &str,
+        headers: I,
+    ) -> ModSecurityResult<Option<Disruption<B>>>
+    where
+        I: IntoIterator<Item = (&'h [u8], &'h [u8])>,
+    {
+        for (key, value) in headers {
+            self.add_response_header_bytes(key, value)?;
+        }
+        self.process_response_headers(status.into(), &format!("HTTP {}", version))?;
+        check_phase!(self, Phase::ResponseHeaders);
+
+        Ok(None)
+    }
+
+    /// Appends `chunk` to the response body, and processes the body if `end_of_stream` is set.
+    ///
+    /// Returns the first disruptive intervention raised, if any.
+    pub fn inspect_response_body(
+        &mut self,
+        chunk: &[u8],
+        end_of_stream: bool,
+    ) -> ModSecurityResult<Option<Disruption<B>>> {
+        self.append_response_body(chunk)?;
+        check_phase!(self, Phase::ResponseBody);
+
+        if end_of_stream {
+            self.process_response_body()?;
+            check_phase!(self, Phase::ResponseBody);
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the current intervention if it is disruptive, along with `phase`, the phase that
+    /// was last processed.
+    ///
+    /// Non-disruptive interventions (e.g. from rules that only log) are discarded, and `None` is
+    /// returned for them.
+    pub fn disruption(&mut self, phase: Phase) -> Option<Disruption<B>> {
+        self.intervention()
+            .filter(|intervention| intervention.disruptive())
+            .map(|intervention| Disruption {
+                phase,
+                intervention,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{msc::ModSecurity, rules::Rules, ModSecurityError};
+
+    struct TestBindings;
+
+    impl RawBindings for TestBindings {}
+
+    struct FallibleBindings;
+
+    impl RawBindings for FallibleBindings {
+        unsafe fn msc_process_connection(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+            _client: *const std::os::raw::c_char,
+            _c_port: i32,
+            _server: *const std::os::raw::c_char,
+            _s_port: i32,
+        ) -> i32 {
+            0
+        }
+
+        unsafe fn msc_process_response_headers(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+            _code: i32,
+            _protocol: *const std::os::raw::c_char,
+        ) -> i32 {
+            0
+        }
+    }
+
+    fn peer() -> SocketAddr {
+        "124.123.122.121:12345".parse().unwrap()
+    }
+
+    fn local() -> SocketAddr {
+        "127.0.0.1:80".parse().unwrap()
+    }
+
+    #[test]
+    fn test_version_str() {
+        assert_eq!(version_str(::http::Version::HTTP_09), "0.9");
+        assert_eq!(version_str(::http::Version::HTTP_10), "1.0");
+        assert_eq!(version_str(::http::Version::HTTP_11), "1.1");
+        assert_eq!(version_str(::http::Version::HTTP_2), "2.0");
+        assert_eq!(version_str(::http::Version::HTTP_3), "3.0");
+    }
+
+    #[test]
+    fn test_response_status() {
+        let intervention = |status| {
+            Intervention::<TestBindings>::new(crate::bindings::types::ModSecurityIntervention_t {
+                status,
+                pause: 0,
+                url: std::ptr::null_mut(),
+                log: std::ptr::null_mut(),
+                disruptive: 1,
+            })
+        };
+
+        assert_eq!(intervention(401).response_status(), 401);
+        assert_eq!(intervention(0).response_status(), 403);
+        assert_eq!(intervention(-1).response_status(), 403);
+        assert_eq!(intervention(1000).response_status(), 403);
+    }
+
+    #[test]
+    fn test_inspect_request_allowed() {
+        let ms = ModSecurity::<TestBindings>::default();
+        let mut rules = Rules::new();
+        rules
+            .add_plain(
+                r#"
+                SecRuleEngine On
+
+                SecRule REQUEST_URI "@rx admin" "id:1,phase:1,deny,status:401"
+            "#,
+            )
+            .unwrap();
+
+        let mut transaction = ms.transaction_builder().with_rules(&rules).build().unwrap();
+
+        let request = ::http::Request::get("/index.html")
+            .header("Host", "example.com")
+            .body(b"hello".to_vec())
+            .unwrap();
+
+        assert!(transaction
+            .inspect_request(&request, peer(), local())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_inspect_request_headers() {
+        let ms = ModSecurity::<TestBindings>::default();
+        let mut rules = Rules::new();
+        rules
+            .add_plain(
+                r#"
+                SecRuleEngine On
+
+                SecRule REQUEST_HEADERS:X-Client-Port "@streq 22" "id:1,phase:1,deny,status:403"
+            "#,
+            )
+            .unwrap();
+
+        let mut transaction = ms.transaction_builder().with_rules(&rules).build().unwrap();
+
+        let request = ::http::Request::get("/")
+            .header("X-Client-Port", "22")
+            .body("")
+            .unwrap();
+
+        let disruption = transaction
+            .inspect_request(&request, peer(), local())
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(disruption.phase, Phase::RequestHeaders);
+        assert_eq!(disruption.intervention.status(), 403);
+    }
+
+    #[test]
+    fn test_inspect_request_body() {
+        let ms = ModSecurity::<TestBindings>::default();
+        let mut rules = Rules::new();
+        rules
+            .add_plain(
+                r#"
+                SecRuleEngine On
+
+                SecRequestBodyAccess On
+
+                SecRule REQUEST_BODY "@rx attack" "id:1,phase:2,deny,status:403"
+            "#,
+            )
+            .unwrap();
+
+        let mut transaction = ms.transaction_builder().with_rules(&rules).build().unwrap();
+
+        let request = ::http::Request::post("/")
+            .body(b"an attack".as_slice())
+            .unwrap();
+
+        let disruption = transaction
+            .inspect_request(&request, peer(), local())
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(disruption.phase, Phase::RequestBody);
+    }
+
+    #[test]
+    fn test_inspect_request_body_chunks() {
+        let ms = ModSecurity::<TestBindings>::default();
+        let mut rules = Rules::new();
+        rules
+            .add_plain(
+                r#"
+                SecRuleEngine On
+
+                SecRequestBodyAccess On
+
+                SecRule REQUEST_BODY "@rx attack" "id:1,phase:2,deny,status:403"
+            "#,
+            )
+            .unwrap();
+
+        let mut transaction = ms.transaction_builder().with_rules(&rules).build().unwrap();
+
+        assert!(transaction
+            .inspect_request_headers(
+                peer(),
+                local(),
+                "POST",
+                "/",
+                "1.1",
+                [(b"Content-Type".as_slice(), b"text/plain".as_slice())],
+            )
+            .unwrap()
+            .is_none());
+
+        assert!(transaction
+            .inspect_request_body(b"an att", false)
+            .unwrap()
+            .is_none());
+
+        let disruption = transaction
+            .inspect_request_body(b"ack", true)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(disruption.phase, Phase::RequestBody);
+        assert_eq!(disruption.intervention.status(), 403);
+    }
+
+    #[test]
+    fn test_inspect_request_failure() {
+        let ms = ModSecurity::<FallibleBindings>::default();
+        let rules = Rules::new();
+
+        let mut transaction = ms.transaction_builder().with_rules(&rules).build().unwrap();
+
+        let request = ::http::Request::get("/").body("").unwrap();
+
+        assert!(matches!(
+            transaction.inspect_request(&request, peer(), local()),
+            Err(ModSecurityError::ProcessConnection)
+        ));
+    }
+
+    #[test]
+    fn test_inspect_response_allowed() {
+        let ms = ModSecurity::<TestBindings>::default();
+        let mut rules = Rules::new();
+        rules
+            .add_plain(
+                r#"
+                SecRuleEngine On
+
+                SecResponseBodyAccess On
+
+                SecRule RESPONSE_BODY "@rx secret" "id:1,phase:4,deny,status:500"
+            "#,
+            )
+            .unwrap();
+
+        let mut transaction = ms.transaction_builder().with_rules(&rules).build().unwrap();
+
+        let response = ::http::Response::builder()
+            .header("Content-Type", "text/plain")
+            .body(b"hello".to_vec())
+            .unwrap();
+
+        assert!(transaction.inspect_response(&response).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_inspect_response_status() {
+        let ms = ModSecurity::<TestBindings>::default();
+        let mut rules = Rules::new();
+        rules
+            .add_plain(
+                r#"
+                SecRuleEngine On
+
+                SecRule RESPONSE_STATUS "@streq 418" "id:1,phase:3,deny,status:500"
+            "#,
+            )
+            .unwrap();
+
+        let mut transaction = ms.transaction_builder().with_rules(&rules).build().unwrap();
+
+        let response = ::http::Response::builder().status(418).body("").unwrap();
+
+        let disruption = transaction.inspect_response(&response).unwrap().unwrap();
+
+        assert_eq!(disruption.phase, Phase::ResponseHeaders);
+        assert_eq!(disruption.intervention.status(), 500);
+    }
+
+    #[test]
+    fn test_inspect_response_body() {
+        let ms = ModSecurity::<TestBindings>::default();
+        let mut rules = Rules::new();
+        rules
+            .add_plain(
+                r#"
+                SecRuleEngine On
+
+                SecResponseBodyAccess On
+
+                SecRule RESPONSE_BODY "@rx secret" "id:1,phase:4,deny,status:500"
+            "#,
+            )
+            .unwrap();
+
+        let mut transaction = ms.transaction_builder().with_rules(&rules).build().unwrap();
+
+        let response = ::http::Response::builder()
+            .body(b"a secret".as_slice())
+            .unwrap();
+
+        let disruption = transaction.inspect_response(&response).unwrap().unwrap();
+
+        assert_eq!(disruption.phase, Phase::ResponseBody);
+    }
+
+    #[test]
+    fn test_inspect_response_failure() {
+        let ms = ModSecurity::<FallibleBindings>::default();
+        let rules = Rules::new();
+
+        let mut transaction = ms.transaction_builder().with_rules(&rules).build().unwrap();
+
+        let response = ::http::Response::builder().body("").unwrap();
+
+        assert!(matches!(
+            transaction.inspect_response(&response),
+            Err(ModSecurityError::ProcessResponseHeaders)
+        ));
+    }
+}
@@ -1,6 +1,9 @@
 //! Intervention related types and methods.
 
-use crate::bindings::{types::ModSecurityIntervention_t, Bindings, RawBindings};
+use crate::{
+    bindings::{types::ModSecurityIntervention_t, Bindings, RawBindings},
+    rule_match::RuleMatch,
+};
 use std::{ffi::CStr, fmt::Debug, marker::PhantomData};
 
 /// Represents an intervention from ModSecurity.
@@ -63,6 +66,11 @@ impl<B: RawBindings> Intervention<B> {
     pub fn disruptive(&self) -> bool {
         self.inner.disruptive != 0
     }
+
+    /// Parses the log message, if any, into a [`RuleMatch`].
+    pub fn rule_match(&self) -> Option<RuleMatch> {
+        self.log().and_then(RuleMatch::parse)
+    }
 }
 
 impl<B: RawBindings> Drop for Intervention<B> {
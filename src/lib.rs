@@ -46,17 +46,34 @@
 
 #![deny(missing_docs)]
 
+#[cfg(feature = "actix")]
+pub mod actix;
+#[cfg(feature = "async")]
+pub mod asynchronous;
 #[doc(hidden)]
 pub mod bindings;
 
+#[cfg(feature = "http-body")]
+pub mod body;
 pub mod error;
+#[cfg(feature = "http")]
+pub mod http;
 pub mod intervention;
+#[cfg(feature = "log")]
+mod logging;
 pub mod msc;
+pub mod phased;
+pub mod rule_match;
 pub mod rules;
+#[cfg(feature = "tower")]
+pub mod tower;
+#[cfg(feature = "tracing")]
+mod trace;
 pub mod transaction;
 
 pub use error::ModSecurityError;
 pub use intervention::Intervention;
+pub use rule_match::RuleMatch;
 
 /// Common result for a ModSecurity operation.
 pub type ModSecurityResult<T> = Result<T, ModSecurityError>;
@@ -67,8 +84,14 @@ pub type ModSecurityResult<T> = Result<T, ModSecurityError>;
 /// See [`transaction::Transaction`].
 pub type Transaction<'a> = transaction::Transaction<'a>;
 
+/// See [`transaction::OwnedTransaction`].
+pub type OwnedTransaction = transaction::OwnedTransaction;
+
 /// See [`msc::ModSecurity`].
 pub type ModSecurity = msc::ModSecurity;
 
 /// See [`rules::Rules`].
 pub type Rules = rules::Rules;
+
+/// See [`rules::ReloadableRules`].
+pub type ReloadableRules = rules::ReloadableRules;
@@ -0,0 +1,248 @@
+//! Forwarding of log messages to the `log` facade.
+//!
+//! Transactions that are not given a logging callback use [`log_callback()`], which logs each
+//! message at the level of the severity of the rule that matched. The target is
+//! `modsecurity::rule::<id>` for rule matches, and `modsecurity` for other messages, so logs can
+//! be filtered by rule.
+//!
+//! libmodsecurity reports rules that do not set a severity as `EMERGENCY`, so their messages are
+//! logged at the error level as well.
+
+use log::Level;
+
+use crate::{
+    rule_match::{RuleMatch, Severity},
+    transaction::BytesLogCallback,
+};
+
+const TARGET: &str = "modsecurity";
+
+/// Returns the level that messages of rules with `severity` are logged at.
+///
+/// Rules that do not set a severity are reported as [`Severity::Emergency`] (see
+/// [`RuleMatch::severity`]), so they are logged at the error level. Other messages are logged at
+/// the info level.
+fn level(severity: Option<Severity>) -> Level {
+    match severity {
+        Some(Severity::Emergency | Severity::Alert | Severity::Critical | Severity::Error) => {
+            Level::Error
+        }
+        Some(Severity::Warning) => Level::Warn,
+        Some(Severity::Notice | Severity::Info) | None => Level::Info,
+        Some(Severity::Debug) => Level::Debug,
+    }
+}
+
+/// Returns the log callback that transactions use by default.
+pub(crate) fn log_callback() -> BytesLogCallback {
+    Box::new(|msg| {
+        let msg = match msg {
+            Some(msg) => msg,
+            None => return,
+        };
+
+        // The target and level of a message are only known once it has been parsed, so parsing
+        // is only skipped when logging is disabled. Other filters are left to the logger.
+        if log::max_level() == log::LevelFilter::Off {
+            return;
+        }
+
+        let msg = String::from_utf8_lossy(msg);
+
+        match RuleMatch::parse(&msg) {
+            Some(RuleMatch {
+                id: Some(id),
+                severity,
+                ..
+            }) => {
+                let target = format!("{}::rule::{}", TARGET, id);
+                log::log!(target: &target, level(severity), "{}", msg);
+            }
+            rule_match => log::log!(
+                target: TARGET,
+                level(rule_match.and_then(|rule_match| rule_match.severity)),
+                "{}",
+                msg
+            ),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Mutex, Once};
+
+    use log::{Log, Metadata, Record};
+
+    use super::*;
+
+    /// A logger that records every message, as `(level, target, message)`.
+    struct Recorder(Mutex<Vec<(Level, String, String)>>);
+
+    impl Log for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record<'_>) {
+            self.0.lock().unwrap().push((
+                record.level(),
+                record.target().to_string(),
+                record.args().to_string(),
+            ));
+        }
+
+        fn flush(&self) {}
+    }
+
+    static RECORDER: Recorder = Recorder(Mutex::new(Vec::new()));
+
+    /// Installs the recording logger, if it is not installed yet.
+    fn init() {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&RECORDER).unwrap();
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+    }
+
+    /// Returns what was logged with `target`, as `(level, message)`.
+    fn logged(target: &str) -> Vec<(Level, String)> {
+        RECORDER
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, logged, _)| logged == target)
+            .map(|(level, _, msg)| (*level, msg.clone()))
+            .collect()
+    }
+
+    /// Passes `msg` to the default log callback, returning what was logged for it.
+    fn log(msg: &[u8]) -> Vec<(Level, String, String)> {
+        init();
+
+        log_callback()(Some(msg));
+
+        let expected = String::from_utf8_lossy(msg);
+        RECORDER
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, _, logged)| *logged == expected)
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn test_level() {
+        let cases = [
+            (Some(Severity::Emergency), Level::Error),
+            (Some(Severity::Alert), Level::Error),
+            (Some(Severity::Critical), Level::Error),
+            (Some(Severity::Error), Level::Error),
+            (Some(Severity::Warning), Level::Warn),
+            (Some(Severity::Notice), Level::Info),
+            (Some(Severity::Info), Level::Info),
+            (Some(Severity::Debug), Level::Debug),
+            (None, Level::Info),
+        ];
+
+        for (severity, expected) in cases {
+            assert_eq!(level(severity), expected, "{:?}", severity);
+        }
+    }
+
+    #[test]
+    fn test_log_callback() {
+        assert_eq!(
+            log(b"ModSecurity: Warning. Matched [id \"942100\"] [severity \"2\"]"),
+            vec![(
+                Level::Error,
+                "modsecurity::rule::942100".to_string(),
+                "ModSecurity: Warning. Matched [id \"942100\"] [severity \"2\"]".to_string()
+            )]
+        );
+
+        assert_eq!(
+            log(b"ModSecurity: Warning. Matched \xe9 [severity \"7\"]"),
+            vec![(
+                Level::Debug,
+                "modsecurity".to_string(),
+                "ModSecurity: Warning. Matched \u{fffd} [severity \"7\"]".to_string()
+            )]
+        );
+
+        // Rules that do not set a severity are reported with a severity of 0, i.e. EMERGENCY.
+        assert_eq!(
+            log(b"ModSecurity: Warning. Matched [id \"949110\"] [severity \"0\"]"),
+            vec![(
+                Level::Error,
+                "modsecurity::rule::949110".to_string(),
+                "ModSecurity: Warning. Matched [id \"949110\"] [severity \"0\"]".to_string()
+            )]
+        );
+
+        // Fields in the matched value do not change the level or target.
+        assert_eq!(
+            log(b"ModSecurity: Warning. Matched \"Operator `Rx' with parameter `x' against variable `ARGS:q' (Value: `[id \"1\"] [severity \"2\"]' ) [file \"rules.conf\"] [line \"3\"] [id \"5\"] [severity \"4\"]"),
+            vec![(
+                Level::Warn,
+                "modsecurity::rule::5".to_string(),
+                "ModSecurity: Warning. Matched \"Operator `Rx' with parameter `x' against variable `ARGS:q' (Value: `[id \"1\"] [severity \"2\"]' ) [file \"rules.conf\"] [line \"3\"] [id \"5\"] [severity \"4\"]".to_string()
+            )]
+        );
+
+        assert_eq!(
+            log(b"Some other message"),
+            vec![(
+                Level::Info,
+                "modsecurity".to_string(),
+                "Some other message".to_string()
+            )]
+        );
+
+        // The end of the messages is not logged.
+        log_callback()(None);
+    }
+
+    #[test]
+    fn test_log_callback_transaction() {
+        init();
+
+        let ms = crate::ModSecurity::builder().with_log_callbacks().build();
+        let mut rules = crate::Rules::new();
+        rules
+            .add_plain(
+                r#"
+                SecRuleEngine On
+
+                SecRule ARGS:q "@streq emergency" "id:2501,phase:1,log,pass,t:none,severity:'EMERGENCY'"
+                SecRule ARGS:q "@streq emergency" "id:2502,phase:1,log,pass,t:none"
+                SecRule ARGS:q "@streq emergency" "id:2503,phase:1,log,pass,t:none,severity:'CRITICAL'"
+            "#,
+            )
+            .unwrap();
+
+        let mut transaction = ms.transaction_builder().with_rules(&rules).build().unwrap();
+
+        transaction
+            .process_uri("/?q=emergency", "GET", "1.1")
+            .unwrap();
+        transaction.process_request_headers().unwrap();
+
+        // Rules without a severity are reported with the same severity as EMERGENCY rules, so
+        // both are logged at the error level.
+        let levels = |target| {
+            logged(target)
+                .into_iter()
+                .map(|(level, _)| level)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(levels("modsecurity::rule::2501"), vec![Level::Error]);
+        assert_eq!(levels("modsecurity::rule::2502"), vec![Level::Error]);
+        assert_eq!(levels("modsecurity::rule::2503"), vec![Level::Error]);
+    }
+}
@@ -1,12 +1,16 @@
 //! ModSecurity instance and builder.
 
 use lazy_static::lazy_static;
-use std::sync::Mutex;
-use std::{ffi::CStr, marker::PhantomData};
+use std::sync::{Arc, Mutex};
+use std::{
+    ffi::CStr,
+    marker::PhantomData,
+    panic::{self, AssertUnwindSafe},
+};
 
 use crate::bindings::{types::ModSecurity_t, Bindings, RawBindings};
 
-use crate::transaction::TransactionBuilderWithoutRules;
+use crate::transaction::{BytesLogCallback, TransactionBuilderWithoutRules};
 use crate::ModSecurityResult;
 
 lazy_static! {
@@ -39,6 +43,12 @@ impl<B: RawBindings> ModSecurityBuilder<B> {
 
     /// Enables log callbacks on the ModSecurity instance. The callbacks themselves are specified when
     /// creating a [`crate::transaction::Transaction`].
+    ///
+    /// A panic in a callback cannot unwind into libmodsecurity, so it is caught and discarded.
+    ///
+    /// With the `log` feature, transactions that are not given a callback forward their log
+    /// messages to the `log` facade, with the target `modsecurity::rule::<id>` and a level based
+    /// on the severity of the rule.
     pub fn with_log_callbacks(mut self) -> Self {
         self.msc.enable_log_callbacks();
         self
@@ -115,6 +125,35 @@ impl<B: RawBindings> ModSecurity<B> {
         TransactionBuilderWithoutRules::new(self)
     }
 
+    /// Creates a new transaction builder from a shared instance.
+    ///
+    /// Transactions created this way keep the instance alive for as long as they exist, rather
+    /// than borrowing it. See [`crate::transaction::OwnedTransaction`].
+    ///
+    /// ## Examples
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    ///
+    /// use modsecurity::{ModSecurity, Rules};
+    ///
+    /// let ms = Arc::new(ModSecurity::default());
+    /// let rules = Arc::new(Rules::new());
+    /// let transaction = ms
+    ///     .shared_transaction_builder()
+    ///     .with_shared_rules(rules)
+    ///     .build()
+    ///     .expect("error building transaction");
+    /// ```
+    pub fn shared_transaction_builder(
+        self: &Arc<Self>,
+    ) -> TransactionBuilderWithoutRules<'static, B>
+    where
+        B: 'static,
+    {
+        TransactionBuilderWithoutRules::new_shared(Arc::clone(self))
+    }
+
     /// Returns information about this ModSecurity version and platform.
     ///
     /// ## Examples
@@ -147,17 +186,22 @@ impl<B: RawBindings> ModSecurity<B> {
             cb: *mut std::os::raw::c_void,
             msg: *const ::std::os::raw::c_void,
         ) {
+            if cb.is_null() {
+                return;
+            }
+
             let data = msg as *const std::os::raw::c_char;
-            let c_str = if data.is_null() {
+            let bytes = if data.is_null() {
                 None
             } else {
-                Some(unsafe { CStr::from_ptr(data) })
+                Some(unsafe { CStr::from_ptr(data) }.to_bytes())
             };
-            let str_slice = c_str.map(|s| s.to_str().expect("Invalid UTF-8 string"));
-            if !cb.is_null() {
-                let cb = cb as *const *const (dyn Fn(Option<&str>) + Send + Sync + 'static);
-                (**cb)(str_slice);
-            }
+
+            let cb = cb as *const BytesLogCallback;
+
+            // Unwinding out of an `extern "C"` function aborts the process, so a panicking
+            // callback is caught here and its panic discarded.
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| unsafe { (*cb)(bytes) }));
         }
 
         unsafe {
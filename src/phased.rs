@@ -0,0 +1,572 @@
+//! A transaction API that enforces the order of phases at compile time.
+//!
+//! A [`PhasedTransaction`] wraps a [`Transaction`] and only exposes the methods that are valid in
+//! its current state. Each phase method consumes the transaction and returns an [`Outcome`]
+//! which either continues to the next state or carries the [`Intervention`] raised by that
+//! phase, so an intervention cannot be missed.
+//!
+//! The states, in order, are [`state::Connection`], [`state::Uri`], [`state::RequestHeaders`],
+//! [`state::RequestBody`], [`state::ResponseHeaders`], [`state::ResponseBody`] and
+//! [`state::Logging`].
+//!
+//! ## Examples
+//!
+//! ```
+//! use modsecurity::{phased::Outcome, ModSecurity, Rules};
+//!
+//! let ms = ModSecurity::default();
+//!
+//! let mut rules = Rules::new();
+//! rules.add_plain(r#"
+//!     SecRuleEngine On
+//!
+//!     SecRule REQUEST_URI "@rx admin" "id:1,phase:1,deny,status:401"
+//! "#).expect("Failed to add rules");
+//!
+//! let transaction = ms
+//!     .transaction_builder()
+//!     .with_rules(&rules)
+//!     .build_phased()
+//!     .expect("Error building transaction");
+//!
+//! let transaction = match transaction
+//!     .process_connection("127.0.0.1", 12345, "127.0.0.1", 80)
+//!     .expect("Error processing connection")
+//! {
+//!     Outcome::Continue(transaction) => transaction,
+//!     Outcome::Intervention { .. } => unreachable!(),
+//! };
+//!
+//! let mut transaction = match transaction
+//!     .process_uri("http://example.com/admin", "GET", "1.1")
+//!     .expect("Error processing URI")
+//! {
+//!     Outcome::Continue(transaction) => transaction,
+//!     Outcome::Intervention { .. } => unreachable!(),
+//! };
+//!
+//! transaction.add_request_header("Host", "example.com").expect("Error adding request header");
+//!
+//! match transaction.process_request_headers().expect("Error processing request headers") {
+//!     Outcome::Continue(_) => panic!("Expected intervention"),
+//!     Outcome::Intervention { intervention, transaction } => {
+//!         assert_eq!(intervention.status(), 401);
+//!         transaction.process_logging().expect("Error processing logging");
+//!     }
+//! };
+//! ```
+//!
+//! Calling phases out of order does not compile:
+//!
+//! ```compile_fail
+//! use modsecurity::{ModSecurity, Rules};
+//!
+//! let ms = ModSecurity::default();
+//! let rules = Rules::new();
+//!
+//! let transaction = ms.transaction_builder().with_rules(&rules).build_phased().unwrap();
+//!
+//! transaction.process_request_body();
+//! ```
+
+use std::marker::PhantomData;
+
+use crate::{
+    bindings::{Bindings, RawBindings},
+    intervention::Intervention,
+    transaction::Transaction,
+    ModSecurityResult,
+};
+
+/// The states of a [`PhasedTransaction`]. Each state is named after the phase that is run next.
+pub mod state {
+    /// The connection is processed next.
+    pub struct Connection;
+    /// The URI is processed next.
+    pub struct Uri;
+    /// Request headers are added and processed next.
+    pub struct RequestHeaders;
+    /// The request body is appended and processed next.
+    pub struct RequestBody;
+    /// Response headers are added and processed next.
+    pub struct ResponseHeaders;
+    /// The response body is appended and processed next.
+    pub struct ResponseBody;
+    /// The logging phase is processed next.
+    pub struct Logging;
+}
+
+/// The result of running a phase on a [`PhasedTransaction`].
+pub enum Outcome<'a, S, B: RawBindings = Bindings> {
+    /// No intervention was raised. The transaction can move on to state `S`.
+    Continue(PhasedTransaction<'a, S, B>),
+    /// An intervention was raised. The transaction can only move on to the logging phase.
+    Intervention {
+        /// The intervention raised by the phase.
+        intervention: Intervention<B>,
+        /// The transaction, ready for the logging phase.
+        transaction: PhasedTransaction<'a, state::Logging, B>,
+    },
+}
+
+/// A [`Transaction`] that is in state `S`. See the [module documentation](self).
+pub struct PhasedTransaction<'a, S, B: RawBindings = Bindings> {
+    transaction: Transaction<'a, B>,
+    _state: PhantomData<S>,
+}
+
+impl<'a, B: RawBindings> PhasedTransaction<'a, state::Connection, B> {
+    pub(crate) fn new(transaction: Transaction<'a, B>) -> Self {
+        Self {
+            transaction,
+            _state: PhantomData,
+        }
+    }
+
+    /// See [`Transaction::process_connection()`].
+    pub fn process_connection(
+        mut self,
+        client: &str,
+        c_port: i32,
+        server: &str,
+        s_port: i32,
+    ) -> ModSecurityResult<Outcome<'a, state::Uri, B>> {
+        self.transaction
+            .process_connection(client, c_port, server, s_port)?;
+        Ok(self.outcome())
+    }
+}
+
+impl<'a, B: RawBindings> PhasedTransaction<'a, state::Uri, B> {
+    /// See [`Transaction::process_uri()`].
+    pub fn process_uri(
+        mut self,
+        uri: &str,
+        method: &str,
+        http_version: &str,
+    ) -> ModSecurityResult<Outcome<'a, state::RequestHeaders, B>> {
+        self.transaction.process_uri(uri, method, http_version)?;
+        Ok(self.outcome())
+    }
+}
+
+impl<'a, B: RawBindings> PhasedTransaction<'a, state::RequestHeaders, B> {
+    /// See [`Transaction::add_request_header()`].
+    pub fn add_request_header(&mut self, key: &str, value: &str) -> ModSecurityResult<()> {
+        self.transaction.add_request_header(key, value)
+    }
+
+    /// See [`Transaction::add_request_header_bytes()`].
+    pub fn add_request_header_bytes(&mut self, key: &[u8], value: &[u8]) -> ModSecurityResult<()> {
+        self.transaction.add_request_header_bytes(key, value)
+    }
+
+    /// See [`Transaction::process_request_headers()`].
+    pub fn process_request_headers(
+        mut self,
+    ) -> ModSecurityResult<Outcome<'a, state::RequestBody, B>> {
+        self.transaction.process_request_headers()?;
+        Ok(self.outcome())
+    }
+}
+
+impl<'a, B: RawBindings> PhasedTransaction<'a, state::RequestBody, B> {
+    /// See [`Transaction::append_request_body()`].
+    pub fn append_request_body(&mut self, body: &[u8]) -> ModSecurityResult<()> {
+        self.transaction.append_request_body(body)
+    }
+
+    /// See [`Transaction::process_request_body()`].
+    pub fn process_request_body(
+        mut self,
+    ) -> ModSecurityResult<Outcome<'a, state::ResponseHeaders, B>> {
+        self.transaction.process_request_body()?;
+        Ok(self.outcome())
+    }
+}
+
+impl<'a, B: RawBindings> PhasedTransaction<'a, state::ResponseHeaders, B> {
+    /// See [`Transaction::add_response_header()`].
+    pub fn add_response_header(&mut self, key: &str, value: &str) -> ModSecurityResult<()> {
+        self.transaction.add_response_header(key, value)
+    }
+
+    /// See [`Transaction::add_response_header_bytes()`].
+    pub fn add_response_header_bytes(&mut self, key: &[u8], value: &[u8]) -> ModSecurityResult<()> {
+        self.transaction.add_response_header_bytes(key, value)
+    }
+
+    /// See [`Transaction::process_response_headers()`].
+    pub fn process_response_headers(
+        mut self,
+        code: i32,
+        protocol: &str,
+    ) -> ModSecurityResult<Outcome<'a, state::ResponseBody, B>> {
+        self.transaction.process_response_headers(code, protocol)?;
+        Ok(self.outcome())
+    }
+}
+
+impl<'a, B: RawBindings> PhasedTransaction<'a, state::ResponseBody, B> {
+    /// See [`Transaction::update_status_code()`].
+    pub fn update_status_code(&mut self, status: i32) -> ModSecurityResult<()> {
+        self.transaction.update_status_code(status)
+    }
+
+    /// See [`Transaction::append_response_body()`].
+    pub fn append_response_body(&mut self, body: &[u8]) -> ModSecurityResult<()> {
+        self.transaction.append_response_body(body)
+    }
+
+    /// See [`Transaction::process_response_body()`].
+    pub fn process_response_body(mut self) -> ModSecurityResult<Outcome<'a, state::Logging, B>> {
+        self.transaction.process_response_body()?;
+        Ok(self.outcome())
+    }
+}
+
+impl<'a, B: RawBindings> PhasedTransaction<'a, state::Logging, B> {
+    /// See [`Transaction::process_logging()`].
+    ///
+    /// This is the last phase, so the transaction is returned in case it is still needed (e.g.
+    /// for [`Transaction::get_response_body()`]), along with any intervention raised.
+    pub fn process_logging(
+        mut self,
+    ) -> ModSecurityResult<(Transaction<'a, B>, Option<Intervention<B>>)> {
+        self.transaction.process_logging()?;
+        let intervention = self.transaction.intervention();
+        Ok((self.transaction, intervention))
+    }
+}
+
+impl<'a, S, B: RawBindings> PhasedTransaction<'a, S, B> {
+    /// Returns a reference to the underlying transaction.
+    pub fn transaction(&self) -> &Transaction<'a, B> {
+        &self.transaction
+    }
+
+    /// Returns the underlying transaction, giving up the ordering guarantees.
+    pub fn into_inner(self) -> Transaction<'a, B> {
+        self.transaction
+    }
+
+    fn transition<T>(self) -> PhasedTransaction<'a, T, B> {
+        PhasedTransaction {
+            transaction: self.transaction,
+            _state: PhantomData,
+        }
+    }
+
+    fn outcome<T>(mut self) -> Outcome<'a, T, B> {
+        match self.transaction.intervention() {
+            Some(intervention) => Outcome::Intervention {
+                intervention,
+                transaction: self.transition(),
+            },
+            None => Outcome::Continue(self.transition()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{msc::ModSecurity, rules::Rules, ModSecurityError};
+
+    struct TestBindings;
+
+    #[cfg(not(miri))]
+    impl RawBindings for TestBindings {}
+
+    #[cfg(miri)]
+    impl RawBindings for TestBindings {
+        unsafe fn msc_init() -> *mut modsecurity_sys::ModSecurity {
+            std::ptr::null_mut()
+        }
+
+        unsafe fn msc_set_connector_info(
+            _: *mut modsecurity_sys::ModSecurity,
+            _: *const std::os::raw::c_char,
+        ) {
+        }
+
+        unsafe fn msc_cleanup(_: *mut modsecurity_sys::ModSecurity) {}
+
+        unsafe fn msc_create_rules_set() -> *mut crate::bindings::types::Rules_t {
+            std::ptr::null_mut()
+        }
+
+        unsafe fn msc_rules_add(
+            _: *mut crate::bindings::types::Rules_t,
+            _: *const std::os::raw::c_char,
+            _: *mut *const std::os::raw::c_char,
+        ) -> std::os::raw::c_int {
+            1
+        }
+
+        unsafe fn msc_rules_cleanup(
+            _: *mut crate::bindings::types::Rules_t,
+        ) -> std::os::raw::c_int {
+            1
+        }
+
+        unsafe fn msc_new_transaction(
+            _msc: *mut modsecurity_sys::ModSecurity,
+            _rules: *mut modsecurity_sys::RulesSet,
+            _log_cb: *mut std::ffi::c_void,
+        ) -> *mut crate::bindings::types::Transaction_t {
+            std::ptr::null_mut()
+        }
+
+        unsafe fn msc_transaction_cleanup(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+        ) {
+        }
+
+        unsafe fn msc_intervention(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+            _intervention: *mut crate::bindings::types::ModSecurityIntervention_t,
+        ) -> i32 {
+            0
+        }
+
+        unsafe fn msc_process_connection(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+            _client: *const std::os::raw::c_char,
+            _c_port: i32,
+            _server: *const std::os::raw::c_char,
+            _s_port: i32,
+        ) -> i32 {
+            1
+        }
+
+        unsafe fn msc_process_uri(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+            _uri: *const std::os::raw::c_char,
+            _protocol: *const std::os::raw::c_char,
+            _http_version: *const std::os::raw::c_char,
+        ) -> i32 {
+            1
+        }
+
+        unsafe fn msc_add_n_request_header(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+            _key: *const std::os::raw::c_uchar,
+            _len_key: usize,
+            _value: *const std::os::raw::c_uchar,
+            _len_value: usize,
+        ) -> i32 {
+            1
+        }
+
+        unsafe fn msc_process_request_headers(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+        ) -> i32 {
+            1
+        }
+
+        unsafe fn msc_append_request_body(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+            _body: *const std::os::raw::c_uchar,
+            _size: usize,
+        ) -> i32 {
+            1
+        }
+
+        unsafe fn msc_process_request_body(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+        ) -> i32 {
+            1
+        }
+
+        unsafe fn msc_add_n_response_header(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+            _key: *const std::os::raw::c_uchar,
+            _len_key: usize,
+            _value: *const std::os::raw::c_uchar,
+            _len_value: usize,
+        ) -> i32 {
+            1
+        }
+
+        unsafe fn msc_process_response_headers(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+            _code: i32,
+            _protocol: *const std::os::raw::c_char,
+        ) -> i32 {
+            1
+        }
+
+        unsafe fn msc_append_response_body(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+            _body: *const std::os::raw::c_uchar,
+            _size: usize,
+        ) -> i32 {
+            1
+        }
+
+        unsafe fn msc_process_response_body(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+        ) -> i32 {
+            1
+        }
+
+        unsafe fn msc_process_logging(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+        ) -> i32 {
+            1
+        }
+    }
+
+    struct FallibleBindings;
+
+    impl RawBindings for FallibleBindings {
+        #[cfg(miri)]
+        unsafe fn msc_init() -> *mut modsecurity_sys::ModSecurity {
+            std::ptr::null_mut()
+        }
+
+        #[cfg(miri)]
+        unsafe fn msc_set_connector_info(
+            _: *mut modsecurity_sys::ModSecurity,
+            _: *const std::os::raw::c_char,
+        ) {
+        }
+
+        #[cfg(miri)]
+        unsafe fn msc_cleanup(_: *mut modsecurity_sys::ModSecurity) {}
+
+        #[cfg(miri)]
+        unsafe fn msc_create_rules_set() -> *mut crate::bindings::types::Rules_t {
+            std::ptr::null_mut()
+        }
+
+        #[cfg(miri)]
+        unsafe fn msc_rules_cleanup(
+            _: *mut crate::bindings::types::Rules_t,
+        ) -> std::os::raw::c_int {
+            1
+        }
+
+        #[cfg(miri)]
+        unsafe fn msc_new_transaction(
+            _msc: *mut modsecurity_sys::ModSecurity,
+            _rules: *mut modsecurity_sys::RulesSet,
+            _log_cb: *mut std::ffi::c_void,
+        ) -> *mut crate::bindings::types::Transaction_t {
+            std::ptr::null_mut()
+        }
+
+        #[cfg(miri)]
+        unsafe fn msc_transaction_cleanup(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+        ) {
+        }
+
+        unsafe fn msc_process_connection(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+            _client: *const std::os::raw::c_char,
+            _c_port: i32,
+            _server: *const std::os::raw::c_char,
+            _s_port: i32,
+        ) -> i32 {
+            0
+        }
+    }
+
+    macro_rules! expect_continue {
+        ($outcome:expr) => {
+            match $outcome.unwrap() {
+                Outcome::Continue(transaction) => transaction,
+                Outcome::Intervention { intervention, .. } => {
+                    panic!("Unexpected intervention: {:?}", intervention)
+                }
+            }
+        };
+    }
+
+    #[test]
+    fn test_all_phases() {
+        let ms = ModSecurity::<TestBindings>::default();
+        let mut rules = Rules::new();
+        rules.add_plain("SecRuleEngine On\n").unwrap();
+
+        let transaction = ms
+            .transaction_builder()
+            .with_rules(&rules)
+            .build_phased()
+            .unwrap();
+
+        let transaction =
+            expect_continue!(transaction.process_connection("127.0.0.1", 12345, "127.0.0.1", 80));
+        let mut transaction = expect_continue!(transaction.process_uri("/", "GET", "1.1"));
+        transaction.add_request_header("Host", "localhost").unwrap();
+        let mut transaction = expect_continue!(transaction.process_request_headers());
+        transaction.append_request_body(b"body").unwrap();
+        let mut transaction = expect_continue!(transaction.process_request_body());
+        transaction
+            .add_response_header("Content-Type", "text/plain")
+            .unwrap();
+        let mut transaction =
+            expect_continue!(transaction.process_response_headers(200, "HTTP 1.1"));
+        transaction.append_response_body(b"body").unwrap();
+        let transaction = expect_continue!(transaction.process_response_body());
+
+        let (_, intervention) = transaction.process_logging().unwrap();
+        assert!(intervention.is_none());
+    }
+
+    #[test]
+    fn test_intervention() {
+        let ms = ModSecurity::<TestBindings>::default();
+        let mut rules = Rules::new();
+        rules
+            .add_plain(
+                r#"
+                SecRuleEngine On
+
+                SecRule REQUEST_URI "test" "phase:1,id:'1',t:none,deny,status:403"
+            "#,
+            )
+            .unwrap();
+
+        let transaction = ms
+            .transaction_builder()
+            .with_rules(&rules)
+            .build_phased()
+            .unwrap();
+
+        let transaction =
+            expect_continue!(transaction.process_connection("127.0.0.1", 12345, "127.0.0.1", 80));
+        let transaction = expect_continue!(transaction.process_uri("/test", "GET", "1.1"));
+
+        #[cfg(not(miri))]
+        match transaction.process_request_headers().unwrap() {
+            Outcome::Continue(_) => panic!("Expected intervention"),
+            Outcome::Intervention {
+                intervention,
+                transaction,
+            } => {
+                assert_eq!(intervention.status(), 403);
+                transaction.process_logging().unwrap();
+            }
+        };
+    }
+
+    #[test]
+    fn test_phase_failure() {
+        let ms = ModSecurity::<FallibleBindings>::default();
+        let rules = Rules::new();
+
+        let transaction = ms
+            .transaction_builder()
+            .with_rules(&rules)
+            .build_phased()
+            .unwrap();
+
+        assert!(matches!(
+            transaction.process_connection("127.0.0.1", 12345, "127.0.0.1", 80),
+            Err(ModSecurityError::ProcessConnection)
+        ));
+    }
+}
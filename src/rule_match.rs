@@ -0,0 +1,527 @@
+//! Parsing of the log messages that libmodsecurity generates when a rule matches.
+//!
+//! Both [`Intervention::log()`](crate::Intervention::log) and the callback set with
+//! [`TransactionBuilder::with_logging()`](crate::transaction::TransactionBuilder::with_logging)
+//! provide these messages as strings, e.g.
+//!
+//! ```text
+//! [client 127.0.0.1] ModSecurity: Access denied with code 403 (phase 1). Matched "Operator `Rx' with parameter `admin' against variable `REQUEST_URI' (Value: `/admin' ) [file "/etc/modsecurity/rules.conf"] [line "12"] [id "1"] [rev ""] [msg "Admin access"] [data ""] [severity "2"] [ver ""] [maturity "0"] [accuracy "0"] [tag "admin"] [hostname "127.0.0.1"] [uri "/admin"] [unique_id "170000000000.000000"] [ref "o1,5v4,6"]
+//! ```
+//!
+//! [`RuleMatch::parse()`] turns them into a [`RuleMatch`].
+//!
+//! ## Examples
+//!
+//! ```
+//! use modsecurity::{rule_match::Severity, RuleMatch};
+//!
+//! let log = r#"ModSecurity: Warning. Matched "Operator `Rx' with parameter `admin' against variable `REQUEST_URI' (Value: `/admin' ) [file "rules.conf"] [line "3"] [id "1"] [msg "Admin access"] [severity "2"] [tag "admin"]"#;
+//!
+//! let rule_match = RuleMatch::parse(log).expect("Not a rule match");
+//!
+//! assert_eq!(rule_match.id, Some(1));
+//! assert_eq!(rule_match.msg.as_deref(), Some("Admin access"));
+//! assert_eq!(rule_match.severity, Some(Severity::Critical));
+//! assert_eq!(rule_match.matched_variable.as_deref(), Some("REQUEST_URI"));
+//! ```
+
+const HEADER: &str = "ModSecurity: ";
+const VARIABLE: &str = "against variable `";
+const VALUE: &str = "' (Value: `";
+const VALUE_END: &str = "' )";
+
+/// The severity of a rule, as set with its `severity` action.
+///
+/// Variants are ordered from most to least severe, so `Severity::Critical < Severity::Warning`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Severity {
+    /// `0`, `EMERGENCY`.
+    ///
+    /// libmodsecurity also reports this for rules that do not set a severity, so it does not
+    /// necessarily mean that the rule was given this severity.
+    Emergency,
+    /// `1`, `ALERT`.
+    Alert,
+    /// `2`, `CRITICAL`.
+    Critical,
+    /// `3`, `ERROR`.
+    Error,
+    /// `4`, `WARNING`.
+    Warning,
+    /// `5`, `NOTICE`.
+    Notice,
+    /// `6`, `INFO`.
+    Info,
+    /// `7`, `DEBUG`.
+    Debug,
+}
+
+impl TryFrom<u8> for Severity {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, u8> {
+        Ok(match value {
+            0 => Severity::Emergency,
+            1 => Severity::Alert,
+            2 => Severity::Critical,
+            3 => Severity::Error,
+            4 => Severity::Warning,
+            5 => Severity::Notice,
+            6 => Severity::Info,
+            7 => Severity::Debug,
+            other => return Err(other),
+        })
+    }
+}
+
+/// A rule match, parsed from a libmodsecurity log message.
+///
+/// Fields that are missing from the message, or empty, are `None`.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+#[non_exhaustive]
+pub struct RuleMatch {
+    /// The ID of the rule.
+    pub id: Option<u64>,
+    /// The phase the rule matched in, for disruptive matches.
+    pub phase: Option<u8>,
+    /// The status of the intervention, for disruptive matches.
+    pub status: Option<i32>,
+    /// The message of the rule, as set with its `msg` action.
+    pub msg: Option<String>,
+    /// The data of the rule, as set with its `logdata` action.
+    pub data: Option<String>,
+    /// The severity of the rule.
+    ///
+    /// libmodsecurity reports rules that do not set a severity as [`Severity::Emergency`], so
+    /// those cannot be told apart from rules with an `EMERGENCY` severity.
+    pub severity: Option<Severity>,
+    /// The tags of the rule.
+    pub tags: Vec<String>,
+    /// The file the rule is defined in.
+    pub file: Option<String>,
+    /// The line the rule is defined on.
+    pub line: Option<u32>,
+    /// The variable the rule matched against, e.g. `ARGS:q`.
+    pub matched_variable: Option<String>,
+    /// The ID of the transaction.
+    pub unique_id: Option<String>,
+}
+
+impl RuleMatch {
+    /// Parses a log message generated by libmodsecurity, returning `None` if it does not
+    /// describe a rule match.
+    ///
+    /// libmodsecurity does not escape every value in these messages. If a value contains text
+    /// that looks like the start of another field (e.g. `"] [id "`), it may be split at that
+    /// point. Fields are only read after the matched variable and value, which come from the
+    /// request, so those cannot add fields unless the value contains `' ) [file "`.
+    pub fn parse(log: &str) -> Option<Self> {
+        let start = log.find(HEADER)? + HEADER.len();
+        let rest = &log[start..];
+
+        let mut rule_match = RuleMatch::default();
+
+        if let Some(denied) = rest.strip_prefix("Access denied with code ") {
+            rule_match.status = leading_number(denied);
+            rule_match.phase = denied
+                .find("(phase ")
+                .and_then(|index| leading_number(&denied[index + "(phase ".len()..]));
+        } else if !rest.starts_with("Warning.") {
+            return None;
+        }
+
+        // The variable name and value in the "Matched" section come from the request, so they
+        // may contain text that looks like a field. Fields are only looked for after it.
+        let details = match rest.find(VALUE) {
+            Some(value) => {
+                let value = value + VALUE.len();
+                rule_match.matched_variable = rest[..value - VALUE.len()]
+                    .find(VARIABLE)
+                    .map(|index| rest[index + VARIABLE.len()..value - VALUE.len()].to_string());
+
+                match_end(&rest[value..]).map_or(rest.len(), |end| value + end)
+            }
+            None => find_field(rest).unwrap_or(rest.len()),
+        };
+
+        for (key, value) in Fields(&rest[details..]) {
+            match key {
+                "id" if rule_match.id.is_none() => rule_match.id = value.parse().ok(),
+                "msg" if rule_match.msg.is_none() => rule_match.msg = non_empty(value),
+                "data" if rule_match.data.is_none() => rule_match.data = non_empty(value),
+                "severity" if rule_match.severity.is_none() => {
+                    rule_match.severity = value
+                        .parse::<u8>()
+                        .ok()
+                        .and_then(|severity| Severity::try_from(severity).ok())
+                }
+                "tag" => rule_match.tags.push(value.to_string()),
+                "file" if rule_match.file.is_none() => rule_match.file = non_empty(value),
+                "line" if rule_match.line.is_none() => rule_match.line = value.parse().ok(),
+                "unique_id" if rule_match.unique_id.is_none() => {
+                    rule_match.unique_id = non_empty(value)
+                }
+                _ => {}
+            }
+        }
+
+        Some(rule_match)
+    }
+}
+
+fn leading_number<T: std::str::FromStr>(s: &str) -> Option<T> {
+    let end = s
+        .find(|c: char| !c.is_ascii_digit() && c != '-')
+        .unwrap_or(s.len());
+
+    s[..end].parse().ok()
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Returns the length of the field key at the start of `s`, if `s` starts with `[key "`.
+fn field_key_len(s: &str) -> Option<usize> {
+    let key = s.strip_prefix('[')?;
+    let len = key
+        .find(|c: char| !c.is_ascii_lowercase() && c != '_')
+        .unwrap_or(key.len());
+
+    if len > 0 && key[len..].starts_with(" \"") {
+        Some(len)
+    } else {
+        None
+    }
+}
+
+/// Returns the index of the end of the matched value at the start of `s`.
+///
+/// The value is followed by the `file` field in messages generated by libmodsecurity, which is
+/// preferred over the first field that follows the end of a value, as the value itself may contain
+/// `' ) [`.
+fn match_end(s: &str) -> Option<usize> {
+    // Returns the key of the field that follows the end of a value at `index`.
+    let next_key = |index: usize| {
+        let after = s[index + VALUE_END.len()..].trim_start();
+        field_key_len(after).map(|len| &after[1..1 + len])
+    };
+
+    let mut ends = s.match_indices(VALUE_END).map(|(index, _)| index);
+
+    ends.clone()
+        .find(|&index| next_key(index) == Some("file"))
+        .or_else(|| ends.find(|&index| next_key(index).is_some()))
+        .map(|index| index + VALUE_END.len())
+}
+
+/// Returns the index of the first field in `s`.
+fn find_field(s: &str) -> Option<usize> {
+    s.match_indices('[')
+        .map(|(index, _)| index)
+        .find(|&index| field_key_len(&s[index..]).is_some())
+}
+
+/// An iterator over the `[key "value"]` fields of a log message.
+struct Fields<'a>(&'a str);
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = (&'a str, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = find_field(self.0)?;
+        let field = &self.0[start..];
+        let key_len = field_key_len(field)?;
+
+        let key = &field[1..1 + key_len];
+        let value = &field[1 + key_len + 2..];
+
+        // A value ends at the first `"]` that is followed by another field or the end of the
+        // message.
+        let end = value
+            .match_indices("\"]")
+            .map(|(index, _)| index)
+            .find(|&index| {
+                let after = value[index + 2..].trim_start();
+                after.is_empty() || field_key_len(after).is_some()
+            });
+
+        match end {
+            Some(end) => {
+                self.0 = &value[end + 2..];
+                Some((key, &value[..end]))
+            }
+            // The message was truncated in the middle of this field.
+            None => {
+                self.0 = "";
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Messages as generated by libmodsecurity 3.0.x, for the error log callback and for
+    // interventions.
+    const CALLBACK_LOG: &str = r#"[client 127.0.0.1] ModSecurity: Access denied with code 403 (phase 2). Matched "Operator `Rx' with parameter `attack' against variable `ARGS:q' (Value: `an attack' ) [file "/etc/modsecurity/rules.conf"] [line "12"] [id "942100"] [rev "1"] [msg "SQL Injection Attack Detected via libinjection"] [data "Matched Data: attack found within ARGS:q: an attack"] [severity "2"] [ver "OWASP_CRS/4.0.0"] [maturity "0"] [accuracy "0"] [tag "application-multi"] [tag "language-multi"] [tag "attack-sqli"] [tag "capec/1000/152/248/66"] [hostname "10.0.0.2"] [uri "/search"] [unique_id "170000000012.345678"] [ref "o0,6v10,9"]"#;
+
+    const INTERVENTION_LOG: &str = r#"ModSecurity: Access denied with code 401 (phase 1). Matched "Operator `Rx' with parameter `admin' against variable `REQUEST_URI' (Value: `/admin' ) [file "<<reference missing or not informed>>"] [line "4"] [id "1"] [rev ""] [msg ""] [data ""] [severity "0"] [ver ""] [maturity "0"] [accuracy "0"] [hostname "127.0.0.1"] [uri "/admin"] [unique_id "170000000012.345679"] [ref "v4,6"]"#;
+
+    const WARNING_LOG: &str = r#"[client 10.0.0.1] ModSecurity: Warning. Matched "Operator `Ge' with parameter `5' against variable `TX:BLOCKING_INBOUND_ANOMALY_SCORE' (Value: `5' ) [file "/etc/crs/rules/REQUEST-949-BLOCKING-EVALUATION.conf"] [line "222"] [id "949110"] [rev ""] [msg "Inbound Anomaly Score Exceeded (Total Score: 5)"] [data ""] [severity "0"] [ver "OWASP_CRS/4.0.0"] [maturity "0"] [accuracy "0"] [tag "anomaly-evaluation"] [hostname "10.0.0.2"] [uri "/"] [unique_id "170000000012.345680"] [ref ""]"#;
+
+    #[test]
+    fn test_parse_table() {
+        let cases = [
+            (
+                CALLBACK_LOG,
+                Some(RuleMatch {
+                    id: Some(942100),
+                    phase: Some(2),
+                    status: Some(403),
+                    msg: Some("SQL Injection Attack Detected via libinjection".to_string()),
+                    data: Some("Matched Data: attack found within ARGS:q: an attack".to_string()),
+                    severity: Some(Severity::Critical),
+                    tags: vec![
+                        "application-multi".to_string(),
+                        "language-multi".to_string(),
+                        "attack-sqli".to_string(),
+                        "capec/1000/152/248/66".to_string(),
+                    ],
+                    file: Some("/etc/modsecurity/rules.conf".to_string()),
+                    line: Some(12),
+                    matched_variable: Some("ARGS:q".to_string()),
+                    unique_id: Some("170000000012.345678".to_string()),
+                }),
+            ),
+            (
+                INTERVENTION_LOG,
+                Some(RuleMatch {
+                    id: Some(1),
+                    phase: Some(1),
+                    status: Some(401),
+                    severity: Some(Severity::Emergency),
+                    file: Some("<<reference missing or not informed>>".to_string()),
+                    line: Some(4),
+                    matched_variable: Some("REQUEST_URI".to_string()),
+                    unique_id: Some("170000000012.345679".to_string()),
+                    ..Default::default()
+                }),
+            ),
+            (
+                WARNING_LOG,
+                Some(RuleMatch {
+                    id: Some(949110),
+                    msg: Some("Inbound Anomaly Score Exceeded (Total Score: 5)".to_string()),
+                    severity: Some(Severity::Emergency),
+                    tags: vec!["anomaly-evaluation".to_string()],
+                    file: Some("/etc/crs/rules/REQUEST-949-BLOCKING-EVALUATION.conf".to_string()),
+                    line: Some(222),
+                    matched_variable: Some("TX:BLOCKING_INBOUND_ANOMALY_SCORE".to_string()),
+                    unique_id: Some("170000000012.345680".to_string()),
+                    ..Default::default()
+                }),
+            ),
+            // Values that contain brackets and quotes.
+            (
+                r#"ModSecurity: Warning. Matched "Operator `Rx' with parameter `x' against variable `ARGS:a[]' (Value: `x' ) [id "5"] [msg "Found "[x]" in [a]"] [tag "a"]"#,
+                Some(RuleMatch {
+                    id: Some(5),
+                    msg: Some(r#"Found "[x]" in [a]"#.to_string()),
+                    tags: vec!["a".to_string()],
+                    matched_variable: Some("ARGS:a[]".to_string()),
+                    ..Default::default()
+                }),
+            ),
+            // Fields in the matched value are not read.
+            (
+                r#"ModSecurity: Warning. Matched "Operator `Rx' with parameter `x' against variable `ARGS:q' (Value: `x' ) [id "1"] [severity "0"]' ) [file "rules.conf"] [line "3"] [id "5"] [severity "4"]"#,
+                Some(RuleMatch {
+                    id: Some(5),
+                    severity: Some(Severity::Warning),
+                    file: Some("rules.conf".to_string()),
+                    line: Some(3),
+                    matched_variable: Some("ARGS:q".to_string()),
+                    ..Default::default()
+                }),
+            ),
+            // Nor are fields in the matched variable name.
+            (
+                r#"ModSecurity: Warning. Matched "Operator `Rx' with parameter `x' against variable `ARGS:[id "1"] [severity "0"]' (Value: `x' ) [file "rules.conf"] [line "3"] [id "5"] [severity "4"]"#,
+                Some(RuleMatch {
+                    id: Some(5),
+                    severity: Some(Severity::Warning),
+                    file: Some("rules.conf".to_string()),
+                    line: Some(3),
+                    matched_variable: Some(r#"ARGS:[id "1"] [severity "0"]"#.to_string()),
+                    ..Default::default()
+                }),
+            ),
+            // A message truncated in the middle of a field.
+            (
+                r#"ModSecurity: Warning. Matched "Operator `Rx'" [id "7"] [msg "trunc"#,
+                Some(RuleMatch {
+                    id: Some(7),
+                    ..Default::default()
+                }),
+            ),
+            // Values of the wrong type are ignored.
+            (
+                r#"ModSecurity: Warning. [id "x"] [line "-1"] [severity "9"]"#,
+                Some(RuleMatch::default()),
+            ),
+            ("ModSecurity: Warning.", Some(RuleMatch::default())),
+            // Messages that do not describe a rule match.
+            ("", None),
+            ("Rule 1 matched", None),
+            ("ModSecurity: Some other message", None),
+            (r#"[id "1"] [msg "no header"]"#, None),
+        ];
+
+        for (log, expected) in cases {
+            assert_eq!(RuleMatch::parse(log), expected, "{}", log);
+        }
+    }
+
+    #[test]
+    fn test_severity() {
+        for value in 0..=7 {
+            assert!(Severity::try_from(value).is_ok());
+        }
+        assert_eq!(Severity::try_from(8), Err(8));
+        assert!(Severity::Critical < Severity::Warning);
+    }
+
+    /// A small xorshift generator, so the fuzz tests are deterministic.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    #[cfg(not(miri))]
+    #[test]
+    fn test_parse_truncated() {
+        for log in [CALLBACK_LOG, INTERVENTION_LOG, WARNING_LOG] {
+            for end in (0..=log.len()).filter(|&end| log.is_char_boundary(end)) {
+                let _ = RuleMatch::parse(&log[..end]);
+            }
+        }
+    }
+
+    #[cfg(not(miri))]
+    #[test]
+    fn test_parse_fuzz() {
+        const FRAGMENTS: &[&str] = &[
+            "ModSecurity: ",
+            "Access denied with code ",
+            "Warning. ",
+            "(phase ",
+            "against variable `",
+            "'",
+            "[",
+            "]",
+            "\"",
+            "\"]",
+            " [id \"",
+            " [tag \"",
+            " [severity \"",
+            " [line \"",
+            "99999999999999999999",
+            "-",
+            "1",
+            " ",
+            "é",
+            "\u{1F600}",
+        ];
+
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        let corpus = [CALLBACK_LOG, INTERVENTION_LOG, WARNING_LOG];
+
+        for _ in 0..2000 {
+            // Splice random fragments into a real message, or build one from fragments only.
+            let mut log = if rng.below(4) == 0 {
+                String::new()
+            } else {
+                corpus[rng.below(corpus.len())].to_string()
+            };
+
+            for _ in 0..rng.below(8) {
+                let fragment = FRAGMENTS[rng.below(FRAGMENTS.len())];
+                let mut at = rng.below(log.len() + 1);
+                while !log.is_char_boundary(at) {
+                    at -= 1;
+                }
+                log.insert_str(at, fragment);
+            }
+
+            // Parsing must never panic, and always finds the header if it returns a match.
+            if RuleMatch::parse(&log).is_some() {
+                assert!(log.contains(HEADER));
+            }
+        }
+    }
+
+    #[cfg(not(miri))]
+    #[test]
+    fn test_parse_libmodsecurity_output() {
+        use std::sync::{Arc, Mutex};
+
+        use crate::{ModSecurity, Rules};
+
+        let ms = ModSecurity::builder().with_log_callbacks().build();
+        let mut rules = Rules::new();
+        rules
+            .add_plain(
+                r#"
+                SecRuleEngine On
+
+                SecRule ARGS:q "@rx attack" "id:10,phase:1,log,deny,status:403,msg:'Attack',severity:'CRITICAL',tag:'a',tag:'b'"
+            "#,
+            )
+            .unwrap();
+
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let captured = Arc::clone(&logs);
+
+        let mut transaction = ms
+            .transaction_builder()
+            .with_rules(&rules)
+            .with_logging(move |msg| {
+                if let Some(msg) = msg {
+                    captured.lock().unwrap().push(msg.to_string());
+                }
+            })
+            .build()
+            .unwrap();
+
+        transaction.process_uri("/?q=attack", "GET", "1.1").unwrap();
+        transaction.process_request_headers().unwrap();
+
+        let intervention = transaction.intervention().unwrap();
+        let logs = logs.lock().unwrap();
+
+        for log in logs.iter().map(String::as_str).chain(intervention.log()) {
+            let rule_match = RuleMatch::parse(log).unwrap();
+
+            assert_eq!(rule_match.id, Some(10));
+            assert_eq!(rule_match.phase, Some(1));
+            assert_eq!(rule_match.status, Some(403));
+            assert_eq!(rule_match.msg.as_deref(), Some("Attack"));
+            assert_eq!(rule_match.severity, Some(Severity::Critical));
+            assert_eq!(rule_match.tags, vec!["a", "b"]);
+            assert_eq!(rule_match.matched_variable.as_deref(), Some("ARGS:q"));
+        }
+    }
+}
@@ -1,15 +1,16 @@
 //! ModSecurity rules.
 
 use lazy_static::lazy_static;
+use std::collections::HashMap;
 use std::ffi::CStr;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex, RwLock};
 use std::{ffi::CString, marker::PhantomData, os::raw::c_char, path::Path};
 
 use crate::bindings::types::Rules_t;
 
 use crate::{
     bindings::{Bindings, RawBindings},
-    ModSecurityResult,
+    ModSecurityError, ModSecurityResult,
 };
 
 lazy_static! {
@@ -59,6 +60,35 @@ macro_rules! msc_add_rules_result {
     };
 }
 
+/// Fetches the content of remote rules for [`Rules::add_remote_with_fetcher()`].
+///
+/// This allows rules to be served from somewhere other than the network, such as an in-memory
+/// map in tests or a local mirror in air-gapped deployments. It is implemented for closures
+/// and for `HashMap<String, String>`, which is keyed by URI.
+pub trait RulesFetcher {
+    /// Returns the rules found at `uri`, or a description of why they could not be fetched.
+    ///
+    /// `key` is the key given to [`Rules::add_remote_with_fetcher()`].
+    fn fetch(&self, key: &str, uri: &str) -> Result<String, String>;
+}
+
+impl<F> RulesFetcher for F
+where
+    F: Fn(&str, &str) -> Result<String, String>,
+{
+    fn fetch(&self, key: &str, uri: &str) -> Result<String, String> {
+        self(key, uri)
+    }
+}
+
+impl RulesFetcher for HashMap<String, String> {
+    fn fetch(&self, _key: &str, uri: &str) -> Result<String, String> {
+        self.get(uri)
+            .cloned()
+            .ok_or_else(|| format!("No rules found for {}", uri))
+    }
+}
+
 unsafe impl<B: RawBindings> Send for Rules<B> {}
 unsafe impl<B: RawBindings> Sync for Rules<B> {}
 
@@ -105,6 +135,82 @@ impl<B: RawBindings> Rules<B> {
     /// rules.add_plain("SecRuleEngine On\n").expect("Failed to add rules");
     /// ```
     pub fn add_plain(&mut self, plain_rules: &str) -> ModSecurityResult<()> {
+        self.add_plain_with_error(plain_rules, ModSecurityError::RulesAddPlain)
+    }
+
+    /// Adds rules from a remote URI to the set.
+    ///
+    /// The rules are downloaded by ModSecurity itself, which requires it to have been built with
+    /// `libcurl` support. `key` is sent to the server alongside the request, as with the
+    /// `SecRemoteRules` directive.
+    ///
+    /// ## Examples
+    ///
+    /// ```no_run
+    /// use modsecurity::Rules;
+    ///
+    /// let mut rules = Rules::new();
+    /// rules
+    ///     .add_remote("some-key", "https://example.com/rules.conf")
+    ///     .expect("Failed to add remote rules");
+    /// ```
+    pub fn add_remote(&mut self, key: &str, uri: &str) -> ModSecurityResult<()> {
+        // SAFETY: Parsing is not thread-safe. So we serialize the calls
+        // to this function across instances.
+        let _lock = RULES.lock().expect("Poisoned lock");
+
+        let key = CString::new(key)?;
+        let uri = CString::new(uri)?;
+
+        let mut error: *const c_char = std::ptr::null();
+        let result =
+            unsafe { B::msc_rules_add_remote(self.inner, key.as_ptr(), uri.as_ptr(), &mut error) };
+
+        msc_add_rules_result!(result, error, ModSecurityError::RulesAddRemote)
+    }
+
+    /// Adds rules from a remote URI to the set, using `fetcher` to retrieve them.
+    ///
+    /// This is an alternative to [`Rules::add_remote()`] where the rules are fetched on the Rust
+    /// side and then parsed as plain rules. See [`RulesFetcher`].
+    ///
+    /// ## Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    ///
+    /// use modsecurity::Rules;
+    ///
+    /// let mut mirror = HashMap::new();
+    /// mirror.insert(
+    ///     "https://example.com/rules.conf".to_string(),
+    ///     "SecRuleEngine On\n".to_string(),
+    /// );
+    ///
+    /// let mut rules = Rules::new();
+    /// rules
+    ///     .add_remote_with_fetcher("some-key", "https://example.com/rules.conf", &mirror)
+    ///     .expect("Failed to add remote rules");
+    /// ```
+    pub fn add_remote_with_fetcher(
+        &mut self,
+        key: &str,
+        uri: &str,
+        fetcher: &dyn RulesFetcher,
+    ) -> ModSecurityResult<()> {
+        // Fetching happens before taking the parser lock as it may be slow.
+        let plain_rules = fetcher
+            .fetch(key, uri)
+            .map_err(ModSecurityError::RulesAddRemote)?;
+
+        self.add_plain_with_error(&plain_rules, ModSecurityError::RulesAddRemote)
+    }
+
+    fn add_plain_with_error(
+        &mut self,
+        plain_rules: &str,
+        error_ty: fn(String) -> ModSecurityError,
+    ) -> ModSecurityResult<()> {
         // SAFETY: Parsing is not thread-safe. So we serialize the calls
         // to this function across instances.
         let _lock = RULES.lock().expect("Poisoned lock");
@@ -114,7 +220,36 @@ impl<B: RawBindings> Rules<B> {
         let mut error: *const c_char = std::ptr::null();
         let result = unsafe { B::msc_rules_add(self.inner, plain_rules.as_ptr(), &mut error) };
 
-        msc_add_rules_result!(result, error, crate::ModSecurityError::RulesAddPlain)
+        msc_add_rules_result!(result, error, error_ty)
+    }
+
+    /// Merges the rules from another set into this one.
+    ///
+    /// This allows a base rule set to be parsed once and then extended with additional rules
+    /// without re-parsing the base. `other` is left unchanged.
+    ///
+    /// ## Examples
+    ///
+    /// ```
+    /// use modsecurity::Rules;
+    ///
+    /// let mut base = Rules::new();
+    /// base.add_plain("SecRuleEngine On\n").expect("Failed to add rules");
+    ///
+    /// let mut tenant = Rules::new();
+    /// tenant.add_plain(r#"SecRule REQUEST_URI "@rx admin" "id:1,phase:1,deny""#).expect("Failed to add rules");
+    ///
+    /// base.merge(&tenant).expect("Failed to merge rules");
+    /// ```
+    pub fn merge(&mut self, other: &Rules<B>) -> ModSecurityResult<()> {
+        // SAFETY: Merging touches the same non thread-safe state as parsing. So we
+        // serialize the calls to this function across instances.
+        let _lock = RULES.lock().expect("Poisoned lock");
+
+        let mut error: *const c_char = std::ptr::null();
+        let result = unsafe { B::msc_rules_merge(self.inner, other.inner, &mut error) };
+
+        msc_add_rules_result!(result, error, crate::ModSecurityError::RulesMerge)
     }
 
     /// Dumps the rules to stdout.
@@ -139,6 +274,94 @@ impl<B: RawBindings> Drop for Rules<B> {
     }
 }
 
+/// A rule set that can be reloaded while transactions are in flight.
+///
+/// Each call to [`ReloadableRules::snapshot()`] returns the currently active [`Rules`]. A reload
+/// builds a new rule set and, only if that succeeds, swaps it in for subsequent snapshots.
+/// Transactions created from an older snapshot keep using it, and it is dropped once the last
+/// snapshot referencing it goes away.
+///
+/// Snapshots can also be handed to
+/// [`crate::transaction::TransactionBuilderWithoutRules::with_shared_rules()`] so that the
+/// transaction holds on to them itself.
+///
+/// ## Examples
+///
+/// ```
+/// use modsecurity::{ModSecurity, Rules, ReloadableRules};
+///
+/// let ms = ModSecurity::default();
+///
+/// let mut rules = Rules::new();
+/// rules.add_plain("SecRuleEngine On\n").expect("Failed to add rules");
+///
+/// let reloadable = ReloadableRules::new(rules);
+///
+/// let snapshot = reloadable.snapshot();
+/// let transaction = ms
+///     .transaction_builder()
+///     .with_rules(&snapshot)
+///     .build()
+///     .expect("Error building transaction");
+///
+/// // The transaction above keeps using the previous rules
+/// reloadable
+///     .reload_plain("SecRuleEngine DetectionOnly\n")
+///     .expect("Failed to reload rules");
+/// ```
+pub struct ReloadableRules<B: RawBindings = Bindings> {
+    current: RwLock<Arc<Rules<B>>>,
+}
+
+impl<B: RawBindings> ReloadableRules<B> {
+    /// Creates a new reloadable rule set, starting with `rules`.
+    pub fn new(rules: Rules<B>) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(rules)),
+        }
+    }
+
+    /// Returns the currently active rule set.
+    pub fn snapshot(&self) -> Arc<Rules<B>> {
+        Arc::clone(&self.current.read().expect("Poisoned lock"))
+    }
+
+    /// Builds a new rule set using `build` and makes it the active one.
+    ///
+    /// `build` is given an empty rule set. If it returns an error, the active rule set is left
+    /// unchanged and the error is returned.
+    pub fn reload<F>(&self, build: F) -> ModSecurityResult<()>
+    where
+        F: FnOnce(&mut Rules<B>) -> ModSecurityResult<()>,
+    {
+        let mut rules = Rules::new();
+        build(&mut rules)?;
+
+        // The previous rule set is dropped here if no snapshots of it remain. Otherwise, it is
+        // dropped by whichever holder releases it last.
+        let _previous = std::mem::replace(
+            &mut *self.current.write().expect("Poisoned lock"),
+            Arc::new(rules),
+        );
+
+        Ok(())
+    }
+
+    /// Replaces the active rule set with the rules from a file.
+    ///
+    /// See [`ReloadableRules::reload()`] and [`Rules::add_file()`].
+    pub fn reload_file<P: AsRef<Path>>(&self, file: P) -> ModSecurityResult<()> {
+        self.reload(|rules| rules.add_file(file))
+    }
+
+    /// Replaces the active rule set with plain rules.
+    ///
+    /// See [`ReloadableRules::reload()`] and [`Rules::add_plain()`].
+    pub fn reload_plain(&self, plain_rules: &str) -> ModSecurityResult<()> {
+        self.reload(|rules| rules.add_plain(plain_rules))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Write;
@@ -172,6 +395,23 @@ mod tests {
             0
         }
 
+        unsafe fn msc_rules_add_remote(
+            _: *mut Rules_t,
+            _: *const std::os::raw::c_char,
+            _: *const std::os::raw::c_char,
+            _: *mut *const std::os::raw::c_char,
+        ) -> std::os::raw::c_int {
+            0
+        }
+
+        unsafe fn msc_rules_merge(
+            _: *mut Rules_t,
+            _: *mut Rules_t,
+            _: *mut *const std::os::raw::c_char,
+        ) -> std::os::raw::c_int {
+            0
+        }
+
         unsafe fn msc_rules_cleanup(_: *mut Rules_t) -> std::os::raw::c_int {
             0
         }
@@ -203,6 +443,23 @@ mod tests {
             -1
         }
 
+        unsafe fn msc_rules_add_remote(
+            _: *mut Rules_t,
+            _: *const std::os::raw::c_char,
+            _: *const std::os::raw::c_char,
+            _: *mut *const std::os::raw::c_char,
+        ) -> std::os::raw::c_int {
+            -1
+        }
+
+        unsafe fn msc_rules_merge(
+            _: *mut Rules_t,
+            _: *mut Rules_t,
+            _: *mut *const std::os::raw::c_char,
+        ) -> std::os::raw::c_int {
+            -1
+        }
+
         unsafe fn msc_rules_cleanup(_: *mut Rules_t) -> std::os::raw::c_int {
             0
         }
@@ -216,6 +473,23 @@ mod tests {
     #[cfg(not(miri))]
     impl RawBindings for TestFallibleBindings {
         unsafe fn msc_rules_dump(_: *mut Rules_t) {}
+
+        unsafe fn msc_rules_add_remote(
+            _: *mut Rules_t,
+            _: *const std::os::raw::c_char,
+            _: *const std::os::raw::c_char,
+            _: *mut *const std::os::raw::c_char,
+        ) -> std::os::raw::c_int {
+            -1
+        }
+
+        unsafe fn msc_rules_merge(
+            _: *mut Rules_t,
+            _: *mut Rules_t,
+            _: *mut *const std::os::raw::c_char,
+        ) -> std::os::raw::c_int {
+            -1
+        }
     }
 
     #[test]
@@ -286,6 +560,121 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_rules_add_remote_err() {
+        let mut rules = Rules::<TestFallibleBindings>::new();
+
+        assert!(matches!(
+            rules.add_remote("key", "https://example.com/rules.conf"),
+            Err(ModSecurityError::RulesAddRemote(_))
+        ));
+    }
+
+    #[test]
+    fn test_rules_add_remote_with_fetcher_ok() {
+        let mut mirror = HashMap::new();
+        mirror.insert(
+            "https://example.com/rules.conf".to_string(),
+            "SecRuleEngine On\n".to_string(),
+        );
+
+        let mut rules = Rules::<TestBindings>::new();
+
+        assert!(matches!(
+            rules.add_remote_with_fetcher("key", "https://example.com/rules.conf", &mirror),
+            Ok(())
+        ));
+    }
+
+    #[test]
+    fn test_rules_add_remote_with_fetcher_closure() {
+        let fetcher = |key: &str, uri: &str| {
+            assert_eq!(key, "key");
+            assert_eq!(uri, "https://example.com/rules.conf");
+            Ok("SecRuleEngine On\n".to_string())
+        };
+
+        let mut rules = Rules::<TestBindings>::new();
+
+        assert!(matches!(
+            rules.add_remote_with_fetcher("key", "https://example.com/rules.conf", &fetcher),
+            Ok(())
+        ));
+    }
+
+    #[test]
+    fn test_rules_add_remote_with_fetcher_fetch_err() {
+        let mirror = HashMap::new();
+
+        let mut rules = Rules::<TestBindings>::new();
+
+        assert!(matches!(
+            rules.add_remote_with_fetcher("key", "https://example.com/rules.conf", &mirror),
+            Err(ModSecurityError::RulesAddRemote(_))
+        ));
+    }
+
+    #[test]
+    fn test_rules_add_remote_with_fetcher_parse_err() {
+        let fetcher = |_: &str, _: &str| Ok("InvalidDirectiveXXX Yeet\n".to_string());
+
+        let mut rules = Rules::<TestFallibleBindings>::new();
+
+        assert!(matches!(
+            rules.add_remote_with_fetcher("key", "https://example.com/rules.conf", &fetcher),
+            Err(ModSecurityError::RulesAddRemote(_))
+        ));
+    }
+
+    #[test]
+    fn test_rules_merge_ok() {
+        let mut base = Rules::<TestBindings>::new();
+        base.add_plain("SecRuleEngine On\n").unwrap();
+
+        let mut other = Rules::<TestBindings>::new();
+        other
+            .add_plain(r#"SecRule REQUEST_URI "@rx admin" "id:1,phase:1,deny""#)
+            .unwrap();
+
+        assert!(matches!(base.merge(&other), Ok(())));
+    }
+
+    #[test]
+    fn test_rules_merge_err() {
+        let mut base = Rules::<TestFallibleBindings>::new();
+        let other = Rules::<TestFallibleBindings>::new();
+
+        assert!(matches!(
+            base.merge(&other),
+            Err(ModSecurityError::RulesMerge(_))
+        ));
+    }
+
+    #[test]
+    fn test_reloadable_rules_reload() {
+        let reloadable = ReloadableRules::new(Rules::<TestBindings>::new());
+
+        let before = reloadable.snapshot();
+        reloadable.reload_plain("SecRuleEngine On\n").unwrap();
+        let after = reloadable.snapshot();
+
+        assert!(!Arc::ptr_eq(&before, &after));
+        assert!(Arc::ptr_eq(&after, &reloadable.snapshot()));
+        // The previous snapshot is kept alive by `before` only
+        assert_eq!(Arc::strong_count(&before), 1);
+    }
+
+    #[test]
+    fn test_reloadable_rules_reload_err() {
+        let reloadable = ReloadableRules::new(Rules::<TestBindings>::new());
+
+        let before = reloadable.snapshot();
+        let result = reloadable.reload(|_| Err(ModSecurityError::RulesAddPlain("error".into())));
+
+        assert!(matches!(result, Err(ModSecurityError::RulesAddPlain(_))));
+        assert!(Arc::ptr_eq(&before, &reloadable.snapshot()));
+    }
+
     #[test]
     fn test_rules_dump() {
         let plain_rules = r#"
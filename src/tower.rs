@@ -0,0 +1,924 @@
+//! A [`tower`](https://docs.rs/tower) layer that enforces ModSecurity rules on HTTP services.
+//!
+//! This module is only available with the `tower` feature enabled.
+//!
+//! [`ModSecurityLayer`] runs the request phases of a transaction before calling the inner
+//! service, and the response phases on the response it returns. If a phase raises a disruptive
+//! intervention, a response is built from it instead.
+//!
+//! The connection, URI and request headers phases run before the request body is read, so they
+//! can reject a request without receiving its body. The request body is then inspected as it is
+//! received, and buffered up to a limit so that the request body phase can run before the inner
+//! service is called. Trailers are passed on either way.
+//!
+//! Response body inspection is disabled by default: response bodies are streamed through and
+//! the response body phase is skipped, so rules on `RESPONSE_BODY` never match, even with
+//! `SecResponseBodyAccess On`. Once enabled, response bodies are read up to a limit for
+//! inspection first. See [`ModSecurityLayer::with_request_body_limit()`] and
+//! [`ModSecurityLayer::with_response_body_inspection()`].
+//!
+//! ## Examples
+//!
+//! ```
+//! use std::sync::Arc;
+//!
+//! use bytes::Bytes;
+//! use http_body_util::{Collected, Full};
+//! use modsecurity::{tower::ModSecurityLayer, ModSecurity, Rules};
+//! # extern crate tower_0_5 as tower;
+//! use tower::{service_fn, ServiceBuilder, ServiceExt};
+//!
+//! # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+//! let ms = Arc::new(ModSecurity::default());
+//!
+//! let mut rules = Rules::new();
+//! rules.add_plain(r#"
+//!     SecRuleEngine On
+//!
+//!     SecRule REQUEST_URI "@rx admin" "id:1,phase:1,deny,status:401"
+//! "#).expect("Failed to add rules");
+//!
+//! let service = ServiceBuilder::new()
+//!     .layer(ModSecurityLayer::new(ms, Arc::new(rules)))
+//!     .service(service_fn(|_: http::Request<Collected<Bytes>>| async {
+//!         Ok::<_, std::convert::Infallible>(http::Response::new(Full::new(Bytes::from("Hello"))))
+//!     }));
+//!
+//! let request = http::Request::get("/admin").body(Full::new(Bytes::new())).unwrap();
+//! let response = service.oneshot(request).await.unwrap();
+//!
+//! assert_eq!(response.status(), 401);
+//! # });
+//! ```
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    net::{Ipv4Addr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, Mutex, PoisonError},
+    task::{Context, Poll},
+};
+
+use bytes::{Buf, BufMut, Bytes};
+use http_body::{Body, Frame, SizeHint};
+use http_body_util::{BodyExt, Collected, Full};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    body::{InspectedBody, InspectedBodyError},
+    http::{version_str, Disruption},
+    intervention::Intervention,
+    msc::ModSecurity,
+    rules::Rules,
+    transaction::OwnedTransaction,
+};
+
+pub use crate::http::{DEFAULT_REQUEST_BODY_LIMIT, DEFAULT_RESPONSE_BODY_LIMIT};
+
+/// The addresses of the connection a request was received on.
+///
+/// [`ModSecurityService`] reads this from the request extensions to process the connection. If
+/// it is missing, unspecified addresses are used instead.
+#[derive(Clone, Copy, Debug)]
+pub struct ConnectionInfo {
+    /// The address of the client.
+    pub peer: SocketAddr,
+    /// The address the request was received on.
+    pub local: SocketAddr,
+}
+
+impl Default for ConnectionInfo {
+    fn default() -> Self {
+        let unspecified = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0));
+
+        Self {
+            peer: unspecified,
+            local: unspecified,
+        }
+    }
+}
+
+type InterventionResponse =
+    Arc<dyn Fn(&Intervention) -> http::Response<Full<Bytes>> + Send + Sync + 'static>;
+
+/// A [`Layer`] that wraps services in a [`ModSecurityService`].
+#[derive(Clone)]
+pub struct ModSecurityLayer {
+    ms: Arc<ModSecurity>,
+    rules: Arc<Rules>,
+    intervention_response: InterventionResponse,
+    request_body_limit: usize,
+    response_body_limit: usize,
+    inspect_response_body: bool,
+}
+
+impl ModSecurityLayer {
+    /// Creates a new layer that inspects traffic using `ms` and `rules`.
+    ///
+    /// Response bodies are not inspected unless enabled with
+    /// [`ModSecurityLayer::with_response_body_inspection()`].
+    pub fn new(ms: Arc<ModSecurity>, rules: Arc<Rules>) -> Self {
+        Self {
+            ms,
+            rules,
+            intervention_response: Arc::new(intervention_response),
+            request_body_limit: DEFAULT_REQUEST_BODY_LIMIT,
+            response_body_limit: DEFAULT_RESPONSE_BODY_LIMIT,
+            inspect_response_body: false,
+        }
+    }
+
+    /// Overrides the response that is sent when a disruptive intervention is raised.
+    ///
+    /// By default, the response has the status of the intervention (or `403 Forbidden` if it is
+    /// not a valid status), a `Location` header if the intervention has a redirect URL, and an
+    /// empty body.
+    pub fn with_intervention_response<F>(mut self, response: F) -> Self
+    where
+        F: Fn(&Intervention) -> http::Response<Full<Bytes>> + Send + Sync + 'static,
+    {
+        self.intervention_response = Arc::new(response);
+        self
+    }
+
+    /// Sets the maximum size of request bodies, in bytes. Defaults to
+    /// [`DEFAULT_REQUEST_BODY_LIMIT`].
+    ///
+    /// Requests with a larger body are rejected with a `413 Payload Too Large` response as soon
+    /// as the limit is exceeded, without calling the inner service.
+    pub fn with_request_body_limit(mut self, limit: usize) -> Self {
+        self.request_body_limit = limit;
+        self
+    }
+
+    /// Sets the maximum size of response bodies that are buffered for inspection, in bytes.
+    /// Defaults to [`DEFAULT_RESPONSE_BODY_LIMIT`].
+    ///
+    /// Only the first `limit` bytes of larger bodies are inspected, and the rest is streamed
+    /// through, as ModSecurity does with `SecResponseBodyLimitAction ProcessPartial`.
+    pub fn with_response_body_limit(mut self, limit: usize) -> Self {
+        self.response_body_limit = limit;
+        self
+    }
+
+    /// Sets whether response bodies are buffered and inspected. Disabled by default.
+    ///
+    /// This should be enabled when rules inspect response bodies, i.e. with
+    /// `SecResponseBodyAccess On`. Otherwise, response bodies are streamed through without being
+    /// buffered, and the response body phase is skipped.
+    pub fn with_response_body_inspection(mut self, enabled: bool) -> Self {
+        self.inspect_response_body = enabled;
+        self
+    }
+}
+
+impl<S> Layer<S> for ModSecurityLayer {
+    type Service = ModSecurityService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ModSecurityService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// The body of the responses of a [`ModSecurityService`].
+pub enum ResponseBody<B> {
+    /// A body that was built for an intervention.
+    Full(Full<Bytes>),
+    /// The body of the inner service, read for inspection.
+    Buffered(BufferedBody<B>),
+    /// The body of the inner service, streamed through without inspection.
+    Streamed(Pin<Box<B>>),
+}
+
+impl<B: Body> Body for ResponseBody<B> {
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match self.get_mut() {
+            ResponseBody::Full(body) => Pin::new(body)
+                .poll_frame(cx)
+                .map_err(|never| match never {}),
+            ResponseBody::Buffered(body) => Pin::new(body).poll_frame(cx),
+            ResponseBody::Streamed(body) => body
+                .as_mut()
+                .poll_frame(cx)
+                .map_ok(|frame| frame.map_data(|mut data| data.copy_to_bytes(data.remaining()))),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match self {
+            ResponseBody::Full(body) => body.is_end_stream(),
+            ResponseBody::Buffered(body) => body.is_end_stream(),
+            ResponseBody::Streamed(body) => body.is_end_stream(),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self {
+            ResponseBody::Full(body) => body.size_hint(),
+            ResponseBody::Buffered(body) => body.size_hint(),
+            ResponseBody::Streamed(body) => body.size_hint(),
+        }
+    }
+}
+
+/// The body of a response of the inner service, after it was read for inspection.
+///
+/// The frames that were read, including trailers, are sent first. If the body was larger than
+/// the response body limit, they are followed by the rest of it.
+pub struct BufferedBody<B> {
+    frames: VecDeque<Frame<Bytes>>,
+    rest: Option<Pin<Box<B>>>,
+}
+
+impl<B: Body> BufferedBody<B> {
+    /// Reads the frames of `body` until it ends, or until more than `limit` bytes of data have
+    /// been read.
+    async fn read(mut body: Pin<Box<B>>, limit: usize) -> Result<Self, B::Error> {
+        let mut frames = VecDeque::new();
+        let mut len = 0;
+
+        while len <= limit {
+            let frame = match body.frame().await {
+                Some(frame) => frame?.map_data(|mut data| data.copy_to_bytes(data.remaining())),
+                None => return Ok(Self { frames, rest: None }),
+            };
+
+            if let Some(data) = frame.data_ref() {
+                len += data.len();
+            }
+
+            frames.push_back(frame);
+        }
+
+        Ok(Self {
+            frames,
+            rest: Some(body),
+        })
+    }
+
+    /// Returns the data that was read, up to `limit` bytes.
+    fn data(&self, limit: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        for frame in &self.frames {
+            if let Some(frame) = frame.data_ref() {
+                data.put_slice(frame);
+            }
+        }
+
+        data.truncate(limit);
+        data
+    }
+}
+
+impl<B: Body> Body for BufferedBody<B> {
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+
+        if let Some(frame) = this.frames.pop_front() {
+            return Poll::Ready(Some(Ok(frame)));
+        }
+
+        match &mut this.rest {
+            Some(rest) => rest
+                .as_mut()
+                .poll_frame(cx)
+                .map_ok(|frame| frame.map_data(|mut data| data.copy_to_bytes(data.remaining()))),
+            None => Poll::Ready(None),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.frames.is_empty()
+            && match &self.rest {
+                Some(rest) => rest.is_end_stream(),
+                None => true,
+            }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let buffered = self
+            .frames
+            .iter()
+            .filter_map(Frame::data_ref)
+            .map(|data| data.len() as u64)
+            .sum();
+
+        match &self.rest {
+            Some(rest) => {
+                let rest = rest.size_hint();
+                let mut hint = SizeHint::new();
+                hint.set_lower(rest.lower() + buffered);
+                if let Some(upper) = rest.upper() {
+                    hint.set_upper(upper + buffered);
+                }
+                hint
+            }
+            None => SizeHint::with_exact(buffered),
+        }
+    }
+}
+
+/// A [`Service`] that enforces ModSecurity rules on the requests and responses of an inner
+/// service. See [`ModSecurityLayer`].
+///
+/// The inner service receives the request with its body buffered, including its trailers.
+///
+/// Errors from ModSecurity result in a `500 Internal Server Error` response. Errors reading the
+/// request body result in a `400 Bad Request` response, and errors reading the response body of
+/// the inner service in a `502 Bad Gateway` response. The logging phase runs in all these cases,
+/// except if the transaction could not be created.
+#[derive(Clone)]
+pub struct ModSecurityService<S> {
+    inner: S,
+    layer: ModSecurityLayer,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for ModSecurityService<S>
+where
+    S: Service<http::Request<Collected<Bytes>>, Response = http::Response<ResBody>>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+    ReqBody: Body + Send + 'static,
+    ReqBody::Data: Send,
+    ReqBody::Error: Send,
+    ResBody: Body + Send + 'static,
+    ResBody::Data: Send,
+{
+    type Response = http::Response<ResponseBody<ResBody>>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        // The inner service has been driven to readiness, so we take it and leave a clone in its
+        // place for the next call.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let layer = self.layer.clone();
+
+        Box::pin(async move {
+            let mut transaction = match layer
+                .ms
+                .shared_transaction_builder()
+                .with_shared_rules(Arc::clone(&layer.rules))
+                .build()
+            {
+                Ok(transaction) => transaction,
+                Err(_) => return Ok(status_response(http::StatusCode::INTERNAL_SERVER_ERROR)),
+            };
+
+            let connection = request
+                .extensions()
+                .get::<ConnectionInfo>()
+                .copied()
+                .unwrap_or_default();
+            let (parts, body) = request.into_parts();
+
+            match transaction.inspect_request_headers(
+                connection.peer,
+                connection.local,
+                parts.method.as_str(),
+                &parts.uri.to_string(),
+                version_str(parts.version),
+                parts
+                    .headers
+                    .iter()
+                    .map(|(key, value)| (key.as_str().as_bytes(), value.as_bytes())),
+            ) {
+                Ok(None) => {}
+                Ok(Some(disruption)) => {
+                    return Ok(respond(&layer, &mut transaction, disruption));
+                }
+                Err(_) => {
+                    return Ok(reject(
+                        &mut transaction,
+                        http::StatusCode::INTERNAL_SERVER_ERROR,
+                    ))
+                }
+            }
+
+            let shared = Arc::new(Mutex::new(transaction));
+            let body = InspectedBody::new(Box::pin(body), Arc::clone(&shared))
+                .with_limit(layer.request_body_limit)
+                .collect()
+                .await;
+
+            // The inspected body has been consumed, so this is the only reference left.
+            let mut transaction = match Arc::try_unwrap(shared) {
+                Ok(transaction) => transaction
+                    .into_inner()
+                    .unwrap_or_else(PoisonError::into_inner),
+                Err(_) => unreachable!("The inspected body has been dropped"),
+            };
+
+            let body = match body {
+                Ok(body) => body,
+                Err(InspectedBodyError::Intervention(disruption)) => {
+                    return Ok(respond(&layer, &mut transaction, disruption));
+                }
+                Err(InspectedBodyError::LimitExceeded(_)) => {
+                    return Ok(reject(
+                        &mut transaction,
+                        http::StatusCode::PAYLOAD_TOO_LARGE,
+                    ));
+                }
+                Err(InspectedBodyError::Body(_)) => {
+                    return Ok(reject(&mut transaction, http::StatusCode::BAD_REQUEST));
+                }
+                Err(InspectedBodyError::ModSecurity(_)) => {
+                    return Ok(reject(
+                        &mut transaction,
+                        http::StatusCode::INTERNAL_SERVER_ERROR,
+                    ));
+                }
+            };
+
+            let response = match inner.call(http::Request::from_parts(parts, body)).await {
+                Ok(response) => response,
+                Err(err) => {
+                    let _ = transaction.process_logging();
+                    return Err(err);
+                }
+            };
+            let (parts, body) = response.into_parts();
+
+            match transaction.inspect_response_headers(
+                parts.status.as_u16(),
+                version_str(parts.version),
+                parts
+                    .headers
+                    .iter()
+                    .map(|(key, value)| (key.as_str().as_bytes(), value.as_bytes())),
+            ) {
+                Ok(None) => {}
+                Ok(Some(disruption)) => {
+                    return Ok(respond(&layer, &mut transaction, disruption));
+                }
+                Err(_) => {
+                    return Ok(reject(
+                        &mut transaction,
+                        http::StatusCode::INTERNAL_SERVER_ERROR,
+                    ))
+                }
+            }
+
+            // Without inspection, the response body phase is skipped rather than run on an
+            // empty body.
+            let body = if layer.inspect_response_body {
+                let limit = layer.response_body_limit;
+                let body = match BufferedBody::read(Box::pin(body), limit).await {
+                    Ok(body) => body,
+                    Err(_) => return Ok(reject(&mut transaction, http::StatusCode::BAD_GATEWAY)),
+                };
+
+                match transaction.inspect_response_body(&body.data(limit), true) {
+                    Ok(None) => {}
+                    Ok(Some(disruption)) => {
+                        return Ok(respond(&layer, &mut transaction, disruption));
+                    }
+                    Err(_) => {
+                        return Ok(reject(
+                            &mut transaction,
+                            http::StatusCode::INTERNAL_SERVER_ERROR,
+                        ))
+                    }
+                }
+
+                ResponseBody::Buffered(body)
+            } else {
+                ResponseBody::Streamed(Box::pin(body))
+            };
+
+            // The response has already been decided at this point, so any intervention raised
+            // by the logging phase is not acted upon.
+            let _ = transaction.process_logging();
+
+            Ok(http::Response::from_parts(parts, body))
+        })
+    }
+}
+
+/// Builds the response for `disruption`, running the logging phase first.
+fn respond<B>(
+    layer: &ModSecurityLayer,
+    transaction: &mut OwnedTransaction,
+    disruption: Disruption,
+) -> http::Response<ResponseBody<B>> {
+    let _ = transaction.process_logging();
+    (layer.intervention_response)(&disruption.intervention).map(ResponseBody::Full)
+}
+
+/// Builds an empty response with `status` for a request that could not be inspected, running the
+/// logging phase first.
+fn reject<B>(
+    transaction: &mut OwnedTransaction,
+    status: http::StatusCode,
+) -> http::Response<ResponseBody<B>> {
+    let _ = transaction.process_logging();
+    status_response(status)
+}
+
+fn status_response<B>(status: http::StatusCode) -> http::Response<ResponseBody<B>> {
+    let mut response = http::Response::new(ResponseBody::Full(Full::default()));
+    *response.status_mut() = status;
+    response
+}
+
+/// The default response for an intervention. See
+/// [`ModSecurityLayer::with_intervention_response()`].
+fn intervention_response(intervention: &Intervention) -> http::Response<Full<Bytes>> {
+    let mut response = http::Response::new(Full::default());
+    *response.status_mut() = intervention.response_status();
+
+    if let Some(url) = intervention
+        .url()
+        .and_then(|url| http::HeaderValue::from_str(url).ok())
+    {
+        response.headers_mut().insert(http::header::LOCATION, url);
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+
+    use tower_0_5::{service_fn, ServiceBuilder, ServiceExt};
+
+    use super::*;
+
+    fn rules(plain_rules: &str) -> Arc<Rules> {
+        let mut rules = Rules::new();
+        rules.add_plain(plain_rules).unwrap();
+        Arc::new(rules)
+    }
+
+    fn request(uri: &str) -> http::Request<Full<Bytes>> {
+        http::Request::get(uri).body(Full::default()).unwrap()
+    }
+
+    fn empty_response() -> http::Response<Full<Bytes>> {
+        http::Response::new(Full::default())
+    }
+
+    async fn body_bytes<B: Body>(response: http::Response<B>) -> Bytes
+    where
+        B::Error: std::fmt::Debug,
+    {
+        response.into_body().collect().await.unwrap().to_bytes()
+    }
+
+    #[tokio::test]
+    async fn test_allowed() {
+        let layer = ModSecurityLayer::new(
+            Arc::new(ModSecurity::default()),
+            rules(
+                r#"
+                SecRuleEngine On
+
+                SecRule REQUEST_URI "@rx admin" "id:1,phase:1,deny,status:401"
+            "#,
+            ),
+        );
+
+        let service = ServiceBuilder::new().layer(layer).service(service_fn(
+            |request: http::Request<Collected<Bytes>>| async move {
+                let body = request.into_body().collect().await.unwrap().to_bytes();
+                Ok::<_, Infallible>(http::Response::new(Full::new(body)))
+            },
+        ));
+
+        let request = http::Request::post("/index.html")
+            .body(Full::new(Bytes::from("echo")))
+            .unwrap();
+        let response = service.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(body_bytes(response).await, "echo");
+    }
+
+    #[tokio::test]
+    async fn test_request_intervention() {
+        let layer = ModSecurityLayer::new(
+            Arc::new(ModSecurity::default()),
+            rules(
+                r#"
+                SecRuleEngine On
+
+                SecRule REQUEST_URI "@rx admin" "id:1,phase:1,deny,status:401"
+            "#,
+            ),
+        );
+
+        let called = Arc::new(AtomicBool::new(false));
+
+        let service = ServiceBuilder::new().layer(layer).service(service_fn({
+            let called = Arc::clone(&called);
+            move |_: http::Request<Collected<Bytes>>| {
+                called.store(true, Ordering::SeqCst);
+                async { Ok::<_, Infallible>(empty_response()) }
+            }
+        }));
+
+        let response = service.oneshot(request("/admin")).await.unwrap();
+
+        assert_eq!(response.status(), 401);
+        assert!(!called.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_response_intervention() {
+        let layer = ModSecurityLayer::new(
+            Arc::new(ModSecurity::default()),
+            rules(
+                r#"
+                SecRuleEngine On
+
+                SecResponseBodyAccess On
+
+                SecRule RESPONSE_BODY "@rx secret" "id:1,phase:4,deny,status:500"
+            "#,
+            ),
+        )
+        .with_response_body_inspection(true);
+
+        let service = ServiceBuilder::new().layer(layer).service(service_fn(
+            |_: http::Request<Collected<Bytes>>| async {
+                Ok::<_, Infallible>(http::Response::new(Full::new(Bytes::from("a secret"))))
+            },
+        ));
+
+        let response = service.oneshot(request("/")).await.unwrap();
+
+        assert_eq!(response.status(), 500);
+        assert!(body_bytes(response).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_response_body_phase_skipped() {
+        let layer = ModSecurityLayer::new(
+            Arc::new(ModSecurity::default()),
+            rules(
+                r#"
+                SecRuleEngine On
+
+                SecRule REQUEST_URI "@rx ." "id:1,phase:4,deny,status:500"
+            "#,
+            ),
+        );
+
+        let service = ServiceBuilder::new().layer(layer).service(service_fn(
+            |_: http::Request<Collected<Bytes>>| async {
+                Ok::<_, Infallible>(http::Response::new(Full::new(Bytes::from("hello"))))
+            },
+        ));
+
+        let response = service.oneshot(request("/")).await.unwrap();
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(body_bytes(response).await, "hello");
+    }
+
+    #[tokio::test]
+    async fn test_redirect() {
+        let layer = ModSecurityLayer::new(
+            Arc::new(ModSecurity::default()),
+            rules(
+                r#"
+                SecRuleEngine On
+
+                SecRule REQUEST_URI "@rx admin" "id:1,phase:1,redirect:https://example.com/login"
+            "#,
+            ),
+        );
+
+        let service = ServiceBuilder::new().layer(layer).service(service_fn(
+            |_: http::Request<Collected<Bytes>>| async { Ok::<_, Infallible>(empty_response()) },
+        ));
+
+        let response = service.oneshot(request("/admin")).await.unwrap();
+
+        assert_eq!(response.status(), 302);
+        assert_eq!(
+            response.headers().get(http::header::LOCATION).unwrap(),
+            "https://example.com/login"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_custom_intervention_response() {
+        let layer = ModSecurityLayer::new(
+            Arc::new(ModSecurity::default()),
+            rules(
+                r#"
+                SecRuleEngine On
+
+                SecRule REQUEST_URI "@rx admin" "id:1,phase:1,deny,status:401"
+            "#,
+            ),
+        )
+        .with_intervention_response(|intervention| {
+            http::Response::builder()
+                .status(intervention.status() as u16)
+                .body(Full::new(Bytes::from("Blocked")))
+                .unwrap()
+        });
+
+        let service = ServiceBuilder::new().layer(layer).service(service_fn(
+            |_: http::Request<Collected<Bytes>>| async { Ok::<_, Infallible>(empty_response()) },
+        ));
+
+        let response = service.oneshot(request("/admin")).await.unwrap();
+
+        assert_eq!(response.status(), 401);
+        assert_eq!(body_bytes(response).await, "Blocked");
+    }
+
+    #[tokio::test]
+    async fn test_connection_info() {
+        let layer = ModSecurityLayer::new(
+            Arc::new(ModSecurity::default()),
+            rules(
+                r#"
+                SecRuleEngine On
+
+                SecRule REMOTE_ADDR "@ipMatch 124.123.122.121" "id:1,phase:1,deny,status:403"
+            "#,
+            ),
+        );
+
+        let service = ServiceBuilder::new().layer(layer).service(service_fn(
+            |_: http::Request<Collected<Bytes>>| async { Ok::<_, Infallible>(empty_response()) },
+        ));
+
+        let mut request = request("/");
+        request.extensions_mut().insert(ConnectionInfo {
+            peer: "124.123.122.121:12345".parse().unwrap(),
+            local: "127.0.0.1:80".parse().unwrap(),
+        });
+
+        let response = service.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn test_request_body_limit() {
+        let layer = ModSecurityLayer::new(Arc::new(ModSecurity::default()), rules(""))
+            .with_request_body_limit(4);
+
+        let called = Arc::new(AtomicBool::new(false));
+
+        let service = ServiceBuilder::new().layer(layer).service(service_fn({
+            let called = Arc::clone(&called);
+            move |_: http::Request<Collected<Bytes>>| {
+                called.store(true, Ordering::SeqCst);
+                async { Ok::<_, Infallible>(empty_response()) }
+            }
+        }));
+
+        let request = http::Request::post("/")
+            .body(Full::new(Bytes::from("too long")))
+            .unwrap();
+        let response = service.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), 413);
+        assert!(!called.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_response_body_limit() {
+        let layer = ModSecurityLayer::new(
+            Arc::new(ModSecurity::default()),
+            rules(
+                r#"
+                SecRuleEngine On
+
+                SecResponseBodyAccess On
+
+                SecRule RESPONSE_BODY "@rx long" "id:1,phase:4,deny,status:500"
+            "#,
+            ),
+        )
+        .with_response_body_limit(4)
+        .with_response_body_inspection(true);
+
+        let service = ServiceBuilder::new().layer(layer).service(service_fn(
+            |_: http::Request<Collected<Bytes>>| async {
+                Ok::<_, Infallible>(http::Response::new(Full::new(Bytes::from("too long"))))
+            },
+        ));
+
+        let response = service.oneshot(request("/")).await.unwrap();
+
+        // Only the part of the body within the limit is inspected, and the rest is passed on.
+        assert_eq!(response.status(), 200);
+        assert_eq!(body_bytes(response).await, "too long");
+    }
+
+    #[tokio::test]
+    async fn test_response_body_streamed() {
+        let layer = ModSecurityLayer::new(Arc::new(ModSecurity::default()), rules(""));
+
+        let service = ServiceBuilder::new().layer(layer).service(service_fn(
+            |_: http::Request<Collected<Bytes>>| async {
+                Ok::<_, Infallible>(http::Response::new(Full::new(Bytes::from("not buffered"))))
+            },
+        ));
+
+        let response = service.oneshot(request("/")).await.unwrap();
+
+        assert_eq!(response.status(), 200);
+        assert!(matches!(response.body(), ResponseBody::Streamed(_)));
+        assert_eq!(body_bytes(response).await, "not buffered");
+    }
+
+    #[tokio::test]
+    async fn test_trailers() {
+        fn trailers() -> http::HeaderMap {
+            let mut trailers = http::HeaderMap::new();
+            trailers.insert("grpc-status", http::HeaderValue::from_static("0"));
+            trailers
+        }
+
+        for inspect_response_body in [false, true] {
+            let layer = ModSecurityLayer::new(Arc::new(ModSecurity::default()), rules(""))
+                .with_response_body_inspection(inspect_response_body);
+
+            let service = ServiceBuilder::new().layer(layer).service(service_fn(
+                |request: http::Request<Collected<Bytes>>| async move {
+                    let request = request.into_body().collect().await.unwrap();
+                    assert_eq!(request.trailers(), Some(&trailers()));
+
+                    let body =
+                        Full::new(request.to_bytes()).with_trailers(async { Some(Ok(trailers())) });
+                    Ok::<_, Infallible>(http::Response::new(body))
+                },
+            ));
+
+            let request = http::Request::post("/")
+                .body(Full::new(Bytes::from("echo")).with_trailers(async { Some(Ok(trailers())) }))
+                .unwrap();
+            let response = service.oneshot(request).await.unwrap();
+
+            assert_eq!(response.status(), 200);
+
+            let body = response.into_body().collect().await.unwrap();
+            assert_eq!(
+                body.trailers(),
+                Some(&trailers()),
+                "{}",
+                inspect_response_body
+            );
+            assert_eq!(body.to_bytes(), "echo");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_buffered_body() {
+        let body = Full::new(Bytes::from("too long"));
+        let buffered = BufferedBody::read(Box::pin(body), 4).await.unwrap();
+
+        assert_eq!(buffered.data(4), b"too ");
+        assert_eq!(buffered.size_hint().exact(), Some(8));
+        assert_eq!(
+            buffered.collect().await.unwrap().to_bytes(),
+            Bytes::from("too long")
+        );
+    }
+
+    #[test]
+    fn test_default_connection_info() {
+        let connection = ConnectionInfo::default();
+
+        assert!(connection.peer.ip().is_unspecified());
+        assert!(connection.local.ip().is_unspecified());
+    }
+}
@@ -0,0 +1,319 @@
+//! `tracing` spans and events for transactions.
+//!
+//! Each transaction is recorded in a `transaction` span, with its ID as the `id` field. Within
+//! it, an event is emitted for each phase with its duration and result, for each log message
+//! when log callbacks are enabled, and for each intervention found.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tracing::Span;
+
+use crate::{
+    bindings::RawBindings,
+    intervention::Intervention,
+    rule_match::RuleMatch,
+    transaction::{BytesLogCallback, Phase},
+    ModSecurityResult,
+};
+
+/// Generates an ID for the span of a transaction that was not given one.
+pub(crate) fn generate_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let micros = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros();
+
+    format!("{}-{}", micros, COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Creates the span for a transaction.
+pub(crate) fn transaction_span(id: &str) -> Span {
+    tracing::info_span!("transaction", id)
+}
+
+/// Emits an event for a phase that was processed.
+pub(crate) fn phase_event(phase: Phase, elapsed: Duration, result: &ModSecurityResult<()>) {
+    let elapsed_us = elapsed.as_micros() as u64;
+
+    match result {
+        Ok(()) => tracing::debug!(?phase, elapsed_us, result = "ok", "processed phase"),
+        Err(err) => {
+            tracing::warn!(?phase, elapsed_us, result = "error", error = %err, "failed to process phase")
+        }
+    }
+}
+
+/// Wraps a log callback so that an event is emitted for each log message before it is called.
+pub(crate) fn log_callback(inner: Option<BytesLogCallback>) -> BytesLogCallback {
+    Box::new(move |msg| {
+        if let Some(msg) = msg {
+            let log = String::from_utf8_lossy(msg);
+
+            match RuleMatch::parse(&log) {
+                Some(rule_match) => tracing::info!(
+                    rule_id = rule_match.id,
+                    severity = ?rule_match.severity,
+                    msg = rule_match.msg.as_deref(),
+                    tags = ?rule_match.tags,
+                    matched_variable = rule_match.matched_variable.as_deref(),
+                    log = %log,
+                    "rule matched"
+                ),
+                None => tracing::info!(log = %log, "log message"),
+            }
+        }
+
+        if let Some(inner) = &inner {
+            inner(msg);
+        }
+    })
+}
+
+/// Emits an event for an intervention that was found.
+pub(crate) fn intervention_event<B: RawBindings>(span: &Span, intervention: &Intervention<B>) {
+    let rule_match = intervention.rule_match();
+
+    tracing::info!(
+        parent: span,
+        status = intervention.status(),
+        disruptive = intervention.disruptive(),
+        url = intervention.url(),
+        rule_id = rule_match.and_then(|rule_match| rule_match.id),
+        log = intervention.log(),
+        "intervention"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        fmt,
+        sync::{Arc, Mutex},
+    };
+
+    use tracing::{
+        field::{Field, Visit},
+        span, Event, Metadata, Subscriber,
+    };
+
+    use crate::{msc::ModSecurity, rules::Rules, transaction::tests::LogBindings};
+
+    /// A span or event, with its fields formatted with `Debug`.
+    #[derive(Clone, Debug, Default)]
+    struct Record {
+        name: String,
+        fields: HashMap<String, String>,
+        /// The `id` field of the span the event was recorded in.
+        span_id: Option<String>,
+    }
+
+    impl Visit for Record {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.fields
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+
+    /// A subscriber that records spans and events.
+    #[derive(Clone, Default)]
+    struct Recorder {
+        spans: Arc<Mutex<Vec<Record>>>,
+        events: Arc<Mutex<Vec<Record>>>,
+        stack: Arc<Mutex<Vec<u64>>>,
+    }
+
+    impl Recorder {
+        fn events(&self, name: &str) -> Vec<Record> {
+            self.events
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|event| event.name == name)
+                .cloned()
+                .collect()
+        }
+
+        fn span_id(&self, id: &span::Id) -> Option<String> {
+            self.spans.lock().unwrap()[id.into_u64() as usize - 1]
+                .fields
+                .get("id")
+                .cloned()
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, attributes: &span::Attributes<'_>) -> span::Id {
+            let mut record = Record {
+                name: attributes.metadata().name().to_string(),
+                ..Default::default()
+            };
+            attributes.record(&mut record);
+
+            let mut spans = self.spans.lock().unwrap();
+            spans.push(record);
+            span::Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut record = Record::default();
+            event.record(&mut record);
+            record.name = record
+                .fields
+                .remove("message")
+                .map(|message| message.to_string())
+                .unwrap_or_default();
+
+            let parent = event.parent().cloned().or_else(|| {
+                self.stack
+                    .lock()
+                    .unwrap()
+                    .last()
+                    .map(|id| span::Id::from_u64(*id))
+            });
+            record.span_id = parent.and_then(|parent| self.span_id(&parent));
+
+            self.events.lock().unwrap().push(record);
+        }
+
+        fn enter(&self, span: &span::Id) {
+            self.stack.lock().unwrap().push(span.into_u64());
+        }
+
+        fn exit(&self, _: &span::Id) {
+            self.stack.lock().unwrap().pop();
+        }
+    }
+
+    fn with_recorder(f: impl FnOnce()) -> Recorder {
+        let recorder = Recorder::default();
+        tracing::subscriber::with_default(recorder.clone(), f);
+        recorder
+    }
+
+    #[test]
+    fn test_transaction_span() {
+        let recorder = with_recorder(|| {
+            let ms = ModSecurity::<LogBindings>::builder().build();
+            let rules = Rules::new();
+
+            let _ = ms
+                .transaction_builder()
+                .with_rules(&rules)
+                .with_id("abc")
+                .build()
+                .unwrap();
+            let _ = ms.transaction_builder().with_rules(&rules).build().unwrap();
+        });
+
+        let spans = recorder.spans.lock().unwrap();
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0].name, "transaction");
+        assert_eq!(spans[0].fields["id"], "\"abc\"");
+        // Transactions without an ID are given a generated one on their span.
+        assert_ne!(spans[1].fields["id"], spans[0].fields["id"]);
+    }
+
+    #[test]
+    fn test_phase_events() {
+        let recorder = with_recorder(|| {
+            let ms = ModSecurity::<LogBindings>::builder().build();
+            let rules = Rules::new();
+
+            let mut transaction = ms
+                .transaction_builder()
+                .with_rules(&rules)
+                .with_id("abc")
+                .build()
+                .unwrap();
+
+            transaction.process_request_headers().unwrap();
+            // Errors before the library is called are recorded as well.
+            assert!(transaction.process_response_headers(200, "\0").is_err());
+        });
+
+        let processed = recorder.events("processed phase");
+        assert_eq!(processed.len(), 1);
+        assert_eq!(processed[0].fields["phase"], "RequestHeaders");
+        assert_eq!(processed[0].fields["result"], "\"ok\"");
+        assert!(processed[0].fields.contains_key("elapsed_us"));
+        assert_eq!(processed[0].span_id.as_deref(), Some("\"abc\""));
+
+        let failed = recorder.events("failed to process phase");
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].fields["phase"], "ResponseHeaders");
+        assert_eq!(failed[0].fields["result"], "\"error\"");
+        assert!(failed[0].fields.contains_key("error"));
+    }
+
+    #[test]
+    fn test_log_events() {
+        let logs = Arc::new(Mutex::new(Vec::new()));
+
+        let recorder = with_recorder(|| {
+            let ms = ModSecurity::<LogBindings>::builder()
+                .with_log_callbacks()
+                .build();
+            let rules = Rules::new();
+
+            let mut transaction = ms
+                .transaction_builder()
+                .with_rules(&rules)
+                .with_id("abc")
+                .with_logging({
+                    let logs = Arc::clone(&logs);
+                    move |msg| logs.lock().unwrap().push(msg.map(str::to_string))
+                })
+                .build()
+                .unwrap();
+
+            transaction.process_request_headers().unwrap();
+        });
+
+        let events = recorder.events("rule matched");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].fields["rule_id"], "7");
+        assert_eq!(events[0].fields["severity"], "None");
+        assert_eq!(events[0].span_id.as_deref(), Some("\"abc\""));
+
+        // The callback of the transaction is still called, including for the end of the messages.
+        assert_eq!(logs.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_intervention_event() {
+        let recorder = with_recorder(|| {
+            let ms = ModSecurity::<LogBindings>::builder().build();
+            let rules = Rules::new();
+
+            let mut transaction = ms
+                .transaction_builder()
+                .with_rules(&rules)
+                .with_id("abc")
+                .build()
+                .unwrap();
+
+            assert!(transaction.intervention().is_some());
+        });
+
+        let events = recorder.events("intervention");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].fields["status"], "403");
+        assert_eq!(events[0].fields["disruptive"], "true");
+        assert_eq!(events[0].fields["rule_id"], "5");
+        assert_eq!(events[0].span_id.as_deref(), Some("\"abc\""));
+    }
+}
@@ -1,9 +1,11 @@
 //! ModSecurity transaction API.
 
 use std::{
-    ffi::CString,
-    marker::PhantomData,
+    ffi::{CStr, CString},
+    ops::Deref,
     os::raw::{c_char, c_uchar, c_void},
+    path::Path,
+    sync::Arc,
 };
 
 use crate::{
@@ -14,49 +16,90 @@ use crate::{
     error::ModSecurityError,
     intervention::Intervention,
     msc::ModSecurity,
+    phased::{state, PhasedTransaction},
+    rule_match::RuleMatch,
     rules::Rules,
     ModSecurityResult,
 };
 
+/// A reference to a [`ModSecurity`] or [`Rules`] instance used by a transaction.
+///
+/// Shared references keep the instance alive for as long as the transaction, which is what
+/// allows a transaction to be `'static`.
+enum Handle<'a, T> {
+    Borrowed(&'a T),
+    Shared(Arc<T>),
+}
+
+impl<T> Deref for Handle<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match self {
+            Handle::Borrowed(inner) => inner,
+            Handle::Shared(inner) => inner,
+        }
+    }
+}
+
 /// An intermediate builder for creating a [`TransactionBuilder`]
 pub struct TransactionBuilderWithoutRules<'a, B: RawBindings = Bindings> {
-    ms: &'a ModSecurity<B>,
+    ms: Handle<'a, ModSecurity<B>>,
 }
 
 impl<'a, B: RawBindings> TransactionBuilderWithoutRules<'a, B> {
     pub(crate) fn new(ms: &'a ModSecurity<B>) -> Self {
-        Self { ms }
+        Self {
+            ms: Handle::Borrowed(ms),
+        }
+    }
+
+    pub(crate) fn new_shared(ms: Arc<ModSecurity<B>>) -> Self {
+        Self {
+            ms: Handle::Shared(ms),
+        }
     }
 
     /// Creates a new transaction builder with the given rules.
     pub fn with_rules(self, rules: &'a Rules<B>) -> TransactionBuilder<'a, B> {
-        TransactionBuilder::new(self.ms, rules)
+        TransactionBuilder::new(self.ms, Handle::Borrowed(rules))
+    }
+
+    /// Creates a new transaction builder with the given shared rules.
+    ///
+    /// The transaction keeps the rules alive for as long as it exists. Combined with
+    /// [`ModSecurity::shared_transaction_builder()`], this creates an [`OwnedTransaction`].
+    pub fn with_shared_rules(self, rules: Arc<Rules<B>>) -> TransactionBuilder<'a, B> {
+        TransactionBuilder::new(self.ms, Handle::Shared(rules))
     }
 }
 
 /// Builds a ModSecurity transaction with custom configuration.
 pub struct TransactionBuilder<'a, B: RawBindings = Bindings> {
-    ms: &'a ModSecurity<B>,
-    rules: &'a Rules<B>,
-    log_cb: Option<LogCallback>,
-    id: Option<&'a str>,
-    _bindings: PhantomData<B>,
+    ms: Handle<'a, ModSecurity<B>>,
+    rules: Handle<'a, Rules<B>>,
+    log_cb: Option<BytesLogCallback>,
+    id: Option<String>,
+    hostname: Option<String>,
 }
 
 impl<'a, B: RawBindings> TransactionBuilder<'a, B> {
-    fn new(ms: &'a ModSecurity<B>, rules: &'a Rules<B>) -> Self {
+    fn new(ms: Handle<'a, ModSecurity<B>>, rules: Handle<'a, Rules<B>>) -> Self {
         Self {
             ms,
             rules,
             log_cb: None,
             id: None,
-            _bindings: PhantomData,
+            hostname: None,
         }
     }
 
     /// Sets a logging callback for the transaction that will be invoked for each generated
     /// log message.
     ///
+    /// With the `log` feature, transactions without a logging callback forward their log messages
+    /// to the `log` facade instead.
+    ///
     /// ## Examples
     ///
     /// ```
@@ -72,16 +115,97 @@ impl<'a, B: RawBindings> TransactionBuilder<'a, B> {
     /// }).build().expect("error building transaction");
     ///
     /// ```
-    pub fn with_logging<F>(mut self, log_cb: F) -> Self
+    ///
+    /// Log messages that are not valid UTF-8 are converted lossily. Use
+    /// [`TransactionBuilder::with_logging_bytes()`] to receive them as they are.
+    pub fn with_logging<F>(self, log_cb: F) -> Self
     where
         F: Fn(Option<&str>) + Send + Sync + 'static,
+    {
+        self.with_logging_bytes(move |msg| log_cb(msg.map(String::from_utf8_lossy).as_deref()))
+    }
+
+    /// Sets a logging callback for the transaction that will be invoked with the raw bytes of
+    /// each generated log message.
+    ///
+    /// Log messages can contain bytes from the request that are not valid UTF-8, which this
+    /// callback receives unchanged. This replaces any callback set with
+    /// [`TransactionBuilder::with_logging()`], and vice versa.
+    ///
+    /// ## Examples
+    ///
+    /// ```
+    /// use modsecurity::{ModSecurity, Rules};
+    ///
+    /// let ms = ModSecurity::builder().with_log_callbacks().build();
+    /// let rules = Rules::new();
+    ///
+    /// let transaction = ms
+    ///     .transaction_builder()
+    ///     .with_rules(&rules)
+    ///     .with_logging_bytes(|msg| {
+    ///         if let Some(msg) = msg {
+    ///             println!("Log: {}", msg.escape_ascii());
+    ///         }
+    ///     })
+    ///     .build()
+    ///     .expect("error building transaction");
+    /// ```
+    pub fn with_logging_bytes<F>(mut self, log_cb: F) -> Self
+    where
+        F: Fn(Option<&[u8]>) + Send + Sync + 'static,
     {
         self.log_cb = Some(Box::new(log_cb));
         self
     }
 
+    /// Sets a logging callback for the transaction that will be invoked with each rule match,
+    /// parsed from the generated log messages.
+    ///
+    /// Log messages that do not describe a rule match are not passed to the callback. See
+    /// [`RuleMatch::parse()`] for details. This replaces any callback set with
+    /// [`TransactionBuilder::with_logging()`], and vice versa.
+    ///
+    /// Rules with an `EMERGENCY` severity are reported with a [`severity`](RuleMatch::severity)
+    /// of [`Severity::Emergency`](crate::rule_match::Severity::Emergency), as are rules that do
+    /// not set a severity, since libmodsecurity does not tell the two apart.
+    ///
+    /// ## Examples
+    ///
+    /// ```
+    /// use modsecurity::{rule_match::Severity, ModSecurity, Rules};
+    ///
+    /// let ms = ModSecurity::builder().with_log_callbacks().build();
+    /// let rules = Rules::new();
+    ///
+    /// let transaction = ms
+    ///     .transaction_builder()
+    ///     .with_rules(&rules)
+    ///     .with_structured_logging(|rule_match| {
+    ///         if matches!(rule_match.severity, Some(s) if s <= Severity::Critical) {
+    ///             println!("Alert: rule {:?} matched: {:?}", rule_match.id, rule_match.msg);
+    ///         }
+    ///     })
+    ///     .build()
+    ///     .expect("error building transaction");
+    /// ```
+    pub fn with_structured_logging<F>(self, log_cb: F) -> Self
+    where
+        F: Fn(&RuleMatch) + Send + Sync + 'static,
+    {
+        self.with_logging(move |msg| {
+            if let Some(rule_match) = msg.and_then(RuleMatch::parse) {
+                log_cb(&rule_match);
+            }
+        })
+    }
+
     /// Sets an explicit transaction ID.
     ///
+    /// With the `tracing` feature, the ID is recorded on the span of the transaction. Transactions
+    /// without an explicit ID have a generated one recorded on their span instead, which is not
+    /// passed to libmodsecurity and so does not match the `unique_id` in its logs.
+    ///
     /// ## Examples
     ///
     /// ```
@@ -97,35 +221,135 @@ impl<'a, B: RawBindings> TransactionBuilder<'a, B> {
     ///     .build()
     ///     .expect("error building transaction");
     /// ```
-    pub fn with_id(mut self, id: &'a str) -> Self {
-        self.id = Some(id);
+    pub fn with_id(mut self, id: &str) -> Self {
+        self.id = Some(id.to_string());
+        self
+    }
+
+    /// Sets the hostname of the request (e.g. the virtual host it was received on).
+    ///
+    /// The hostname is applied as soon as the transaction is created, so it is available to
+    /// rules in every phase. See [`Transaction::set_request_hostname()`].
+    ///
+    /// ## Examples
+    ///
+    /// ```
+    /// use modsecurity::{ModSecurity, Rules};
+    ///
+    /// let ms = ModSecurity::default();
+    /// let rules = Rules::new();
+    ///
+    /// let transaction = ms
+    ///     .transaction_builder()
+    ///     .with_rules(&rules)
+    ///     .with_hostname("example.com")
+    ///     .build()
+    ///     .expect("error building transaction");
+    /// ```
+    pub fn with_hostname(mut self, hostname: &str) -> Self {
+        self.hostname = Some(hostname.to_string());
         self
     }
 
     /// Creates the configured transaction.
     pub fn build(self) -> ModSecurityResult<Transaction<'a, B>> {
-        let transaction = Transaction::new(self.ms, self.rules, self.id, self.log_cb);
-        transaction
+        let mut transaction =
+            Transaction::new(self.ms, self.rules, self.id.as_deref(), self.log_cb)?;
+
+        if let Some(hostname) = self.hostname {
+            transaction.set_request_hostname(&hostname)?;
+        }
+
+        Ok(transaction)
+    }
+
+    /// Creates the configured transaction, wrapped in a [`PhasedTransaction`] that enforces the
+    /// order in which phases are run.
+    ///
+    /// See [`crate::phased`] for more information.
+    pub fn build_phased(self) -> ModSecurityResult<PhasedTransaction<'a, state::Connection, B>> {
+        Ok(PhasedTransaction::new(self.build()?))
     }
 }
 
 /// The type of the logging callback that can be set on a [`Transaction`].
 pub type LogCallback = Box<dyn Fn(Option<&str>) + Send + Sync + 'static>;
 
+/// The type of the logging callback that receives the raw bytes of log messages. See
+/// [`TransactionBuilder::with_logging_bytes()`].
+pub type BytesLogCallback = Box<dyn Fn(Option<&[u8]>) + Send + Sync + 'static>;
+
+/// A point in a transaction at which an intervention can be raised.
+///
+/// Each variant corresponds to one of the `process_*` methods of [`Transaction`].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Phase {
+    /// See [`Transaction::process_connection()`].
+    Connection,
+    /// See [`Transaction::process_uri()`].
+    Uri,
+    /// See [`Transaction::process_request_headers()`].
+    RequestHeaders,
+    /// See [`Transaction::process_request_body()`].
+    RequestBody,
+    /// See [`Transaction::process_response_headers()`].
+    ResponseHeaders,
+    /// See [`Transaction::process_response_body()`].
+    ResponseBody,
+    /// See [`Transaction::process_logging()`].
+    Logging,
+}
+
+/// A transaction that owns (shared) references to the [`ModSecurity`] and [`Rules`] instances it
+/// was created from, and so is not bound to their lifetimes.
+///
+/// ## Examples
+///
+/// ```
+/// use std::sync::Arc;
+///
+/// use modsecurity::{ModSecurity, OwnedTransaction, Rules};
+///
+/// let ms = Arc::new(ModSecurity::default());
+/// let rules = Arc::new(Rules::new());
+///
+/// let transaction: OwnedTransaction = ms
+///     .shared_transaction_builder()
+///     .with_shared_rules(Arc::clone(&rules))
+///     .build()
+///     .expect("Error building transaction");
+///
+/// std::thread::spawn(move || {
+///     let mut transaction = transaction;
+///     transaction.process_uri("/", "GET", "1.1").expect("Error processing URI");
+/// })
+/// .join()
+/// .unwrap();
+/// ```
+pub type OwnedTransaction<B = Bindings> = Transaction<'static, B>;
+
 /// A ModSecurity transaction.
 ///
 /// A transaction represents the inspection on an entire request and response cycle.
+///
+/// With the `tracing` feature, each transaction is recorded in a `transaction` span. Events are
+/// emitted within it for each `process_*` call, with its duration and result, for each log
+/// message, and for each intervention returned by [`Transaction::intervention()`].
 pub struct Transaction<'a, B: RawBindings = Bindings> {
     inner: *mut Transaction_t,
-    /// This field ensures that the lifetime of `Transaction` is tied to the `ModSecurity` and `Rules`
-    /// instances that it was created from. We pack in B and 'a here even though they are unreleated.
-    _phantom: PhantomData<&'a B>,
+    /// These fields ensure that the `ModSecurity` and `Rules` instances that the transaction was
+    /// created from outlive it, either through the `'a` lifetime or by holding a shared reference.
+    /// They are only dropped after `msc_transaction_cleanup` has been called.
+    _ms: Handle<'a, ModSecurity<B>>,
+    _rules: Handle<'a, Rules<B>>,
     /// We store the callback here to ensure it's kept alive for the lifetime of the `Transaction`
     /// instance. Along with the lifetime constraints on this struct, this ensures that the callback
     /// can be safely invoked.
-    _log_cb: Option<Box<LogCallback>>,
+    _log_cb: Option<Box<BytesLogCallback>>,
     /// Optional explicit transaction ID
     _id: Option<*mut c_char>,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
 }
 
 unsafe impl Send for Transaction<'_, Bindings> {}
@@ -152,13 +376,42 @@ macro_rules! msc_result {
     };
 }
 
+/// Returns the raw bytes of a path, without going through UTF-8 where the platform allows it.
+///
+/// Returns `None` if the path cannot be represented as bytes on this platform.
+fn path_as_bytes(path: &Path) -> Option<&[u8]> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Some(path.as_os_str().as_bytes())
+    }
+
+    #[cfg(not(unix))]
+    {
+        path.to_str().map(str::as_bytes)
+    }
+}
+
 impl<'a, B: RawBindings> Transaction<'a, B> {
-    pub(crate) fn new(
-        ms: &'a ModSecurity<B>,
-        rules: &'a Rules<B>,
+    fn new(
+        ms: Handle<'a, ModSecurity<B>>,
+        rules: Handle<'a, Rules<B>>,
         id: Option<&str>,
-        log_cb: Option<LogCallback>,
+        log_cb: Option<BytesLogCallback>,
     ) -> ModSecurityResult<Self> {
+        #[cfg(feature = "log")]
+        let log_cb = log_cb.or_else(|| Some(crate::logging::log_callback()));
+
+        // Transactions without an explicit ID are only given a generated one on their span, so
+        // libmodsecurity still generates its own.
+        #[cfg(feature = "tracing")]
+        let span = match id {
+            Some(id) => crate::trace::transaction_span(id),
+            None => crate::trace::transaction_span(&crate::trace::generate_id()),
+        };
+        #[cfg(feature = "tracing")]
+        let log_cb = Some(crate::trace::log_callback(log_cb));
+
         // NOTE: The double indirection is required here as `Box<dyn Trait>` is a fat pointer and
         // we must be able to convert to it from `*mut c_void`
         let log_cb = log_cb.map(|cb| Box::new(cb));
@@ -188,11 +441,41 @@ impl<'a, B: RawBindings> Transaction<'a, B> {
         Ok(Self {
             inner: msc_transaction,
             _log_cb: log_cb,
-            _phantom: PhantomData,
+            _ms: ms,
+            _rules: rules,
             _id: maybe_id,
+            #[cfg(feature = "tracing")]
+            span,
         })
     }
 
+    /// Runs `f`, which processes `phase`.
+    ///
+    /// With the `tracing` feature, `f` runs in the span of the transaction and an event is
+    /// emitted with its duration and result.
+    fn phase<F>(&mut self, phase: Phase, f: F) -> ModSecurityResult<()>
+    where
+        F: FnOnce(&mut Self) -> ModSecurityResult<()>,
+    {
+        #[cfg(feature = "tracing")]
+        {
+            let span = self.span.clone();
+            let _enter = span.enter();
+            let start = std::time::Instant::now();
+
+            let result = f(self);
+            crate::trace::phase_event(phase, start.elapsed(), &result);
+
+            result
+        }
+
+        #[cfg(not(feature = "tracing"))]
+        {
+            let _ = phase;
+            f(self)
+        }
+    }
+
     /// Processes rules in the logging phase for this transaction.
     ///
     /// At this point there is not need to hold the connection, the response can be
@@ -232,9 +515,11 @@ impl<'a, B: RawBindings> Transaction<'a, B> {
     /// assert!(transaction.intervention().is_some());
     /// ```
     pub fn process_logging(&mut self) -> ModSecurityResult<()> {
-        let result = unsafe { B::msc_process_logging(self.inner) };
+        self.phase(Phase::Logging, |transaction| {
+            let result = unsafe { B::msc_process_logging(transaction.inner) };
 
-        msc_result!(result, ModSecurityError::ProcessLogging, ())
+            msc_result!(result, ModSecurityError::ProcessLogging, ())
+        })
     }
 
     /// Performs analysis on the connection.
@@ -284,14 +569,22 @@ impl<'a, B: RawBindings> Transaction<'a, B> {
         server: &str,
         s_port: i32,
     ) -> ModSecurityResult<()> {
-        let client = CString::new(client)?;
-        let server = CString::new(server)?;
-
-        let result = unsafe {
-            B::msc_process_connection(self.inner, client.as_ptr(), c_port, server.as_ptr(), s_port)
-        };
+        self.phase(Phase::Connection, |transaction| {
+            let client = CString::new(client)?;
+            let server = CString::new(server)?;
+
+            let result = unsafe {
+                B::msc_process_connection(
+                    transaction.inner,
+                    client.as_ptr(),
+                    c_port,
+                    server.as_ptr(),
+                    s_port,
+                )
+            };
 
-        msc_result!(result, ModSecurityError::ProcessConnection, ())
+            msc_result!(result, ModSecurityError::ProcessConnection, ())
+        })
     }
 
     /// Perform the analysis on the URI and all the query string variables.
@@ -342,20 +635,63 @@ impl<'a, B: RawBindings> Transaction<'a, B> {
         method: &str,
         http_version: &str,
     ) -> ModSecurityResult<()> {
-        let uri = CString::new(uri)?;
-        let protocol = CString::new(method)?;
-        let http_version = CString::new(http_version)?;
+        self.phase(Phase::Uri, |transaction| {
+            let uri = CString::new(uri)?;
+            let protocol = CString::new(method)?;
+            let http_version = CString::new(http_version)?;
+
+            let result = unsafe {
+                B::msc_process_uri(
+                    transaction.inner,
+                    uri.as_ptr(),
+                    protocol.as_ptr(),
+                    http_version.as_ptr(),
+                )
+            };
 
-        let result = unsafe {
-            B::msc_process_uri(
-                self.inner,
-                uri.as_ptr(),
-                protocol.as_ptr(),
-                http_version.as_ptr(),
-            )
-        };
+            msc_result!(result, ModSecurityError::ProcessUri, ())
+        })
+    }
+
+    /// Sets the hostname of the request.
+    ///
+    /// The hostname is exposed to rules through the `SERVER_NAME` variable. This is useful when
+    /// serving multiple virtual hosts, and should be called before
+    /// [`Transaction::process_request_headers()`] so that it is visible to rules in phase 1.
+    ///
+    /// ## Examples
+    ///
+    /// ```
+    /// use modsecurity::{ModSecurity, Rules};
+    ///
+    /// let ms = ModSecurity::default();
+    /// let mut rules = Rules::new();
+    ///
+    /// rules.add_plain(r#"
+    ///     SecRuleEngine On
+    ///
+    ///     SecRule SERVER_NAME "@streq admin.example.com" "id:1,phase:1,t:none,deny,status:403"
+    /// "#).expect("Error adding rule set");
+    ///
+    /// let mut transaction = ms
+    ///     .transaction_builder()
+    ///     .with_rules(&rules)
+    ///     .build()
+    ///     .expect("Error building transaction");
+    ///
+    /// transaction.set_request_hostname("admin.example.com").expect("Error setting hostname");
+    /// transaction.process_uri("/", "GET", "1.1").expect("Error processing URI");
+    /// transaction.process_request_headers().expect("Error processing request headers");
+    ///
+    /// assert!(transaction.intervention().is_some());
+    /// ```
+    pub fn set_request_hostname(&mut self, hostname: &str) -> ModSecurityResult<()> {
+        let hostname = CString::new(hostname)?;
+
+        let result =
+            unsafe { B::msc_set_request_hostname(self.inner, hostname.as_ptr() as *const c_uchar) };
 
-        msc_result!(result, ModSecurityError::ProcessUri, ())
+        msc_result!(result, ModSecurityError::SetRequestHostname, ())
     }
 
     /// Appends a request body to the transaction.
@@ -372,6 +708,38 @@ impl<'a, B: RawBindings> Transaction<'a, B> {
         msc_result!(result, ModSecurityError::AppendResponseBody, ())
     }
 
+    /// Reads the request body from a file.
+    ///
+    /// This can be used in place of [`Transaction::append_request_body()`] when the body has
+    /// already been spooled to disk, so that the caller does not need to read the file. The
+    /// file is read by ModSecurity itself.
+    ///
+    /// ## Examples
+    ///
+    /// ```no_run
+    /// use modsecurity::{ModSecurity, Rules};
+    ///
+    /// let ms = ModSecurity::default();
+    /// let rules = Rules::new();
+    ///
+    /// let mut transaction = ms
+    ///     .transaction_builder()
+    ///     .with_rules(&rules)
+    ///     .build()
+    ///     .expect("Error building transaction");
+    ///
+    /// transaction.request_body_from_file("/path/to/body").expect("Error reading request body");
+    /// transaction.process_request_body().expect("Error processing request body");
+    /// ```
+    pub fn request_body_from_file<P: AsRef<Path>>(&mut self, path: P) -> ModSecurityResult<()> {
+        let path = path_as_bytes(path.as_ref()).ok_or(ModSecurityError::RequestBodyFromFile)?;
+        let path = CString::new(path)?;
+
+        let result = unsafe { B::msc_request_body_from_file(self.inner, path.as_ptr()) };
+
+        msc_result!(result, ModSecurityError::RequestBodyFromFile, ())
+    }
+
     /// Processes rules in the request body phase for this transaction.
     ///
     /// **NOTE**: Remember to check for a possible intervention using [`Transaction::intervention()`]
@@ -403,9 +771,11 @@ impl<'a, B: RawBindings> Transaction<'a, B> {
     /// assert!(transaction.intervention().is_some());
     /// ```
     pub fn process_request_body(&mut self) -> ModSecurityResult<()> {
-        let result = unsafe { B::msc_process_request_body(self.inner) };
+        self.phase(Phase::RequestBody, |transaction| {
+            let result = unsafe { B::msc_process_request_body(transaction.inner) };
 
-        msc_result!(result, ModSecurityError::ProcessRequestBody, ())
+            msc_result!(result, ModSecurityError::ProcessRequestBody, ())
+        })
     }
 
     /// Processes rules in the response body phase for this transaction.
@@ -441,9 +811,11 @@ impl<'a, B: RawBindings> Transaction<'a, B> {
     /// assert!(transaction.intervention().is_some());
     /// ```
     pub fn process_response_body(&mut self) -> ModSecurityResult<()> {
-        let result = unsafe { B::msc_process_response_body(self.inner) };
+        self.phase(Phase::ResponseBody, |transaction| {
+            let result = unsafe { B::msc_process_response_body(transaction.inner) };
 
-        msc_result!(result, ModSecurityError::ProcessResponseBody, ())
+            msc_result!(result, ModSecurityError::ProcessResponseBody, ())
+        })
     }
 
     /// Processes rules in the request headers phase for this transaction.
@@ -484,9 +856,11 @@ impl<'a, B: RawBindings> Transaction<'a, B> {
     /// assert!(transaction.intervention().is_some());
     /// ```
     pub fn process_request_headers(&mut self) -> ModSecurityResult<()> {
-        let result = unsafe { B::msc_process_request_headers(self.inner) };
+        self.phase(Phase::RequestHeaders, |transaction| {
+            let result = unsafe { B::msc_process_request_headers(transaction.inner) };
 
-        msc_result!(result, ModSecurityError::ProcessRequestHeaders, ())
+            msc_result!(result, ModSecurityError::ProcessRequestHeaders, ())
+        })
     }
 
     /// Processes rules in the response headers phase for this transaction.
@@ -527,24 +901,99 @@ impl<'a, B: RawBindings> Transaction<'a, B> {
     /// assert!(transaction.intervention().is_some());
     /// ```
     pub fn process_response_headers(&mut self, code: i32, protocol: &str) -> ModSecurityResult<()> {
-        let protocol = CString::new(protocol)?;
+        self.phase(Phase::ResponseHeaders, |transaction| {
+            let protocol = CString::new(protocol)?;
 
-        let result =
-            unsafe { B::msc_process_response_headers(self.inner, code, protocol.as_ptr()) };
+            let result = unsafe {
+                B::msc_process_response_headers(transaction.inner, code, protocol.as_ptr())
+            };
+
+            msc_result!(result, ModSecurityError::ProcessResponseHeaders, ())
+        })
+    }
+
+    /// Updates the response status code for this transaction.
+    ///
+    /// The status code is normally supplied through [`Transaction::process_response_headers()`].
+    /// If the status changes after that call (e.g. an upstream error replaced the response),
+    /// this method should be used to inform ModSecurity so that the remaining phases and the
+    /// audit log see the status code that is actually being sent to the client.
+    ///
+    /// This method has no effect if called before [`Transaction::process_response_headers()`]
+    /// as that call sets the status code itself.
+    ///
+    /// ## Examples
+    ///
+    /// ```
+    /// use modsecurity::{ModSecurity, Rules};
+    ///
+    /// let ms = ModSecurity::default();
+    /// let mut rules = Rules::new();
+    ///
+    /// rules.add_plain(r#"
+    ///    SecRuleEngine On
+    ///
+    ///    SecResponseBodyAccess On
+    ///
+    ///    SecRule RESPONSE_STATUS "@streq 502" "phase:4,id:'1',t:none,deny"
+    /// "#).expect("Error adding rule set");
+    ///
+    /// let mut transaction = ms
+    ///     .transaction_builder()
+    ///     .with_rules(&rules)
+    ///     .build()
+    ///     .expect("Error building transaction");
+    ///
+    /// transaction.process_response_headers(200, "HTTP 1.1").expect("Error processing response headers");
+    /// transaction.update_status_code(502).expect("Error updating status code");
+    /// transaction.process_response_body().expect("Error processing response body");
+    ///
+    /// assert!(transaction.intervention().is_some());
+    /// ```
+    pub fn update_status_code(&mut self, status: i32) -> ModSecurityResult<()> {
+        let result = unsafe { B::msc_update_status_code(self.inner, status) };
 
-        msc_result!(result, ModSecurityError::ProcessResponseHeaders, ())
+        msc_result!(result, ModSecurityError::UpdateStatusCode, ())
     }
 
     /// Adds a request header to the transaction.
+    ///
+    /// This is a convenience wrapper around [`Transaction::add_request_header_bytes()`].
     pub fn add_request_header(&mut self, key: &str, value: &str) -> ModSecurityResult<()> {
-        let key = CString::new(key)?;
-        let value = CString::new(value)?;
+        self.add_request_header_bytes(key.as_bytes(), value.as_bytes())
+    }
 
+    /// Adds a request header to the transaction from raw bytes.
+    ///
+    /// Unlike [`Transaction::add_request_header()`], the key and value do not need to be valid
+    /// UTF-8 and may contain NUL bytes. They are passed to ModSecurity exactly as received.
+    ///
+    /// ## Examples
+    ///
+    /// ```
+    /// use modsecurity::{ModSecurity, Rules};
+    ///
+    /// let ms = ModSecurity::default();
+    /// let rules = Rules::new();
+    ///
+    /// let mut transaction = ms
+    ///     .transaction_builder()
+    ///     .with_rules(&rules)
+    ///     .build()
+    ///     .expect("Error building transaction");
+    ///
+    /// transaction
+    ///     .add_request_header_bytes(b"X-Raw", b"caf\xe9\0")
+    ///     .expect("Error adding request header");
+    /// ```
+    pub fn add_request_header_bytes(&mut self, key: &[u8], value: &[u8]) -> ModSecurityResult<()> {
         let result = unsafe {
-            B::msc_add_request_header(
+            B::msc_add_n_request_header(
                 self.inner,
-                key.as_ptr() as *const c_uchar,
-                value.as_ptr() as *const c_uchar,
+                key.as_ptr(),
+                key.len(),
+                value.as_ptr(),
+                value.len(),
             )
         };
 
@@ -552,15 +1001,24 @@ impl<'a, B: RawBindings> Transaction<'a, B> {
     }
 
     /// Adds a response header to the transaction.
+    ///
+    /// This is a convenience wrapper around [`Transaction::add_response_header_bytes()`].
     pub fn add_response_header(&mut self, key: &str, value: &str) -> ModSecurityResult<()> {
-        let key = CString::new(key)?;
-        let value = CString::new(value)?;
+        self.add_response_header_bytes(key.as_bytes(), value.as_bytes())
+    }
 
+    /// Adds a response header to the transaction from raw bytes.
+    ///
+    /// Unlike [`Transaction::add_response_header()`], the key and value do not need to be valid
+    /// UTF-8 and may contain NUL bytes. They are passed to ModSecurity exactly as received.
+    pub fn add_response_header_bytes(&mut self, key: &[u8], value: &[u8]) -> ModSecurityResult<()> {
         let result = unsafe {
-            B::msc_add_response_header(
+            B::msc_add_n_response_header(
                 self.inner,
-                key.as_ptr() as *const c_uchar,
-                value.as_ptr() as *const c_uchar,
+                key.as_ptr(),
+                key.len(),
+                value.as_ptr(),
+                value.len(),
             )
         };
 
@@ -610,7 +1068,12 @@ impl<'a, B: RawBindings> Transaction<'a, B> {
         let result = unsafe { B::msc_intervention(self.inner, &mut intervention) };
 
         if result > 0 {
-            Some(Intervention::<B>::new(intervention))
+            let intervention = Intervention::<B>::new(intervention);
+
+            #[cfg(feature = "tracing")]
+            crate::trace::intervention_event(&self.span, &intervention);
+
+            Some(intervention)
         } else {
             None
         }
@@ -625,15 +1088,80 @@ impl<'a, B: RawBindings> Transaction<'a, B> {
     pub fn get_response_body_length(&mut self) -> usize {
         unsafe { B::msc_get_response_body_length(self.inner) }
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use std::sync::{atomic::AtomicBool, Arc};
-
-    use crate::{msc::ModSecurity, rules::Rules, ModSecurityError};
-
-    pub struct TestBindings;
+
+    /// Returns a copy of the response body held by ModSecurity, or `None` if it contains a NUL
+    /// byte, so binary bodies (e.g. images or gzip) are not returned.
+    ///
+    /// libmodsecurity returns the body as a NUL-terminated copy, so a body containing a NUL byte
+    /// cannot be retrieved in full, and `None` is returned rather than a truncated body. The
+    /// returned body is empty if no response body is available.
+    ///
+    /// This is the content passed to [`Transaction::append_response_body()`], as libmodsecurity
+    /// v3 does not rewrite response bodies (its `@rsub` operator is not implemented). It should
+    /// be called after [`Transaction::process_response_body()`].
+    ///
+    /// ## Examples
+    ///
+    /// ```
+    /// use modsecurity::{ModSecurity, Rules};
+    ///
+    /// let ms = ModSecurity::default();
+    /// let mut rules = Rules::new();
+    ///
+    /// rules.add_plain(r#"
+    ///    SecRuleEngine On
+    ///
+    ///    SecResponseBodyAccess On
+    /// "#).expect("Error adding rule set");
+    ///
+    /// let mut transaction = ms
+    ///     .transaction_builder()
+    ///     .with_rules(&rules)
+    ///     .build()
+    ///     .expect("Error building transaction");
+    ///
+    /// transaction.append_response_body(b"hello").expect("Error appending response body");
+    /// transaction.process_response_body().expect("Error processing response body");
+    ///
+    /// assert_eq!(transaction.get_response_body(), Some(b"hello".to_vec()));
+    /// ```
+    pub fn get_response_body(&self) -> Option<Vec<u8>> {
+        unsafe {
+            let len = B::msc_get_response_body_length(self.inner);
+            let body = B::msc_get_response_body(self.inner);
+
+            if body.is_null() {
+                return if len == 0 { Some(Vec::new()) } else { None };
+            }
+
+            // The body is a copy owned by the caller, which is only valid up to its terminating
+            // NUL byte even if the body held by ModSecurity is longer.
+            let bytes = CStr::from_ptr(body).to_bytes();
+            let copy = if bytes.len() < len {
+                None
+            } else {
+                Some(bytes[..len].to_vec())
+            };
+
+            B::free(body as *mut c_void);
+
+            copy
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        io::Write,
+        sync::{atomic::AtomicBool, Arc},
+    };
+
+    use tempfile::NamedTempFile;
+
+    use crate::{msc::ModSecurity, rules::Rules, ModSecurityError};
+
+    pub struct TestBindings;
 
     #[cfg(not(miri))]
     impl crate::bindings::RawBindings for TestBindings {}
@@ -669,6 +1197,14 @@ mod tests {
             1
         }
 
+        unsafe fn msc_get_response_body(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+        ) -> *const std::os::raw::c_char {
+            "t\0".as_ptr() as *const std::os::raw::c_char
+        }
+
+        unsafe fn free(_ptr: *mut std::ffi::c_void) {}
+
         unsafe fn msc_init() -> *mut modsecurity_sys::ModSecurity {
             std::ptr::null_mut()
         }
@@ -799,18 +1335,43 @@ mod tests {
             1
         }
 
-        unsafe fn msc_add_request_header(
+        unsafe fn msc_update_status_code(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+            _status: i32,
+        ) -> i32 {
+            1
+        }
+
+        unsafe fn msc_set_request_hostname(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+            _hostname: *const std::os::raw::c_uchar,
+        ) -> i32 {
+            1
+        }
+
+        unsafe fn msc_request_body_from_file(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+            _path: *const std::os::raw::c_char,
+        ) -> i32 {
+            1
+        }
+
+        unsafe fn msc_add_n_request_header(
             _transaction: *mut crate::bindings::types::Transaction_t,
             _key: *const std::os::raw::c_uchar,
+            _len_key: usize,
             _value: *const std::os::raw::c_uchar,
+            _len_value: usize,
         ) -> i32 {
             1
         }
 
-        unsafe fn msc_add_response_header(
+        unsafe fn msc_add_n_response_header(
             _transaction: *mut crate::bindings::types::Transaction_t,
             _key: *const std::os::raw::c_uchar,
+            _len_key: usize,
             _value: *const std::os::raw::c_uchar,
+            _len_value: usize,
         ) -> i32 {
             1
         }
@@ -857,6 +1418,305 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_with_structured_logging() {
+        let ms = ModSecurity::<TestBindings>::builder()
+            .with_log_callbacks()
+            .build();
+        let mut rules = Rules::new();
+        rules
+            .add_plain(
+                r#"
+                SecRuleEngine DetectionOnly
+
+                SecRule REQUEST_URI "test" "phase:1,id:'1',t:none,log,deny,status:403,msg:'Access denied',severity:'CRITICAL',tag:'a',tag:'b'"
+            "#,
+            )
+            .unwrap();
+
+        let matches = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let mut transaction = ms
+            .transaction_builder()
+            .with_rules(&rules)
+            .with_structured_logging({
+                let matches = Arc::clone(&matches);
+                move |rule_match| {
+                    matches.lock().unwrap().push(rule_match.clone());
+                }
+            })
+            .build()
+            .unwrap();
+
+        transaction.process_uri("/test", "GET", "1.1").unwrap();
+        transaction.process_request_headers().unwrap();
+
+        #[cfg(not(miri))]
+        {
+            let matches = matches.lock().unwrap();
+            assert_eq!(matches.len(), 1);
+            assert_eq!(matches[0].id, Some(1));
+            assert_eq!(matches[0].msg.as_deref(), Some("Access denied"));
+            assert_eq!(
+                matches[0].severity,
+                Some(crate::rule_match::Severity::Critical)
+            );
+            assert_eq!(matches[0].tags, vec!["a", "b"]);
+        }
+    }
+
+    #[test]
+    fn test_with_structured_logging_emergency() {
+        let ms = ModSecurity::<TestBindings>::builder()
+            .with_log_callbacks()
+            .build();
+        let mut rules = Rules::new();
+        rules
+            .add_plain(
+                r#"
+                SecRuleEngine DetectionOnly
+
+                SecRule REQUEST_URI "test" "phase:1,id:'1',t:none,log,pass,severity:'EMERGENCY'"
+            "#,
+            )
+            .unwrap();
+
+        let matches = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let mut transaction = ms
+            .transaction_builder()
+            .with_rules(&rules)
+            .with_structured_logging({
+                let matches = Arc::clone(&matches);
+                move |rule_match| {
+                    matches.lock().unwrap().push(rule_match.clone());
+                }
+            })
+            .build()
+            .unwrap();
+
+        transaction.process_uri("/test", "GET", "1.1").unwrap();
+        transaction.process_request_headers().unwrap();
+
+        #[cfg(not(miri))]
+        {
+            let matches = matches.lock().unwrap();
+            assert_eq!(matches.len(), 1);
+            assert_eq!(matches[0].id, Some(1));
+            assert_eq!(
+                matches[0].severity,
+                Some(crate::rule_match::Severity::Emergency)
+            );
+        }
+    }
+
+    /// Bindings that emit [`LogBindings::MESSAGE`], which is not valid UTF-8, to the log
+    /// callback when the request headers are processed, and always raise an intervention.
+    pub(crate) struct LogBindings;
+
+    std::thread_local! {
+        static LOG_CB: std::cell::Cell<(modsecurity_sys::ModSecLogCb, *mut std::ffi::c_void)> =
+            const { std::cell::Cell::new((None, std::ptr::null_mut())) };
+    }
+
+    impl LogBindings {
+        pub(crate) const MESSAGE: &'static [u8] =
+            b"ModSecurity: Warning. Matched \xe9t\xe9 [id \"7\"]";
+        pub(crate) const INTERVENTION_LOG: &'static [u8] =
+            b"ModSecurity: Access denied with code 403 (phase 1). [id \"5\"] [severity \"2\"]\0";
+    }
+
+    impl crate::bindings::RawBindings for LogBindings {
+        unsafe fn msc_init() -> *mut modsecurity_sys::ModSecurity {
+            std::ptr::null_mut()
+        }
+
+        unsafe fn msc_set_connector_info(
+            _: *mut modsecurity_sys::ModSecurity,
+            _: *const std::os::raw::c_char,
+        ) {
+        }
+
+        unsafe fn msc_set_log_cb(
+            _: *mut modsecurity_sys::ModSecurity,
+            cb: modsecurity_sys::ModSecLogCb,
+        ) {
+            LOG_CB.with(|log_cb| log_cb.set((cb, std::ptr::null_mut())));
+        }
+
+        unsafe fn msc_cleanup(_: *mut modsecurity_sys::ModSecurity) {}
+
+        unsafe fn msc_create_rules_set() -> *mut crate::bindings::types::Rules_t {
+            std::ptr::null_mut()
+        }
+
+        unsafe fn msc_rules_cleanup(
+            _: *mut crate::bindings::types::Rules_t,
+        ) -> std::os::raw::c_int {
+            1
+        }
+
+        unsafe fn msc_new_transaction(
+            _msc: *mut modsecurity_sys::ModSecurity,
+            _rules: *mut modsecurity_sys::RulesSet,
+            log_cb: *mut std::ffi::c_void,
+        ) -> *mut crate::bindings::types::Transaction_t {
+            LOG_CB.with(|cb| cb.set((cb.get().0, log_cb)));
+            std::ptr::null_mut()
+        }
+
+        unsafe fn msc_new_transaction_with_id(
+            msc: *mut modsecurity_sys::ModSecurity,
+            rules: *mut modsecurity_sys::RulesSet,
+            _id: *mut std::os::raw::c_char,
+            log_cb: *mut std::ffi::c_void,
+        ) -> *mut crate::bindings::types::Transaction_t {
+            Self::msc_new_transaction(msc, rules, log_cb)
+        }
+
+        unsafe fn msc_transaction_cleanup(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+        ) {
+        }
+
+        unsafe fn msc_process_request_headers(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+        ) -> i32 {
+            let (cb, data) = LOG_CB.with(|cb| cb.get());
+            let msg = std::ffi::CString::new(LogBindings::MESSAGE).unwrap();
+
+            if let Some(cb) = cb {
+                cb(data, msg.as_ptr() as *const std::ffi::c_void);
+                cb(data, std::ptr::null());
+            }
+
+            1
+        }
+
+        unsafe fn msc_intervention(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+            intervention: *mut crate::bindings::types::ModSecurityIntervention_t,
+        ) -> i32 {
+            (*intervention).status = 403;
+            (*intervention).disruptive = 1;
+            (*intervention).log =
+                LogBindings::INTERVENTION_LOG.as_ptr() as *mut std::os::raw::c_char;
+            1
+        }
+
+        unsafe fn msc_intervention_cleanup(
+            _: *mut crate::bindings::types::ModSecurityIntervention_t,
+        ) {
+        }
+    }
+
+    fn log_transaction<'a>(
+        ms: &'a ModSecurity<LogBindings>,
+        rules: &'a Rules<LogBindings>,
+        configure: impl FnOnce(
+            super::TransactionBuilder<'a, LogBindings>,
+        ) -> super::TransactionBuilder<'a, LogBindings>,
+    ) -> super::Transaction<'a, LogBindings> {
+        configure(ms.transaction_builder().with_rules(rules))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_logging_invalid_utf8() {
+        let ms = ModSecurity::<LogBindings>::builder()
+            .with_log_callbacks()
+            .build();
+        let rules = Rules::new();
+
+        let messages = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let mut transaction = log_transaction(&ms, &rules, |builder| {
+            let messages = Arc::clone(&messages);
+            builder.with_logging(move |msg| {
+                messages.lock().unwrap().push(msg.map(str::to_string));
+            })
+        });
+
+        transaction.process_request_headers().unwrap();
+
+        assert_eq!(
+            *messages.lock().unwrap(),
+            vec![
+                Some("ModSecurity: Warning. Matched \u{fffd}t\u{fffd} [id \"7\"]".to_string()),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn test_logging_bytes() {
+        let ms = ModSecurity::<LogBindings>::builder()
+            .with_log_callbacks()
+            .build();
+        let rules = Rules::new();
+
+        let messages = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let mut transaction = log_transaction(&ms, &rules, |builder| {
+            let messages = Arc::clone(&messages);
+            builder.with_logging_bytes(move |msg| {
+                messages.lock().unwrap().push(msg.map(<[u8]>::to_vec));
+            })
+        });
+
+        transaction.process_request_headers().unwrap();
+
+        assert_eq!(
+            *messages.lock().unwrap(),
+            vec![Some(LogBindings::MESSAGE.to_vec()), None]
+        );
+    }
+
+    #[test]
+    fn test_structured_logging_invalid_utf8() {
+        let ms = ModSecurity::<LogBindings>::builder()
+            .with_log_callbacks()
+            .build();
+        let rules = Rules::new();
+
+        let ids = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let mut transaction = log_transaction(&ms, &rules, |builder| {
+            let ids = Arc::clone(&ids);
+            builder.with_structured_logging(move |rule_match| {
+                ids.lock().unwrap().push(rule_match.id);
+            })
+        });
+
+        transaction.process_request_headers().unwrap();
+
+        assert_eq!(*ids.lock().unwrap(), vec![Some(7)]);
+    }
+
+    #[test]
+    fn test_logging_callback_panic() {
+        let ms = ModSecurity::<LogBindings>::builder()
+            .with_log_callbacks()
+            .build();
+        let rules = Rules::new();
+
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+        let mut transaction = log_transaction(&ms, &rules, |builder| {
+            let calls = Arc::clone(&calls);
+            builder.with_logging(move |_| {
+                calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                panic!("callback panicked");
+            })
+        });
+
+        // The panics must not unwind into the bindings, and each message is still delivered.
+        assert!(transaction.process_request_headers().is_ok());
+        assert!(transaction.process_request_headers().is_ok());
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 4);
+    }
+
     #[test]
     fn test_logging_enabled_without_callback() {
         let ms = ModSecurity::<TestBindings>::builder()
@@ -1006,6 +1866,69 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_request_body_from_file() {
+        let ms = ModSecurity::<TestBindings>::builder()
+            .with_log_callbacks()
+            .build();
+        let mut rules = Rules::new();
+        rules
+            .add_plain(
+                r#"
+                SecRuleEngine On
+
+                SecRequestBodyAccess On
+
+                SecRule REQUEST_BODY "@rx test" "phase:2,id:'1',t:none,deny"
+            "#,
+            )
+            .unwrap();
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.as_file_mut().write_all(b"test").unwrap();
+
+        let mut transaction = ms.transaction_builder().with_rules(&rules).build().unwrap();
+
+        transaction.request_body_from_file(file.path()).unwrap();
+        transaction.process_request_headers().unwrap();
+        transaction.process_request_body().unwrap();
+
+        #[cfg(not(miri))]
+        assert!(transaction.intervention().is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_request_body_from_file_non_utf8_path() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let ms = ModSecurity::<TestBindings>::builder()
+            .with_log_callbacks()
+            .build();
+        let rules = Rules::new();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(OsStr::from_bytes(b"body-\xff"));
+        std::fs::write(&path, b"test").unwrap();
+
+        let mut transaction = ms.transaction_builder().with_rules(&rules).build().unwrap();
+
+        assert!(transaction.request_body_from_file(&path).is_ok());
+    }
+
+    #[test]
+    fn test_request_body_from_file_nul() {
+        let ms = ModSecurity::<TestBindings>::default();
+        let rules = Rules::new();
+
+        let mut transaction = ms.transaction_builder().with_rules(&rules).build().unwrap();
+
+        assert!(matches!(
+            transaction.request_body_from_file("invalid\0path"),
+            Err(ModSecurityError::Nul(_))
+        ));
+    }
+
     #[test]
     fn test_response_body() {
         let ms = ModSecurity::<TestBindings>::builder()
@@ -1036,6 +1959,125 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_get_response_body() {
+        let ms = ModSecurity::<TestBindings>::builder()
+            .with_log_callbacks()
+            .build();
+        let mut rules = Rules::new();
+        rules
+            .add_plain(
+                r#"
+                SecRuleEngine On
+
+                SecResponseBodyAccess On
+            "#,
+            )
+            .unwrap();
+
+        let mut transaction = ms.transaction_builder().with_rules(&rules).build().unwrap();
+
+        transaction.append_response_body("test".as_bytes()).unwrap();
+        transaction.process_response_body().unwrap();
+
+        #[cfg(not(miri))]
+        assert_eq!(transaction.get_response_body(), Some(b"test".to_vec()));
+        #[cfg(miri)]
+        assert_eq!(transaction.get_response_body(), Some(b"t".to_vec()));
+    }
+
+    #[test]
+    fn test_get_response_body_null() {
+        let ms = ModSecurity::<FallibleBindings>::default();
+        let rules = Rules::new();
+
+        let transaction = ms.transaction_builder().with_rules(&rules).build().unwrap();
+
+        assert_eq!(transaction.get_response_body(), Some(Vec::new()));
+    }
+
+    /// Bindings that hold a response body containing a NUL byte, and count how many times it is
+    /// freed.
+    struct ResponseBodyBindings;
+
+    static RESPONSE_BODY_FREED: std::sync::atomic::AtomicUsize =
+        std::sync::atomic::AtomicUsize::new(0);
+
+    impl ResponseBodyBindings {
+        const BODY: &'static [u8] = b"te\0st\0";
+    }
+
+    impl crate::bindings::RawBindings for ResponseBodyBindings {
+        unsafe fn msc_init() -> *mut modsecurity_sys::ModSecurity {
+            std::ptr::null_mut()
+        }
+
+        unsafe fn msc_set_connector_info(
+            _: *mut modsecurity_sys::ModSecurity,
+            _: *const std::os::raw::c_char,
+        ) {
+        }
+
+        unsafe fn msc_cleanup(_: *mut modsecurity_sys::ModSecurity) {}
+
+        unsafe fn msc_create_rules_set() -> *mut crate::bindings::types::Rules_t {
+            std::ptr::null_mut()
+        }
+
+        unsafe fn msc_rules_cleanup(
+            _: *mut crate::bindings::types::Rules_t,
+        ) -> std::os::raw::c_int {
+            1
+        }
+
+        unsafe fn msc_new_transaction(
+            _msc: *mut modsecurity_sys::ModSecurity,
+            _rules: *mut modsecurity_sys::RulesSet,
+            _log_cb: *mut std::ffi::c_void,
+        ) -> *mut crate::bindings::types::Transaction_t {
+            std::ptr::null_mut()
+        }
+
+        unsafe fn msc_transaction_cleanup(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+        ) {
+        }
+
+        unsafe fn msc_get_response_body_length(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+        ) -> usize {
+            ResponseBodyBindings::BODY.len() - 1
+        }
+
+        unsafe fn msc_get_response_body(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+        ) -> *const std::os::raw::c_char {
+            ResponseBodyBindings::BODY.as_ptr() as *const std::os::raw::c_char
+        }
+
+        unsafe fn free(ptr: *mut std::ffi::c_void) {
+            assert_eq!(ptr as *const u8, ResponseBodyBindings::BODY.as_ptr());
+            RESPONSE_BODY_FREED.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_get_response_body_nul() {
+        let ms = ModSecurity::<ResponseBodyBindings>::default();
+        let rules = Rules::new();
+
+        let transaction = ms.transaction_builder().with_rules(&rules).build().unwrap();
+
+        // The body can only be read up to the NUL byte, so it is not returned, and the copy is
+        // freed on every call.
+        assert_eq!(transaction.get_response_body(), None);
+        assert_eq!(transaction.get_response_body(), None);
+        assert_eq!(
+            RESPONSE_BODY_FREED.load(std::sync::atomic::Ordering::SeqCst),
+            2
+        );
+    }
+
     #[test]
     fn test_request_headers() {
         let ms = ModSecurity::<TestBindings>::builder()
@@ -1069,6 +2111,27 @@ mod tests {
         assert_eq!(transaction.intervention().is_some(), true);
     }
 
+    #[test]
+    fn test_request_headers_binary() {
+        let ms = ModSecurity::<TestBindings>::builder()
+            .with_log_callbacks()
+            .build();
+        let rules = Rules::new();
+
+        let mut transaction = ms.transaction_builder().with_rules(&rules).build().unwrap();
+
+        // Neither of these should be rejected on the Rust side
+        assert!(transaction
+            .add_request_header("X-Nul", "before\0after")
+            .is_ok());
+        assert!(transaction
+            .add_request_header_bytes(b"X-Latin-1", b"caf\xe9")
+            .is_ok());
+        assert!(transaction
+            .add_response_header_bytes(b"X-Latin-1", b"caf\xe9")
+            .is_ok());
+    }
+
     #[test]
     fn test_response_headers() {
         let ms = ModSecurity::<TestBindings>::builder()
@@ -1104,6 +2167,88 @@ mod tests {
         assert_eq!(intervention.status(), 500);
     }
 
+    #[test]
+    fn test_update_status_code() {
+        let ms = ModSecurity::<TestBindings>::builder()
+            .with_log_callbacks()
+            .build();
+        let mut rules = Rules::new();
+
+        rules
+            .add_plain(
+                r#"
+                SecRuleEngine On
+
+                SecRule RESPONSE_STATUS "@streq 502" \
+                    "id:'1234567',\
+                    phase:4,\
+                    t:none,\
+                    status:403,\
+                    deny
+            "#,
+            )
+            .unwrap();
+
+        let mut transaction = ms.transaction_builder().with_rules(&rules).build().unwrap();
+
+        transaction
+            .process_response_headers(200, "HTTP 1.1")
+            .unwrap();
+        transaction.update_status_code(502).unwrap();
+        transaction.process_response_body().unwrap();
+
+        #[cfg(not(miri))]
+        assert!(transaction.intervention().is_some());
+    }
+
+    #[test]
+    fn test_with_hostname() {
+        let ms = ModSecurity::<TestBindings>::builder()
+            .with_log_callbacks()
+            .build();
+        let mut rules = Rules::new();
+
+        rules
+            .add_plain(
+                r#"
+                SecRuleEngine On
+
+                SecRule SERVER_NAME "@streq admin.example.com" "id:1,phase:1,t:none,deny,status:403"
+            "#,
+            )
+            .unwrap();
+
+        let mut transaction = ms
+            .transaction_builder()
+            .with_rules(&rules)
+            .with_hostname("admin.example.com")
+            .build()
+            .unwrap();
+
+        transaction.process_uri("/", "GET", "1.1").unwrap();
+        transaction.process_request_headers().unwrap();
+
+        #[cfg(not(miri))]
+        assert!(transaction.intervention().is_some());
+    }
+
+    #[test]
+    fn test_with_hostname_failure() {
+        let ms = ModSecurity::<FallibleBindings>::default();
+        let rules = Rules::new();
+
+        let transaction = ms
+            .transaction_builder()
+            .with_rules(&rules)
+            .with_hostname("example.com")
+            .build();
+
+        assert!(matches!(
+            transaction,
+            Err(ModSecurityError::SetRequestHostname)
+        ));
+    }
+
     #[test]
     pub fn test_intervention_fields() {
         let ms = ModSecurity::<TestBindings>::builder()
@@ -1145,6 +2290,50 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_owned_transaction() {
+        let ms = Arc::new(
+            ModSecurity::<TestBindings>::builder()
+                .with_log_callbacks()
+                .build(),
+        );
+        let mut rules = Rules::new();
+        rules
+            .add_plain(
+                r#"
+                SecRuleEngine On
+
+                SecRule REQUEST_URI "test" "phase:1,id:'1',t:none,deny"
+            "#,
+            )
+            .unwrap();
+        let rules = Arc::new(rules);
+
+        let mut transaction = ms
+            .shared_transaction_builder()
+            .with_shared_rules(Arc::clone(&rules))
+            .with_id(&String::from("owned-transaction"))
+            .build()
+            .unwrap();
+
+        // The transaction keeps both instances alive
+        drop(ms);
+        drop(rules);
+
+        transaction.process_uri("/test", "GET", "1.1").unwrap();
+        transaction.process_request_headers().unwrap();
+
+        #[cfg(not(miri))]
+        assert!(transaction.intervention().is_some());
+    }
+
+    #[test]
+    fn test_owned_transaction_is_static_and_send() {
+        fn assert_static_send<T: Send + 'static>() {}
+
+        assert_static_send::<super::OwnedTransaction>();
+    }
+
     // Simulate failures in the bindings to make sure our error types are
     // correctly propagated
     pub struct FallibleBindings;
@@ -1183,6 +2372,12 @@ mod tests {
             0
         }
 
+        unsafe fn msc_get_response_body(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+        ) -> *const std::os::raw::c_char {
+            std::ptr::null()
+        }
+
         #[cfg(miri)]
         unsafe fn msc_init() -> *mut modsecurity_sys::ModSecurity {
             std::ptr::null_mut()
@@ -1324,18 +2519,43 @@ mod tests {
             0
         }
 
-        unsafe fn msc_add_request_header(
+        unsafe fn msc_update_status_code(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+            _status: i32,
+        ) -> i32 {
+            0
+        }
+
+        unsafe fn msc_set_request_hostname(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+            _hostname: *const std::os::raw::c_uchar,
+        ) -> i32 {
+            0
+        }
+
+        unsafe fn msc_request_body_from_file(
+            _transaction: *mut crate::bindings::types::Transaction_t,
+            _path: *const std::os::raw::c_char,
+        ) -> i32 {
+            0
+        }
+
+        unsafe fn msc_add_n_request_header(
             _transaction: *mut crate::bindings::types::Transaction_t,
             _key: *const std::os::raw::c_uchar,
+            _len_key: usize,
             _value: *const std::os::raw::c_uchar,
+            _len_value: usize,
         ) -> i32 {
             0
         }
 
-        unsafe fn msc_add_response_header(
+        unsafe fn msc_add_n_response_header(
             _transaction: *mut crate::bindings::types::Transaction_t,
             _key: *const std::os::raw::c_uchar,
+            _len_key: usize,
             _value: *const std::os::raw::c_uchar,
+            _len_value: usize,
         ) -> i32 {
             0
         }
@@ -1372,7 +2592,12 @@ mod tests {
         process_response_body => ModSecurityError::ProcessResponseBody
         process_request_headers => ModSecurityError::ProcessRequestHeaders
         process_response_headers 0, "" => ModSecurityError::ProcessResponseHeaders
+        update_status_code 0 => ModSecurityError::UpdateStatusCode
+        set_request_hostname "" => ModSecurityError::SetRequestHostname
+        request_body_from_file "" => ModSecurityError::RequestBodyFromFile
         add_request_header "", "" => ModSecurityError::AddRequestHeader
         add_response_header "", "" => ModSecurityError::AddResponseHeader
+        add_request_header_bytes b"", b"" => ModSecurityError::AddRequestHeader
+        add_response_header_bytes b"", b"" => ModSecurityError::AddResponseHeader
     }
 }